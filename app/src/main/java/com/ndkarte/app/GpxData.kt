@@ -2,11 +2,13 @@ package com.ndkarte.app
 
 import android.util.Log
 import org.json.JSONObject
+import java.io.File
 
 /**
  * Kotlin representation of parsed GPX data.
  *
- * Deserialized from the JSON string returned by RustBridge.parseGpx().
+ * Deserialized from the JSON string returned by RustBridge.parseGpx()
+ * or RustBridge.importFile().
  */
 data class GpxPoint(
     val lat: Double,
@@ -39,11 +41,21 @@ data class GpxData(
         private const val TAG = "NDKarte.Gpx"
 
         /**
-         * Parse a GPX file from raw bytes via the Rust core.
-         * Returns null on parse failure.
+         * Parse a track file from raw bytes via the Rust core.
+         * The format (GPX, KML, GeoJSON, FIT, ...) is detected from the
+         * content. Returns null on parse failure.
          */
         fun parse(data: ByteArray): GpxData? {
-            val json = RustBridge.parseGpx(data)
+            val json = RustBridge.importFile(data)
+            return fromJson(json)
+        }
+
+        /**
+         * Load a track file via the Rust binary cache in [cacheDir].
+         * Returns null on parse failure.
+         */
+        fun load(file: File, cacheDir: File): GpxData? {
+            val json = RustBridge.loadFileCached(file.path, cacheDir.path)
             return fromJson(json)
         }
 
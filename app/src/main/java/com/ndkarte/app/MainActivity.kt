@@ -81,12 +81,13 @@ class MainActivity : Activity() {
             }
 
             val gpxFile = gpxDir.listFiles()
-                ?.filter { it.extension == "gpx" }
+                ?.filter { it.extension.lowercase() in IMPORT_EXTENSIONS }
                 ?.firstOrNull()
                 ?: return@execute
 
             Log.i(TAG, "Loading GPX: ${gpxFile.name}")
-            val data = GpxData.parse(gpxFile.readBytes()) ?: return@execute
+            val trackCacheDir = File(cacheDir, TRACK_CACHE_DIR)
+            val data = GpxData.load(gpxFile, trackCacheDir) ?: return@execute
 
             runOnUiThread {
                 mapManager.showGpxData(data)
@@ -240,6 +241,10 @@ class MainActivity : Activity() {
     companion object {
         private const val TAG = "NDKarte"
         private const val GPX_DIR = "gpx"
+        private const val TRACK_CACHE_DIR = "tracks"
+
+        /** File extensions picked up from [GPX_DIR]; the format itself is sniffed by Rust. */
+        private val IMPORT_EXTENSIONS = setOf("gpx", "gz", "kml", "kmz", "geojson", "json", "fit", "tcx")
         private const val LOCATION_PERMISSION_REQUEST = 1001
         private const val SIGN_IN_REQUEST = 1002
     }
@@ -42,8 +42,11 @@ class NavigationManager(
     private val computeExecutor = Executors.newSingleThreadExecutor()
 
     // Cached values computed once at startNavigation
-    private var cachedTrackJson: String? = null
+    private var cachedTrack: ByteArray? = null
+    // JSON track for plain projection when encoding fails
+    private var fallbackTrackJson: String? = null
     private var cachedTrackLength: Double = 0.0
+    private var cachedTrackShape: String = SHAPE_ONE_WAY
 
     @Volatile
     private var lastProjection: ProjectionData? = null
@@ -54,7 +57,8 @@ class NavigationManager(
         val point: GpxPoint,
         val segmentIndex: Int,
         val distanceM: Double,
-        val distanceAlongM: Double
+        val distanceAlongM: Double,
+        val arrivalAllowed: Boolean
     )
 
     /** Bind to a MapLibre map instance. Call after map style is loaded. */
@@ -66,8 +70,9 @@ class NavigationManager(
     /**
      * Start navigation on a track.
      *
-     * Pre-computes cached track JSON and length on a background thread,
-     * then begins GPS updates.
+     * Pre-computes the encoded track, its length and its shape on a
+     * background thread, then begins GPS updates. The shape keeps
+     * loops and out-and-backs from matching the start onto the finish.
      */
     fun startNavigation(track: GpxTrack) {
         if (track.points.size < 2) {
@@ -84,8 +89,14 @@ class NavigationManager(
 
         // Pre-compute cached values on background thread
         computeExecutor.execute {
-            cachedTrackJson = pointsToJson(track.points)
+            val pointsJson = pointsToJson(track.points)
+            cachedTrack = RustBridge.encodeTrack(pointsJson)
+            if (cachedTrack == null) {
+                Log.e(TAG, "Track encoding failed, falling back to JSON projection")
+                fallbackTrackJson = pointsJson
+            }
             cachedTrackLength = estimateTrackLength(track.points)
+            cachedTrackShape = parseShape(RustBridge.detectTrackShape(pointsJson))
 
             // Start GPS after caches are ready
             runOnUiThread {
@@ -111,8 +122,10 @@ class NavigationManager(
 
         activeTrackPoints = null
         lastProjection = null
-        cachedTrackJson = null
+        cachedTrack = null
+        fallbackTrackJson = null
         cachedTrackLength = 0.0
+        cachedTrackShape = SHAPE_ONE_WAY
 
         Log.i(TAG, "Navigation stopped")
     }
@@ -150,14 +163,24 @@ class NavigationManager(
     private fun onLocationUpdate(location: Location) {
         if (!navigating.get()) return
 
-        val trackJson = cachedTrackJson ?: return
+        val track = cachedTrack
+        val fallbackJson = fallbackTrackJson
+        if (track == null && fallbackJson == null) return
         val lat = location.latitude
         val lon = location.longitude
 
         computeExecutor.execute {
             if (!navigating.get()) return@execute
 
-            val resultJson = RustBridge.projectOnTrack(lat, lon, trackJson)
+            val lastAlongM = lastProjection?.distanceAlongM ?: -1.0
+            // The JSON path ignores track shape and never gates arrival
+            val resultJson = if (track != null) {
+                RustBridge.projectOnTrackForNavigation(
+                    lat, lon, track, cachedTrackShape, lastAlongM
+                )
+            } else {
+                RustBridge.projectOnTrack(lat, lon, fallbackJson ?: return@execute)
+            }
             val projection = parseProjection(resultJson) ?: return@execute
 
             runOnUiThread {
@@ -289,11 +312,14 @@ class NavigationManager(
     /**
      * Announce progress milestones along the track.
      * Uses cached track length instead of recalculating per update.
+     * On loops the finish is next to the start, so announcements wait
+     * until Rust reports that most of the track is covered.
      */
     private fun checkProgressAnnouncement(projection: ProjectionData, prev: ProjectionData?) {
         if (!ttsReady) return
         if (prev == null) return
         if (cachedTrackLength < 1000.0) return
+        if (!projection.arrivalAllowed) return
 
         val remaining = cachedTrackLength - projection.distanceAlongM
         val prevRemaining = cachedTrackLength - prev.distanceAlongM
@@ -357,7 +383,8 @@ class NavigationManager(
                 ),
                 segmentIndex = obj.getInt("segment_index"),
                 distanceM = obj.getDouble("distance_m"),
-                distanceAlongM = obj.getDouble("distance_along_m")
+                distanceAlongM = obj.getDouble("distance_along_m"),
+                arrivalAllowed = obj.optBoolean("arrival_allowed", true)
             )
         } catch (e: Exception) {
             Log.e(TAG, "Failed to parse projection JSON", e)
@@ -365,6 +392,21 @@ class NavigationManager(
         }
     }
 
+    private fun parseShape(json: String): String {
+        return try {
+            val obj = JSONObject(json)
+            if (obj.has("error")) {
+                Log.e(TAG, "Shape detection error: ${obj.getString("error")}")
+                SHAPE_ONE_WAY
+            } else {
+                obj.getString("shape").also { Log.i(TAG, "Track shape: $it") }
+            }
+        } catch (e: Exception) {
+            Log.e(TAG, "Failed to parse shape JSON", e)
+            SHAPE_ONE_WAY
+        }
+    }
+
     private fun runOnUiThread(action: () -> Unit) {
         (context as? Activity)?.runOnUiThread(action)
     }
@@ -391,5 +433,8 @@ class NavigationManager(
         /** Distance from end of track to announce approach (meters). */
         private const val APPROACH_ANNOUNCE_M = 500.0
         private const val ARRIVAL_M = 50.0
+
+        /** Track shape used until detection finishes or when it fails. */
+        private const val SHAPE_ONE_WAY = "one_way"
     }
 }
@@ -25,6 +25,282 @@ object RustBridge {
      */
     external fun parseGpx(data: ByteArray): String
 
+    /**
+     * Parse a track file of any supported format, detected from its
+     * content rather than its file name: GPX 1.0/1.1, KML, KMZ, GeoJSON,
+     * FIT, TCX, and gzip-compressed variants (e.g. ".gpx.gz").
+     *
+     * Returns the same JSON as [parseGpx] plus "format" (one of "gpx10",
+     * "gpx11", "kml", "kmz", "geojson", "fit", "tcx") and "compressed".
+     */
+    external fun importFile(data: ByteArray): String
+
+    /**
+     * Load a track file through a binary cache in [cacheDir].
+     *
+     * The first load imports the file like [importFile] and stores a
+     * compact binary copy keyed by the file's content hash; later loads
+     * of the same content skip parsing entirely. Entries from an older
+     * importer and entries unused for 30 days are removed on the next store.
+     * Returns the same JSON as [parseGpx].
+     */
+    external fun loadFileCached(path: String, cacheDir: String): String
+
+    /**
+     * Parse a GPX file leniently, recovering from common defects
+     * (BOMs, unescaped ampersands, missing namespaces, GPX 1.0 headers,
+     * invalid coordinates, truncated endings).
+     *
+     * Returns the same JSON as [parseGpx] plus a "warnings" array of
+     * { "kind": str, "line"?: N, "message": str } objects.
+     *
+     * On failure returns: { "error": "description" }
+     */
+    external fun parseGpxLenient(data: ByteArray): String
+
+    /**
+     * Parse a GPX file directly from disk with the streaming reader.
+     *
+     * Avoids copying the file into a ByteArray and building a full
+     * document tree, so very large recordings stay within memory.
+     * Returns the same JSON as [parseGpx].
+     */
+    external fun parseGpxFile(path: String): String
+
+    /**
+     * Summarize a GPX file without keeping its points in memory.
+     *
+     * Returns JSON: { "tracks": N, "routes": N, "waypoints": N,
+     *   "track_points": N, "route_points": N, "names": [str],
+     *   "bounds"?: { "min_lat", "min_lon", "max_lat", "max_lon" },
+     *   "track_length_m": N, "start_time"?: N, "end_time"?: N }
+     */
+    external fun summarizeGpxFile(path: String): String
+
+    /**
+     * Export GPX data as a GPX document, in the version it was read from.
+     *
+     * [gpxJson]: { "tracks": [...], "routes": [...], "waypoints": [...],
+     *   "version"?: "1.0" | "1.1" } as returned by [parseGpx]. Data without
+     *   a version is written as GPX 1.1.
+     * Returns the GPX text, or { "error": "description" } on failure.
+     */
+    external fun exportGpx(gpxJson: String): String
+
+    /**
+     * Parse a Google Earth KML or KMZ file from raw bytes.
+     *
+     * Placemark points become waypoints; LineString and gx:Track
+     * geometries become tracks. Returns the same JSON as [parseGpx].
+     */
+    external fun parseKml(data: ByteArray): String
+
+    /**
+     * Export GPX data as a KML document.
+     *
+     * [gpxJson]: { "tracks": [...], "routes": [...], "waypoints": [...] }
+     * Returns the KML text, or { "error": "description" } on failure.
+     */
+    external fun exportKml(gpxJson: String): String
+
+    /**
+     * Parse a GeoJSON file from raw bytes.
+     *
+     * Points become waypoints; LineStrings become tracks, or routes when
+     * the feature has "kind": "route". Returns the same JSON as [parseGpx].
+     */
+    external fun parseGeoJson(data: ByteArray): String
+
+    /**
+     * Convert GPX data to a GeoJSON FeatureCollection for MapLibre.
+     *
+     * [gpxJson]: { "tracks": [...], "routes": [...], "waypoints": [...] }
+     * Returns a FeatureCollection whose features carry "kind", "name",
+     * "color", "icon" and "coordTimes" properties.
+     */
+    external fun toGeoJson(gpxJson: String): String
+
+    /**
+     * Parse a FIT or TCX activity file, chosen by file signature.
+     *
+     * Activities and courses become tracks; course points and FIT laps
+     * become waypoints. Returns the same JSON as [parseGpx].
+     */
+    external fun parseActivity(data: ByteArray): String
+
+    /**
+     * Validate parsed GPX data: coordinate ranges, zero-length segments,
+     * implausible speed jumps, and elevation spikes.
+     *
+     * [gpxJson]: { "tracks": [...], "routes": [...], "waypoints": [...] }
+     * [fix]: whether to repair the data.
+     * Returns: { "issues": [{ "kind", "collection", "item_index",
+     *   "point_index", "message" }], "data"?: {...} }
+     */
+    external fun validateGpx(gpxJson: String, fix: Boolean): String
+
+    /**
+     * Merge several parsed GPX files into one, dropping duplicates.
+     *
+     * [filesJson]: JSON array of { "tracks", "routes", "waypoints" } objects.
+     * [trackToleranceM]: largest Hausdorff distance between duplicate
+     *   tracks or routes; the one with more points is kept.
+     * [waypointRadiusM]: largest distance between duplicate waypoints
+     *   whose names match or are missing.
+     * Returns: { "data": {...}, "duplicates": [{ "collection", "file_index",
+     *   "item_index", "kept_index", "distance_m" }] }
+     */
+    external fun mergeGpx(
+        filesJson: String,
+        trackToleranceM: Double,
+        waypointRadiusM: Double
+    ): String
+
+    /**
+     * Compare a ride with a plan or another saved track.
+     *
+     * Points of [pointsJson] within [toleranceM] of [otherPointsJson] are
+     * shared, the rest divergent.
+     * Returns: { "hausdorff_m", "frechet_m", "shared_m", "divergent_m",
+     *   "shared_fraction", "covered_fraction", "stretches": [{ "shared",
+     *   "start_index", "end_index", "start_m", "end_m", "other_start_m"?,
+     *   "other_end_m"?, "max_distance_m" }] }
+     */
+    external fun compareTracks(
+        pointsJson: String,
+        otherPointsJson: String,
+        toleranceM: Double
+    ): String
+
+    /**
+     * Find where a recorded ride left its planned route.
+     *
+     * [planJson]: route or track { "name"?, "points" }; [rideJson]: track.
+     * [toleranceM]: distance from the plan beyond which the ride is off it.
+     * [minLengthM]: shorter deviations are treated as GPS noise.
+     * Returns: { "deviations": [{ "start_index", "end_index", "start_m",
+     *   "end_m", "left_plan_m"?, "rejoined_plan_m"?, "max_distance_m",
+     *   "start_time"?, "end_time"?, "duration_s"? }], "deviated_m",
+     *   "deviated_s", "plan_covered_fraction" }
+     */
+    external fun deviationReport(
+        planJson: String,
+        rideJson: String,
+        toleranceM: Double,
+        minLengthM: Double
+    ): String
+
+    /**
+     * Export the deviations found by [deviationReport] as GPX 1.1 text:
+     * the plan as a route and each deviation as a track "Deviation N".
+     */
+    external fun exportDeviationsGpx(
+        planJson: String,
+        rideJson: String,
+        toleranceM: Double,
+        minLengthM: Double
+    ): String
+
+    /**
+     * Set elevations from the SRTM tiles downloaded by MapDownloadManager.
+     *
+     * [gpxJson]: { "tracks": [...], "routes": [...], "waypoints": [...] }
+     * [demDir]: the DEM directory (files/dem) holding <band>/<tile>.hgt.
+     * [replace]: overwrite existing elevations instead of filling gaps.
+     * Returns the same JSON as [parseGpx] plus "updated_points".
+     */
+    external fun applyDemElevation(gpxJson: String, demDir: String, replace: Boolean): String
+
+    /**
+     * Clean noisy track elevations before computing ascent or profiles.
+     *
+     * [gpxJson]: { "tracks": [...], "routes": [...], "waypoints": [...] }
+     * [filtersJson]: filter chain applied in order, e.g.
+     *   [{ "method": "median", "window": 5 },
+     *    { "method": "kalman", "process_noise": 0.05, "measurement_noise_m": 5 },
+     *    { "method": "hysteresis", "threshold_m": 3 }, { "method": "dem_offset" }],
+     *   or "" for the recommended chain.
+     * [demDir]: DEM directory, required by "dem_offset".
+     * Returns the same JSON as [parseGpx] plus "ascent_before_m" and
+     * "ascent_after_m".
+     */
+    external fun filterElevation(gpxJson: String, filtersJson: String, demDir: String?): String
+
+    /**
+     * Build an elevation profile for a chart.
+     *
+     * [pointsJson] is a JSON array of {lat, lon, ele?} objects.
+     * [sampleCount] > 0 gives that many evenly spaced samples; otherwise
+     * one sample every [spacingM] meters. [smoothingM] is the width of
+     * the moving-average window (0 disables smoothing). [demDir] selects
+     * DEM elevations; null uses the points' own elevations.
+     * Returns JSON: { "samples": [{ "distance_m", "ele_m", "grade_pct",
+     *   "ascent_m" }], "total_distance_m", "total_ascent_m",
+     *   "total_descent_m", "min_ele_m", "max_ele_m" }
+     */
+    external fun elevationProfile(
+        pointsJson: String,
+        sampleCount: Int,
+        spacingM: Double,
+        smoothingM: Double,
+        demDir: String?
+    ): String
+
+    /**
+     * Index of the profile sample nearest to [distanceAlongM], e.g. the
+     * "distance_along_m" of a projection, for marking the rider position.
+     * Returns -1 if [profileJson] is invalid.
+     */
+    external fun profileIndexAt(profileJson: String, distanceAlongM: Double): Int
+
+    /**
+     * Detect significant climbs and descents, named after nearby waypoints.
+     *
+     * [pointsJson] is a JSON array of {lat, lon, ele?} objects and
+     * [waypointsJson] a JSON array of waypoints as in [parseGpx].
+     * Returns a JSON array of { "kind": "climb"|"descent", "start_m",
+     *   "end_m", "start_ele_m", "end_ele_m", "gain_m", "avg_grade_pct",
+     *   "max_grade_pct", "top", "name"? }
+     */
+    external fun detectClimbs(
+        pointsJson: String,
+        waypointsJson: String,
+        minGainM: Double,
+        minGradePct: Double
+    ): String
+
+    /**
+     * The next climb relative to the rider's "distance_along_m".
+     *
+     * [climbsJson] is the result of [detectClimbs].
+     * Returns the climb plus "distance_to_start_m", "in_progress" and
+     * "remaining_m", or "null" when no climb is left.
+     */
+    external fun nextClimb(climbsJson: String, distanceAlongM: Double): String
+
+    /**
+     * Render hillshade and contour MBTiles from the downloaded DEM tiles.
+     *
+     * Writes [outDir]/hillshade.mbtiles (PNG rasters) and
+     * [outDir]/contours.mbtiles (vector tiles, layer "contour" with "ele"
+     * and "index" fields) for the bounding box. This is slow for large
+     * regions, so call it from a background thread. Fails for contour
+     * intervals below 1 m and for areas needing more than 100 000 tiles.
+     * Returns: { "hillshade_path", "contour_path", "hillshade_tiles",
+     *   "contour_tiles" }
+     */
+    external fun buildTerrainTiles(
+        demDir: String,
+        outDir: String,
+        minLat: Double,
+        minLon: Double,
+        maxLat: Double,
+        maxLon: Double,
+        minZoom: Int,
+        maxZoom: Int,
+        contourIntervalM: Double
+    ): String
+
     /**
      * Project a position onto a track and return the nearest point.
      *
@@ -35,13 +311,128 @@ object RustBridge {
     external fun projectOnTrack(lat: Double, lon: Double, trackJson: String): String
 
     /**
-     * Simplify a track to a route using Ramer-Douglas-Peucker.
+     * Encode a track once for repeated [projectOnTrackEncoded] calls.
+     *
+     * [trackJson] is a JSON array of {lat, lon, ele?} objects.
+     * Returns the compact binary track, or null on invalid input.
+     */
+    external fun encodeTrack(trackJson: String): ByteArray?
+
+    /**
+     * Project a position onto a track produced by [encodeTrack].
+     *
+     * Returns the same JSON as [projectOnTrack].
+     */
+    external fun projectOnTrackEncoded(lat: Double, lon: Double, track: ByteArray): String
+
+    /**
+     * Classify a track as one-way, loop, or out-and-back.
+     *
+     * [trackJson] is a JSON array of {lat, lon, ele?} objects.
+     * Returns JSON: { "shape": "one_way"|"loop"|"out_and_back",
+     *   "length_m": N, "closure_m": N }
+     */
+    external fun detectTrackShape(trackJson: String): String
+
+    /**
+     * Project a position onto a track produced by [encodeTrack], keeping
+     * loops and out-and-backs matched in riding order.
+     *
+     * [shape]: as returned by [detectTrackShape].
+     * [lastAlongM]: distance_along_m of the previous fix, or a negative
+     *   value for the first fix of a session.
+     * Returns the same JSON as [projectOnTrack] plus "arrival_allowed",
+     * false until most of a closed track is covered.
+     */
+    external fun projectOnTrackForNavigation(
+        lat: Double,
+        lon: Double,
+        track: ByteArray,
+        shape: String,
+        lastAlongM: Double
+    ): String
+
+    /**
+     * Simplify a track to a route.
      *
      * [trackJson]: { "name"?: str, "points": [{lat, lon, ele?}] }
-     * [toleranceM]: simplification tolerance in meters.
-     * Returns: { "name"?: str, "points": [{lat, lon, ele?}] }
+     * [strategy]: "rdp" with [value] as tolerance in meters,
+     *   "visvalingam" with [value] as minimum triangle area in m², or
+     *   "max_points" with [value] as the point budget (e.g. 250 for
+     *   Garmin devices).
+     * Returns: { "name"?: str, "points": [{lat, lon, ele?}],
+     *   "track_indices": [int] } where each index is the track point a
+     *   route point came from.
+     */
+    external fun trackToRoute(trackJson: String, strategy: String, value: Double): String
+
+    /**
+     * Simplify a track to a route for turn-by-turn navigation.
+     *
+     * Keeps the apex of every bend sharper than [turnThresholdDeg], the
+     * track point nearest to each waypoint in [waypointsJson] and both
+     * sides of each index in [segmentStartsJson] (a JSON int array, "[]"
+     * for none); everything else is simplified with RDP at [toleranceM].
+     * Returns the same JSON as [trackToRoute].
+     */
+    external fun trackToRouteForNavigation(
+        trackJson: String,
+        waypointsJson: String,
+        segmentStartsJson: String,
+        toleranceM: Double,
+        turnThresholdDeg: Double
+    ): String
+
+    /**
+     * Resample a point list at even steps or densify its long legs.
+     *
+     * [mode]: "distance" with [value] as spacing in meters, "time" with
+     *   [value] as interval in seconds (needs timestamps), or "densify"
+     *   with [value] as maximum segment length in meters (points added
+     *   along great circles, originals kept).
+     * Returns: JSON array of {lat, lon, ele?, time?}
+     */
+    external fun resampleTrack(pointsJson: String, mode: String, value: Double): String
+
+    /**
+     * Reverse a track's direction; courses are turned around.
+     * Returns: track JSON
+     */
+    external fun reverseTrack(trackJson: String): String
+
+    /**
+     * Split a track in two.
+     *
+     * [atJson]: {"index": n}, {"distance_m": d} or {"lat": .., "lon": ..}
+     *   for the track position nearest to a location.
+     * Returns: JSON array of the two parts, both containing the cut point
      */
-    external fun trackToRoute(trackJson: String, toleranceM: Double): String
+    external fun splitTrack(trackJson: String, atJson: String): String
+
+    /**
+     * Join a JSON array of tracks end to end.
+     * Jumps between tracks longer than [maxGapM] are reported.
+     * Returns: { name, points, gaps: [{ index, distance_m }] }
+     */
+    external fun joinTracks(tracksJson: String, maxGapM: Double): String
+
+    /**
+     * Cut [startM] meters off the start and [endM] meters off the end.
+     * Returns: track JSON
+     */
+    external fun trimTrack(trackJson: String, startM: Double, endM: Double): String
+
+    /**
+     * Keep only the parts of a track inside the bounding box.
+     * Returns: JSON array of tracks, one per stretch inside the box
+     */
+    external fun cropTrack(
+        trackJson: String,
+        minLat: Double,
+        minLon: Double,
+        maxLat: Double,
+        maxLon: Double
+    ): String
 
     /**
      * Convert a route to a track (direct point copy).
@@ -51,6 +442,24 @@ object RustBridge {
      */
     external fun routeToTrack(routeJson: String): String
 
+    /**
+     * Encode points as an encoded polyline for sharing (QR code, chat)
+     * or routing APIs.
+     *
+     * [pointsJson] is a JSON array of {lat, lon, ele?} objects.
+     * [precision] is 5 (Google, OSRM default) or 6 (polyline6, Valhalla).
+     * Returns JSON: { "polyline": "..." }
+     */
+    external fun encodePolyline(pointsJson: String, precision: Int): String
+
+    /**
+     * Decode an encoded polyline, e.g. pasted from a route planner.
+     *
+     * [precision] is 5 or 6 and must match the encoder.
+     * Returns a JSON array of {lat, lon} objects.
+     */
+    external fun decodePolyline(polyline: String, precision: Int): String
+
     /**
      * Generate turn-by-turn instructions for a route.
      *
@@ -0,0 +1,47 @@
+//! Simplification benchmarks on a large synthetic track.
+//!
+//! Run with `cargo bench --bench simplify`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ndkarte::convert::{simplify_indices, Simplify};
+use ndkarte::gpx::Point;
+
+/// A 200k-point track (roughly a multi-day tour at 1 s recording)
+/// wandering along a meandering line with GPS-like jitter.
+fn synthetic_track(n: usize) -> Vec<Point> {
+    (0..n)
+        .map(|i| {
+            let t = i as f64;
+            let jitter = ((t * 12.9898).sin() * 43_758.545).fract() * 0.00002;
+            Point {
+                lat: 47.0 + t * 0.000_01 + (t * 0.001).sin() * 0.01 + jitter,
+                lon: 11.0 + t * 0.000_02 + (t * 0.0007).cos() * 0.01 - jitter,
+                ele: None,
+                time: None,
+                speed: None,
+                course: None,
+            }
+        })
+        .collect()
+}
+
+fn bench_simplify(c: &mut Criterion) {
+    let track = synthetic_track(200_000);
+    let mut group = c.benchmark_group("simplify_200k");
+    group.sample_size(10);
+    group.bench_function("rdp_10m", |b| {
+        b.iter(|| simplify_indices(black_box(&track), Simplify::Rdp { tolerance_m: 10.0 }))
+    });
+    group.bench_function("visvalingam_100m2", |b| {
+        b.iter(|| {
+            simplify_indices(black_box(&track), Simplify::Visvalingam { min_area_m2: 100.0 })
+        })
+    });
+    group.bench_function("max_points_250", |b| {
+        b.iter(|| simplify_indices(black_box(&track), Simplify::MaxPoints(250)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_simplify);
+criterion_main!(benches);
@@ -6,7 +6,7 @@
 
 use jni::JNIEnv;
 use jni::objects::{JByteArray, JClass, JString};
-use jni::sys::{jdouble, jstring};
+use jni::sys::{jboolean, jbyteArray, jdouble, jint, jstring};
 
 use crate::gpx::Point;
 
@@ -22,35 +22,686 @@ fn json_result(env: &mut JNIEnv, result: Result<String, String>) -> jstring {
         .into_raw()
 }
 
+/// Return bytes as a Java byte array, or null on failure.
+fn bytes_result(env: &mut JNIEnv, result: Result<Vec<u8>, String>) -> jbyteArray {
+    result
+        .ok()
+        .and_then(|bytes| env.byte_array_from_slice(&bytes).ok())
+        .map_or(std::ptr::null_mut(), |array| array.into_raw())
+}
+
 // -- Version --
 
-/// Returns the rust-core library version.
-/// Maps to: RustBridge.version() -> String
+/// Returns the rust-core library version.
+/// Maps to: RustBridge.version() -> String
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_version(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    env.new_string(crate::VERSION)
+        .expect("failed to create Java string")
+        .into_raw()
+}
+
+// -- GPX Parsing --
+
+/// Parse a GPX file from raw bytes and return JSON.
+/// Maps to: RustBridge.parseGpx(data: ByteArray) -> String
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseGpx(
+    mut env: JNIEnv,
+    _class: JClass,
+    data: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&data)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        crate::gpx::parse_to_json(&bytes)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Detect the format of a file from its content and parse it.
+/// Maps to: RustBridge.importFile(data: ByteArray) -> String
+///
+/// Accepts GPX 1.0/1.1, KML, KMZ, GeoJSON, FIT, TCX, and gzip-compressed
+/// variants of these. Returns the same JSON as parseGpx plus
+/// "format" (gpx10, gpx11, kml, kmz, geojson, fit, tcx) and "compressed".
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_importFile(
+    mut env: JNIEnv,
+    _class: JClass,
+    data: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&data)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        crate::import::import_to_json(&bytes)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Load a track file through the binary cache and return GPX JSON.
+/// Maps to: RustBridge.loadFileCached(path: String, cacheDir: String) -> String
+///
+/// On a cache miss the file is imported like importFile and the result
+/// cached in cacheDir, keyed by a hash of the file content.
+/// Returns the same JSON as parseGpx.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_loadFileCached(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+    cache_dir: JString,
+) -> jstring {
+    let result = (|| {
+        let path: String = env
+            .get_string(&path)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let cache_dir: String = env
+            .get_string(&cache_dir)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::binary::load_file_cached_to_json(&path, &cache_dir)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Parse a GPX file leniently, repairing common defects.
+/// Maps to: RustBridge.parseGpxLenient(data: ByteArray) -> String
+///
+/// Returns the same JSON as parseGpx plus a "warnings" array of
+/// { kind, line?, message } objects.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseGpxLenient(
+    mut env: JNIEnv,
+    _class: JClass,
+    data: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&data)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        crate::gpx::parse_lenient_to_json(&bytes)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Parse a GPX file from disk with the streaming reader and return JSON.
+///
+/// Maps to: RustBridge.parseGpxFile(path: String) -> String
+///
+/// Reads the file directly instead of copying it into a Java byte array,
+/// keeping peak memory low for very large recordings.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseGpxFile(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+) -> jstring {
+    let result = (|| {
+        let path: String = env
+            .get_string(&path)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::stream::parse_file_to_json(&path)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Summarize a GPX file from disk without keeping its points.
+///
+/// Maps to: RustBridge.summarizeGpxFile(path: String) -> String
+///
+/// Returns: { tracks, routes, waypoints, track_points, route_points, names,
+///   bounds?: { min_lat, min_lon, max_lat, max_lon }, track_length_m,
+///   start_time?, end_time? }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_summarizeGpxFile(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+) -> jstring {
+    let result = (|| {
+        let path: String = env
+            .get_string(&path)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::stream::summarize_file_to_json(&path)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Convert GPX data to a GPX document.
+/// Maps to: RustBridge.exportGpx(gpxJson: String) -> String
+///
+/// gpxJson: { tracks: [], routes: [], waypoints: [], version? } as
+///   returned by parseGpx. Written as GPX 1.0 when version is "1.0",
+///   otherwise as GPX 1.1.
+/// Returns the GPX XML text, or { "error": "..." } on bad input.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_exportGpx(
+    mut env: JNIEnv,
+    _class: JClass,
+    gpx_json: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&gpx_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::gpx::json_to_gpx(&json_str)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Parse a KML or KMZ file from raw bytes and return GPX JSON.
+///
+/// Maps to: RustBridge.parseKml(data: ByteArray) -> String
+///
+/// KMZ archives are recognized by their ZIP signature.
+/// Returns the same JSON as parseGpx.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseKml(
+    mut env: JNIEnv,
+    _class: JClass,
+    data: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&data)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        crate::kml::parse_to_json(&bytes)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Export GPX data as a KML document.
+///
+/// Maps to: RustBridge.exportKml(gpxJson: String) -> String
+///
+/// gpxJson: { tracks: [], routes: [], waypoints: [] }
+/// Returns the KML text, or JSON { error } on failure.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_exportKml(
+    mut env: JNIEnv,
+    _class: JClass,
+    gpx_json: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&gpx_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::kml::json_to_kml(&json_str)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Parse a GeoJSON file from raw bytes and return GPX JSON.
+///
+/// Maps to: RustBridge.parseGeoJson(data: ByteArray) -> String
+///
+/// Returns the same JSON as parseGpx.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseGeoJson(
+    mut env: JNIEnv,
+    _class: JClass,
+    data: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&data)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        crate::geojson::parse_to_json(&bytes)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Convert GPX data to a styled GeoJSON FeatureCollection.
+///
+/// Maps to: RustBridge.toGeoJson(gpxJson: String) -> String
+///
+/// gpxJson: { tracks: [], routes: [], waypoints: [] }
+/// Returns: { type: "FeatureCollection", features: [...] } where each
+///   feature has properties kind, name?, color, icon?, coordTimes?.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_toGeoJson(
+    mut env: JNIEnv,
+    _class: JClass,
+    gpx_json: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&gpx_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::geojson::json_to_geojson(&json_str)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Parse a FIT or TCX activity file from raw bytes and return GPX JSON.
+///
+/// Maps to: RustBridge.parseActivity(data: ByteArray) -> String
+///
+/// The format is chosen by file signature (".FIT" header, otherwise TCX).
+/// Returns the same JSON as parseGpx.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseActivity(
+    mut env: JNIEnv,
+    _class: JClass,
+    data: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&data)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        if crate::fit::is_fit(&bytes) {
+            crate::fit::parse_to_json(&bytes)
+        } else {
+            crate::tcx::parse_to_json(&bytes)
+        }
+    })();
+    json_result(&mut env, result)
+}
+
+/// Validate parsed GPX data and optionally repair it.
+///
+/// Maps to: RustBridge.validateGpx(gpxJson, fix) -> String
+///
+/// gpxJson: { tracks: [], routes: [], waypoints: [] } as returned by parseGpx.
+/// Returns: { issues: [{ kind, collection, item_index, point_index, message }],
+///   data?: {...} } where data holds the repaired GPX data when fix is true.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_validateGpx(
+    mut env: JNIEnv,
+    _class: JClass,
+    gpx_json: JString,
+    fix: jboolean,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&gpx_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::validate::validate_json(&json_str, fix != 0)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Merge several parsed GPX files, dropping duplicates.
+///
+/// Maps to: RustBridge.mergeGpx(filesJson, trackToleranceM, waypointRadiusM) -> String
+///
+/// filesJson: JSON array of { tracks: [], routes: [], waypoints: [] }.
+/// Tracks and routes within trackToleranceM (Hausdorff distance) of a
+/// kept one, and waypoints within waypointRadiusM of a kept one with a
+/// matching or missing name, are dropped.
+/// Returns: { data: {...}, duplicates: [{ collection, file_index,
+///   item_index, kept_index, distance_m }] }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_mergeGpx(
+    mut env: JNIEnv,
+    _class: JClass,
+    files_json: JString,
+    track_tolerance_m: jdouble,
+    waypoint_radius_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&files_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::merge::merge_json(&json_str, track_tolerance_m, waypoint_radius_m)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Compare two point lists: Hausdorff and Fréchet distance, and the
+/// stretches of the first that run along or away from the second.
+///
+/// Maps to: RustBridge.compareTracks(pointsJson, otherPointsJson, toleranceM) -> String
+///
+/// Returns: { hausdorff_m, frechet_m, shared_m, divergent_m,
+///   shared_fraction, covered_fraction, stretches: [{ shared,
+///   start_index, end_index, start_m, end_m, other_start_m?,
+///   other_end_m?, max_distance_m }] }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_compareTracks(
+    mut env: JNIEnv,
+    _class: JClass,
+    points_json: JString,
+    other_points_json: JString,
+    tolerance_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let points: String = env
+            .get_string(&points_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let other: String = env
+            .get_string(&other_points_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::similarity::compare_json(&points, &other, tolerance_m)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Find where a recorded ride left its planned route.
+///
+/// Maps to: RustBridge.deviationReport(planJson, rideJson, toleranceM, minLengthM) -> String
+///
+/// planJson: route or track { name?, points }; rideJson: track.
+/// Ride points farther than toleranceM from the plan are off it;
+/// deviations shorter than minLengthM are ignored.
+/// Returns: { deviations: [{ start_index, end_index, start_m, end_m,
+///   left_plan_m?, rejoined_plan_m?, max_distance_m, start_time?,
+///   end_time?, duration_s? }], deviated_m, deviated_s, plan_covered_fraction }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_deviationReport(
+    mut env: JNIEnv,
+    _class: JClass,
+    plan_json: JString,
+    ride_json: JString,
+    tolerance_m: jdouble,
+    min_length_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let plan: String = env
+            .get_string(&plan_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let ride: String = env
+            .get_string(&ride_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::deviation::deviations_json(&plan, &ride, tolerance_m, min_length_m)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Export the deviations of a ride from its plan as GPX.
+///
+/// Maps to: RustBridge.exportDeviationsGpx(planJson, rideJson, toleranceM, minLengthM) -> String
+///
+/// Same arguments as deviationReport.
+/// Returns GPX 1.1 text with the plan as a route and each deviation as
+/// a track named "Deviation N", or JSON { error } on bad input.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_exportDeviationsGpx(
+    mut env: JNIEnv,
+    _class: JClass,
+    plan_json: JString,
+    ride_json: JString,
+    tolerance_m: jdouble,
+    min_length_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let plan: String = env
+            .get_string(&plan_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let ride: String = env
+            .get_string(&ride_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::deviation::deviations_gpx_json(&plan, &ride, tolerance_m, min_length_m)
+    })();
+    json_result(&mut env, result)
+}
+
+// -- Elevation --
+
+/// Set elevations from local SRTM HGT tiles.
+///
+/// Maps to: RustBridge.applyDemElevation(gpxJson, demDir, replace) -> String
+///
+/// gpxJson: { tracks: [], routes: [], waypoints: [] }
+/// demDir: directory holding <band>/<tile>.hgt files, e.g. files/dem.
+/// replace: overwrite existing elevations instead of only filling gaps.
+/// Returns the same JSON as parseGpx plus "updated_points".
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_applyDemElevation(
+    mut env: JNIEnv,
+    _class: JClass,
+    gpx_json: JString,
+    dem_dir: JString,
+    replace: jboolean,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&gpx_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let dem_dir: String = env
+            .get_string(&dem_dir)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::dem::apply_json(&json_str, &dem_dir, replace != 0)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Clean noisy track elevations with a chain of filters.
+///
+/// Maps to: RustBridge.filterElevation(gpxJson, filtersJson, demDir) -> String
+///
+/// gpxJson: { tracks: [], routes: [], waypoints: [] }
+/// filtersJson: e.g. [{ method: "median", window: 5 },
+///   { method: "kalman", process_noise, measurement_noise_m },
+///   { method: "hysteresis", threshold_m }, { method: "dem_offset" }],
+///   or "" for the recommended chain.
+/// demDir: DEM directory for "dem_offset", or null.
+/// Returns the same JSON as parseGpx plus "ascent_before_m" and "ascent_after_m".
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_filterElevation(
+    mut env: JNIEnv,
+    _class: JClass,
+    gpx_json: JString,
+    filters_json: JString,
+    dem_dir: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&gpx_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let filters: String = env
+            .get_string(&filters_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let dem_dir: Option<String> = if dem_dir.is_null() {
+            None
+        } else {
+            Some(
+                env.get_string(&dem_dir)
+                    .map_err(|e| format!("JNI string conversion failed: {e}"))?
+                    .into(),
+            )
+        };
+        crate::elevation::filter_json(&json_str, &filters, dem_dir.as_deref())
+    })();
+    json_result(&mut env, result)
+}
+
+/// Build an elevation profile for a track or route.
+///
+/// Maps to: RustBridge.elevationProfile(pointsJson, sampleCount, spacingM,
+///   smoothingM, demDir) -> String
+///
+/// pointsJson: JSON array of {lat, lon, ele?} objects.
+/// sampleCount > 0 gives that many samples, otherwise one every spacingM.
+/// smoothingM: moving-average window in meters, 0 for none.
+/// demDir: DEM directory for elevations, or null to use point elevations.
+/// Returns: { samples: [{ distance_m, ele_m, grade_pct, ascent_m }],
+///   total_distance_m, total_ascent_m, total_descent_m, min_ele_m, max_ele_m }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_elevationProfile(
+    mut env: JNIEnv,
+    _class: JClass,
+    points_json: JString,
+    sample_count: jint,
+    spacing_m: jdouble,
+    smoothing_m: jdouble,
+    dem_dir: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&points_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let dem_dir: Option<String> = if dem_dir.is_null() {
+            None
+        } else {
+            Some(
+                env.get_string(&dem_dir)
+                    .map_err(|e| format!("JNI string conversion failed: {e}"))?
+                    .into(),
+            )
+        };
+        crate::profile::profile_json(
+            &json_str,
+            sample_count.max(0) as usize,
+            spacing_m,
+            smoothing_m,
+            dem_dir.as_deref(),
+        )
+    })();
+    json_result(&mut env, result)
+}
+
+/// Map a distance along the path to the nearest profile sample.
+///
+/// Maps to: RustBridge.profileIndexAt(profileJson, distanceAlongM) -> Int
+///
+/// distanceAlongM is typically projectOnTrack's distance_along_m.
+/// Returns the sample index, or -1 if profileJson is invalid.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_profileIndexAt(
+    mut env: JNIEnv,
+    _class: JClass,
+    profile_json: JString,
+    distance_along_m: jdouble,
+) -> jint {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&profile_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::profile::index_at_json(&json_str, distance_along_m)
+    })();
+    result.map_or(-1, |i| i as jint)
+}
+
+/// Detect significant climbs and descents along a track.
+///
+/// Maps to: RustBridge.detectClimbs(pointsJson, waypointsJson, minGainM,
+///   minGradePct) -> String
+///
+/// pointsJson: JSON array of {lat, lon, ele?} objects.
+/// waypointsJson: JSON array of waypoints used to name climbs.
+/// Returns: JSON array of { kind: "climb"|"descent", start_m, end_m,
+///   start_ele_m, end_ele_m, gain_m, avg_grade_pct, max_grade_pct,
+///   top, name? }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_detectClimbs(
+    mut env: JNIEnv,
+    _class: JClass,
+    points_json: JString,
+    waypoints_json: JString,
+    min_gain_m: jdouble,
+    min_grade_pct: jdouble,
+) -> jstring {
+    let result = (|| {
+        let points: String = env
+            .get_string(&points_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let waypoints: String = env
+            .get_string(&waypoints_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::climb::detect_climbs_json(&points, &waypoints, min_gain_m, min_grade_pct)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Find the next climb ahead of the rider.
+///
+/// Maps to: RustBridge.nextClimb(climbsJson, distanceAlongM) -> String
+///
+/// climbsJson: as returned by detectClimbs.
+/// Returns the climb plus distance_to_start_m, in_progress and
+/// remaining_m, or null when no climb is left.
 #[unsafe(no_mangle)]
-pub extern "system" fn Java_com_ndkarte_app_RustBridge_version(
-    env: JNIEnv,
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_nextClimb(
+    mut env: JNIEnv,
     _class: JClass,
+    climbs_json: JString,
+    distance_along_m: jdouble,
 ) -> jstring {
-    env.new_string(crate::VERSION)
-        .expect("failed to create Java string")
-        .into_raw()
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&climbs_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::climb::next_climb_json(&json_str, distance_along_m)
+    })();
+    json_result(&mut env, result)
 }
 
-// -- GPX Parsing --
-
-/// Parse a GPX file from raw bytes and return JSON.
-/// Maps to: RustBridge.parseGpx(data: ByteArray) -> String
+/// Render offline hillshade and contour tiles from local HGT tiles.
+///
+/// Maps to: RustBridge.buildTerrainTiles(demDir, outDir, minLat, minLon,
+///   maxLat, maxLon, minZoom, maxZoom, contourIntervalM) -> String
+///
+/// Writes outDir/hillshade.mbtiles (PNG) and outDir/contours.mbtiles
+/// (vector tiles with a "contour" layer) covering the bounding box.
+/// Returns: { hillshade_path, contour_path, hillshade_tiles, contour_tiles }
 #[unsafe(no_mangle)]
-pub extern "system" fn Java_com_ndkarte_app_RustBridge_parseGpx(
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_buildTerrainTiles(
     mut env: JNIEnv,
     _class: JClass,
-    data: JByteArray,
+    dem_dir: JString,
+    out_dir: JString,
+    min_lat: jdouble,
+    min_lon: jdouble,
+    max_lat: jdouble,
+    max_lon: jdouble,
+    min_zoom: jint,
+    max_zoom: jint,
+    contour_interval_m: jdouble,
 ) -> jstring {
     let result = (|| {
-        let bytes = env
-            .convert_byte_array(&data)
-            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
-        crate::gpx::parse_to_json(&bytes)
+        let dem_dir: String = env
+            .get_string(&dem_dir)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let out_dir: String = env
+            .get_string(&out_dir)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let zoom = |z: jint| u8::try_from(z).map_err(|_| format!("Invalid zoom level {z}"));
+        let bounds = crate::gpx::Bounds {
+            min_lat,
+            min_lon,
+            max_lat,
+            max_lon,
+        };
+        crate::terrain::build_terrain_json(
+            &dem_dir,
+            &out_dir,
+            bounds,
+            zoom(min_zoom)?,
+            zoom(max_zoom)?,
+            contour_interval_m,
+        )
     })();
     json_result(&mut env, result)
 }
@@ -80,41 +731,165 @@ pub extern "system" fn Java_com_ndkarte_app_RustBridge_projectOnTrack(
         let points: Vec<Point> = serde_json::from_str(&json_str)
             .map_err(|e| format!("Track JSON parse failed: {e}"))?;
 
-        let position = Point { lat, lon, ele: None };
-        let proj = crate::nav::project_on_track(&position, &points)
-            .ok_or_else(|| "Track has fewer than 2 points".to_string())?;
+        projection_json(lat, lon, &points)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Encode a track for repeated projectOnTrackEncoded calls.
+///
+/// Maps to: RustBridge.encodeTrack(trackJson) -> ByteArray?
+///
+/// trackJson is a JSON array of {lat, lon, ele?} objects.
+/// Returns the compact binary point list, or null on bad input.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_encodeTrack(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+) -> jbyteArray {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::binary::json_to_points_binary(&json_str)
+    })();
+    bytes_result(&mut env, result)
+}
+
+/// Project a position onto a track encoded by encodeTrack.
+///
+/// Maps to: RustBridge.projectOnTrackEncoded(lat, lon, track) -> String
+///
+/// Returns the same JSON as projectOnTrack, without re-parsing JSON
+/// for every GPS fix.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_projectOnTrackEncoded(
+    mut env: JNIEnv,
+    _class: JClass,
+    lat: jdouble,
+    lon: jdouble,
+    track: JByteArray,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&track)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        let points = crate::binary::decode_points(&bytes)?;
+        projection_json(lat, lon, &points)
+    })();
+    json_result(&mut env, result)
+}
 
-        serde_json::to_string(&proj)
+/// Classify a track as one-way, loop, or out-and-back.
+///
+/// Maps to: RustBridge.detectTrackShape(trackJson) -> String
+///
+/// trackJson is a JSON array of {lat, lon, ele?} objects.
+/// Returns JSON: { shape: "one_way"|"loop"|"out_and_back", length_m, closure_m }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_detectTrackShape(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let points: Vec<Point> = serde_json::from_str(&json_str)
+            .map_err(|e| format!("Track JSON parse failed: {e}"))?;
+        serde_json::to_string(&crate::nav::detect_shape(&points))
             .map_err(|e| format!("JSON serialize failed: {e}"))
     })();
     json_result(&mut env, result)
 }
 
+/// Project a position onto a track encoded by encodeTrack, keeping
+/// loops and out-and-backs matched in riding order.
+///
+/// Maps to: RustBridge.projectOnTrackForNavigation(lat, lon, track, shape,
+///   lastAlongM) -> String
+///
+/// shape: as returned by detectTrackShape.
+/// lastAlongM: distance_along_m of the previous fix, negative at the
+///   start of a session.
+/// Returns the same JSON as projectOnTrack plus arrival_allowed, which
+/// stays false until most of a closed track is covered.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_projectOnTrackForNavigation(
+    mut env: JNIEnv,
+    _class: JClass,
+    lat: jdouble,
+    lon: jdouble,
+    track: JByteArray,
+    shape: JString,
+    last_along_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let bytes = env
+            .convert_byte_array(&track)
+            .map_err(|e| format!("JNI byte array conversion failed: {e}"))?;
+        let points = crate::binary::decode_points(&bytes)?;
+        let shape: String = env
+            .get_string(&shape)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let shape: crate::nav::TrackShape = serde_json::from_value(shape.into())
+            .map_err(|e| format!("Track shape parse failed: {e}"))?;
+        let last = (last_along_m >= 0.0).then_some(last_along_m);
+        let position = Point { lat, lon, ele: None, time: None, speed: None, course: None };
+        let matched = crate::nav::project_for_navigation(&position, &points, shape, last)
+            .ok_or_else(|| "Track has fewer than 2 points".to_string())?;
+        serde_json::to_string(&matched).map_err(|e| format!("JSON serialize failed: {e}"))
+    })();
+    json_result(&mut env, result)
+}
+
+fn projection_json(lat: f64, lon: f64, points: &[Point]) -> Result<String, String> {
+    let position = Point { lat, lon, ele: None, time: None, speed: None, course: None };
+    let proj = crate::nav::project_on_track(&position, points)
+        .ok_or_else(|| "Track has fewer than 2 points".to_string())?;
+
+    serde_json::to_string(&proj)
+        .map_err(|e| format!("JSON serialize failed: {e}"))
+}
+
 // -- Conversion --
 
-/// Simplify a track to a route using Ramer-Douglas-Peucker.
+/// Simplify a track to a route.
 ///
-/// Maps to: RustBridge.trackToRoute(trackJson, toleranceM) -> String
+/// Maps to: RustBridge.trackToRoute(trackJson, strategy, value) -> String
 ///
 /// trackJson: { name?, points: [{lat, lon, ele?}] }
-/// Returns: { name?, points: [{lat, lon, ele?}] }
+/// strategy: "rdp" (value = tolerance in meters), "visvalingam" (value =
+///   minimum triangle area in m²) or "max_points" (value = point budget).
+/// Returns: { name?, points: [{lat, lon, ele?}], track_indices: [int] }
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_ndkarte_app_RustBridge_trackToRoute(
     mut env: JNIEnv,
     _class: JClass,
     track_json: JString,
-    tolerance_m: jdouble,
+    strategy: JString,
+    value: jdouble,
 ) -> jstring {
     let result = (|| {
         let json_str: String = env
             .get_string(&track_json)
             .map_err(|e| format!("JNI string conversion failed: {e}"))?
             .into();
+        let strategy: String = env
+            .get_string(&strategy)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let strategy = crate::convert::Simplify::from_name(&strategy, value)?;
 
         let track: crate::gpx::Track = serde_json::from_str(&json_str)
             .map_err(|e| format!("Track JSON parse failed: {e}"))?;
 
-        let route = crate::convert::track_to_route(&track, tolerance_m);
+        let route = crate::convert::track_to_route_indexed(&track, strategy);
 
         serde_json::to_string(&route)
             .map_err(|e| format!("JSON serialize failed: {e}"))
@@ -122,6 +897,198 @@ pub extern "system" fn Java_com_ndkarte_app_RustBridge_trackToRoute(
     json_result(&mut env, result)
 }
 
+/// Simplify a track to a route without losing turns or waypoints.
+///
+/// Maps to: RustBridge.trackToRouteForNavigation(trackJson, waypointsJson,
+///   segmentStartsJson, toleranceM, turnThresholdDeg) -> String
+///
+/// waypointsJson: JSON array of waypoints whose nearest track points
+///   are kept. segmentStartsJson: JSON array of point indices where a
+///   track segment began, e.g. [] for none.
+/// Returns: { name?, points: [{lat, lon, ele?}], track_indices: [int] }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_trackToRouteForNavigation(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+    waypoints_json: JString,
+    segment_starts_json: JString,
+    tolerance_m: jdouble,
+    turn_threshold_deg: jdouble,
+) -> jstring {
+    let result = (|| {
+        let track: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let waypoints: String = env
+            .get_string(&waypoints_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let segment_starts: String = env
+            .get_string(&segment_starts_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::convert::nav_simplify_json(
+            &track,
+            &waypoints,
+            &segment_starts,
+            tolerance_m,
+            turn_threshold_deg,
+        )
+    })();
+    json_result(&mut env, result)
+}
+
+/// Resample or densify a point list.
+///
+/// Maps to: RustBridge.resampleTrack(pointsJson, mode, value) -> String
+///
+/// mode: "distance" (value = spacing in meters), "time" (value =
+///   interval in seconds) or "densify" (value = maximum segment length
+///   in meters).
+/// Returns: JSON array of {lat, lon, ele?, time?}
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_resampleTrack(
+    mut env: JNIEnv,
+    _class: JClass,
+    points_json: JString,
+    mode: JString,
+    value: jdouble,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&points_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let mode: String = env
+            .get_string(&mode)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::convert::resample_json(&json_str, &mode, value)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Reverse a track.
+///
+/// Maps to: RustBridge.reverseTrack(trackJson) -> String
+///
+/// Returns: track JSON with points and courses reversed
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_reverseTrack(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::edit::reverse_json(&json_str)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Split a track in two.
+///
+/// Maps to: RustBridge.splitTrack(trackJson, atJson) -> String
+///
+/// atJson: {"index": n}, {"distance_m": d} or {"lat": .., "lon": ..}
+/// Returns: JSON array of the two parts, sharing the cut point
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_splitTrack(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+    at_json: JString,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let at: String = env
+            .get_string(&at_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::edit::split_json(&json_str, &at)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Join tracks end to end.
+///
+/// Maps to: RustBridge.joinTracks(tracksJson, maxGapM) -> String
+///
+/// Returns: { name, points, gaps: [{ index, distance_m }] }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_joinTracks(
+    mut env: JNIEnv,
+    _class: JClass,
+    tracks_json: JString,
+    max_gap_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&tracks_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::edit::join_json(&json_str, max_gap_m)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Trim distance off both ends of a track.
+///
+/// Maps to: RustBridge.trimTrack(trackJson, startM, endM) -> String
+///
+/// Returns: trimmed track JSON with interpolated end points
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_trimTrack(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+    start_m: jdouble,
+    end_m: jdouble,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::edit::trim_json(&json_str, start_m, end_m)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Crop a track to a bounding box.
+///
+/// Maps to: RustBridge.cropTrack(trackJson, minLat, minLon, maxLat, maxLon) -> String
+///
+/// Returns: JSON array of tracks, one per stretch inside the box
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_cropTrack(
+    mut env: JNIEnv,
+    _class: JClass,
+    track_json: JString,
+    min_lat: jdouble,
+    min_lon: jdouble,
+    max_lat: jdouble,
+    max_lon: jdouble,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&track_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        let bounds = crate::gpx::Bounds { min_lat, min_lon, max_lat, max_lon };
+        crate::edit::crop_json(&json_str, &bounds)
+    })();
+    json_result(&mut env, result)
+}
+
 /// Convert a route to a track (direct copy).
 ///
 /// Maps to: RustBridge.routeToTrack(routeJson) -> String
@@ -148,6 +1115,59 @@ pub extern "system" fn Java_com_ndkarte_app_RustBridge_routeToTrack(
     json_result(&mut env, result)
 }
 
+/// Encode points as an encoded polyline (Google/OSRM format).
+///
+/// Maps to: RustBridge.encodePolyline(pointsJson, precision) -> String
+///
+/// pointsJson: JSON array of {lat, lon, ele?} objects.
+/// precision: 5 (Google, OSRM default) or 6 (polyline6, Valhalla).
+/// Returns: { polyline: "..." }
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_encodePolyline(
+    mut env: JNIEnv,
+    _class: JClass,
+    points_json: JString,
+    precision: jint,
+) -> jstring {
+    let result = (|| {
+        let json_str: String = env
+            .get_string(&points_json)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::convert::json_to_polyline(&json_str, polyline_precision(precision)?)
+    })();
+    json_result(&mut env, result)
+}
+
+/// Decode an encoded polyline into points.
+///
+/// Maps to: RustBridge.decodePolyline(polyline, precision) -> String
+///
+/// Returns: JSON array of {lat, lon} objects.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_ndkarte_app_RustBridge_decodePolyline(
+    mut env: JNIEnv,
+    _class: JClass,
+    polyline: JString,
+    precision: jint,
+) -> jstring {
+    let result = (|| {
+        let encoded: String = env
+            .get_string(&polyline)
+            .map_err(|e| format!("JNI string conversion failed: {e}"))?
+            .into();
+        crate::convert::polyline_to_json(&encoded, polyline_precision(precision)?)
+    })();
+    json_result(&mut env, result)
+}
+
+fn polyline_precision(precision: jint) -> Result<u32, String> {
+    match precision {
+        5 | 6 => Ok(precision as u32),
+        _ => Err(format!("Unsupported polyline precision {precision}, expected 5 or 6")),
+    }
+}
+
 // -- Route Navigation --
 
 /// Generate turn-by-turn instructions for a route.
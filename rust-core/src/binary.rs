@@ -0,0 +1,626 @@
+//! Compact binary encoding of track data and an on-disk cache.
+//!
+//! Parsing hundreds of XML files on every app start, and shipping the
+//! active track across JNI as JSON on every GPS fix, both cost far more
+//! than the data warrants. This module defines a versioned binary
+//! encoding that is typically 5-10x smaller than the JSON and decodes
+//! without any text parsing.
+//!
+//! Layout (all integers are LEB128 varints, signed values zigzag-encoded):
+//!
+//! - 4-byte magic (`NDKB` for `GpxData`, `NDKP` for a bare point list),
+//!   then the format version byte
+//! - a point sequence is its length, then the latitude and longitude
+//!   columns as deltas in units of 1e-7 degrees, then one optional
+//!   column each for elevation (cm), time (ms), speed (cm/s) and
+//!   course (0.01 degrees)
+//! - an optional column starts with a mode byte: absent, present for
+//!   every point, or present for some points followed by a bitmap;
+//!   its values are deltas between consecutive present values
+//!
+//! Encoding rounds to the units above, well below GPS accuracy.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::gpx::{GpxData, GpxVersion, Point, Route, Track, Waypoint};
+
+const MAGIC_DATA: &[u8; 4] = b"NDKB";
+const MAGIC_POINTS: &[u8; 4] = b"NDKP";
+
+/// Current encoding version. Decoding rejects any other version, so
+/// cache entries written by an older app are simply rebuilt.
+pub const FORMAT_VERSION: u8 = 1;
+
+const COORD_SCALE: f64 = 1e7;
+const ELE_SCALE: f64 = 100.0;
+const TIME_SCALE: f64 = 1000.0;
+const SPEED_SCALE: f64 = 100.0;
+const COURSE_SCALE: f64 = 100.0;
+
+const COLUMN_ABSENT: u8 = 0;
+const COLUMN_DENSE: u8 = 1;
+const COLUMN_SPARSE: u8 = 2;
+
+/// Encode all tracks, routes and waypoints.
+pub fn encode(data: &GpxData) -> Vec<u8> {
+    let mut out = header(MAGIC_DATA);
+    out.push(match data.version {
+        None => 0,
+        Some(GpxVersion::Gpx10) => 10,
+        Some(GpxVersion::Gpx11) => 11,
+    });
+
+    put_varint(&mut out, data.tracks.len() as u64);
+    for track in &data.tracks {
+        put_opt_str(&mut out, track.name.as_deref());
+        put_points(&mut out, &track.points);
+    }
+    put_varint(&mut out, data.routes.len() as u64);
+    for route in &data.routes {
+        put_opt_str(&mut out, route.name.as_deref());
+        put_points(&mut out, &route.points);
+    }
+    let wpt_points: Vec<Point> = data.waypoints.iter().map(|w| w.point.clone()).collect();
+    put_points(&mut out, &wpt_points);
+    for wp in &data.waypoints {
+        put_opt_str(&mut out, wp.name.as_deref());
+        put_opt_str(&mut out, wp.icon.as_deref());
+    }
+    out
+}
+
+/// Decode data written by [`encode`].
+pub fn decode(bytes: &[u8]) -> Result<GpxData, String> {
+    let mut cur = Cursor::new(bytes);
+    cur.header(MAGIC_DATA)?;
+    let version = match cur.byte()? {
+        0 => None,
+        10 => Some(GpxVersion::Gpx10),
+        11 => Some(GpxVersion::Gpx11),
+        v => return Err(format!("Binary track error: unknown GPX version {v}")),
+    };
+
+    let mut data = GpxData {
+        version,
+        ..GpxData::default()
+    };
+    for _ in 0..cur.count()? {
+        let name = cur.opt_str()?;
+        let points = cur.points()?;
+        data.tracks.push(Track { name, points });
+    }
+    for _ in 0..cur.count()? {
+        let name = cur.opt_str()?;
+        let points = cur.points()?;
+        data.routes.push(Route { name, points });
+    }
+    for point in cur.points()? {
+        let name = cur.opt_str()?;
+        let icon = cur.opt_str()?;
+        data.waypoints.push(Waypoint { name, point, icon });
+    }
+    cur.finish()?;
+    Ok(data)
+}
+
+/// Encode a bare point list, e.g. the track used for navigation.
+pub fn encode_points(points: &[Point]) -> Vec<u8> {
+    let mut out = header(MAGIC_POINTS);
+    put_points(&mut out, points);
+    out
+}
+
+/// Decode a point list written by [`encode_points`].
+pub fn decode_points(bytes: &[u8]) -> Result<Vec<Point>, String> {
+    let mut cur = Cursor::new(bytes);
+    cur.header(MAGIC_POINTS)?;
+    let points = cur.points()?;
+    cur.finish()?;
+    Ok(points)
+}
+
+/// Encode a JSON point array (as sent to `projectOnTrack`).
+pub fn json_to_points_binary(points_json: &str) -> Result<Vec<u8>, String> {
+    let points: Vec<Point> = serde_json::from_str(points_json)
+        .map_err(|e| format!("Track JSON parse failed: {e}"))?;
+    Ok(encode_points(&points))
+}
+
+fn header(magic: &[u8; 4]) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.push(FORMAT_VERSION);
+    out
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn put_signed(out: &mut Vec<u8>, v: i64) {
+    put_varint(out, ((v << 1) ^ (v >> 63)) as u64);
+}
+
+fn put_opt_str(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        None => put_varint(out, 0),
+        Some(s) => {
+            put_varint(out, s.len() as u64 + 1);
+            out.extend_from_slice(s.as_bytes());
+        }
+    }
+}
+
+fn put_points(out: &mut Vec<u8>, points: &[Point]) {
+    put_varint(out, points.len() as u64);
+    put_delta_column(out, points.iter().map(|p| fixed(p.lat, COORD_SCALE)));
+    put_delta_column(out, points.iter().map(|p| fixed(p.lon, COORD_SCALE)));
+    put_optional_column(out, points, |p| p.ele, ELE_SCALE);
+    put_optional_column(out, points, |p| p.time, TIME_SCALE);
+    put_optional_column(out, points, |p| p.speed, SPEED_SCALE);
+    put_optional_column(out, points, |p| p.course, COURSE_SCALE);
+}
+
+fn fixed(v: f64, scale: f64) -> i64 {
+    (v * scale).round() as i64
+}
+
+fn put_delta_column(out: &mut Vec<u8>, values: impl Iterator<Item = i64>) {
+    let mut prev = 0i64;
+    for v in values {
+        put_signed(out, v.wrapping_sub(prev));
+        prev = v;
+    }
+}
+
+fn put_optional_column(
+    out: &mut Vec<u8>,
+    points: &[Point],
+    get: impl Fn(&Point) -> Option<f64>,
+    scale: f64,
+) {
+    let present = points.iter().filter(|p| get(p).is_some()).count();
+    if present == 0 {
+        out.push(COLUMN_ABSENT);
+        return;
+    }
+    if present == points.len() {
+        out.push(COLUMN_DENSE);
+    } else {
+        out.push(COLUMN_SPARSE);
+        let mut bitmap = vec![0u8; points.len().div_ceil(8)];
+        for (i, p) in points.iter().enumerate() {
+            if get(p).is_some() {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        out.extend_from_slice(&bitmap);
+    }
+    put_delta_column(out, points.iter().filter_map(&get).map(|v| fixed(v, scale)));
+}
+
+/// Bounds-checked reader over encoded bytes.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn truncated() -> String {
+        "Binary track error: unexpected end of data".to_string()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).ok_or_else(Self::truncated)?;
+        let slice = self.bytes.get(self.pos..end).ok_or_else(Self::truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn header(&mut self, magic: &[u8; 4]) -> Result<(), String> {
+        if self.take(4).ok() != Some(&magic[..]) {
+            return Err("Binary track error: bad magic".to_string());
+        }
+        let version = self.byte()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("Binary track error: unsupported version {version}"));
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.pos != self.bytes.len() {
+            return Err("Binary track error: trailing data".to_string());
+        }
+        Ok(())
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err("Binary track error: varint too long".to_string())
+    }
+
+    fn signed(&mut self) -> Result<i64, String> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    /// Read an element count, rejecting counts that could not possibly
+    /// fit in the remaining bytes so corrupt input cannot force a huge
+    /// allocation.
+    fn count(&mut self) -> Result<usize, String> {
+        let n = self.varint()?;
+        if n > (self.bytes.len() - self.pos) as u64 {
+            return Err(Self::truncated());
+        }
+        Ok(n as usize)
+    }
+
+    fn opt_str(&mut self) -> Result<Option<String>, String> {
+        let len = self.varint()?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let len = usize::try_from(len - 1).map_err(|_| Self::truncated())?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map(Some)
+            .map_err(|_| "Binary track error: invalid UTF-8 string".to_string())
+    }
+
+    fn delta_column(&mut self, n: usize, scale: f64) -> Result<Vec<f64>, String> {
+        let mut prev = 0i64;
+        let mut values = Vec::with_capacity(n);
+        for _ in 0..n {
+            prev = prev.wrapping_add(self.signed()?);
+            values.push(prev as f64 / scale);
+        }
+        Ok(values)
+    }
+
+    fn optional_column(&mut self, n: usize, scale: f64) -> Result<Vec<Option<f64>>, String> {
+        match self.byte()? {
+            COLUMN_ABSENT => Ok(vec![None; n]),
+            COLUMN_DENSE => Ok(self.delta_column(n, scale)?.into_iter().map(Some).collect()),
+            COLUMN_SPARSE => {
+                let bitmap = self.take(n.div_ceil(8))?;
+                let present = (0..n).filter(|i| bitmap[i / 8] & (1 << (i % 8)) != 0);
+                let mut values = self.delta_column(present.clone().count(), scale)?.into_iter();
+                let mut column = vec![None; n];
+                for i in present {
+                    column[i] = values.next();
+                }
+                Ok(column)
+            }
+            mode => Err(format!("Binary track error: unknown column mode {mode}")),
+        }
+    }
+
+    fn points(&mut self) -> Result<Vec<Point>, String> {
+        let n = self.count()?;
+        let lats = self.delta_column(n, COORD_SCALE)?;
+        let lons = self.delta_column(n, COORD_SCALE)?;
+        let eles = self.optional_column(n, ELE_SCALE)?;
+        let times = self.optional_column(n, TIME_SCALE)?;
+        let speeds = self.optional_column(n, SPEED_SCALE)?;
+        let courses = self.optional_column(n, COURSE_SCALE)?;
+        Ok((0..n)
+            .map(|i| Point {
+                lat: lats[i],
+                lon: lons[i],
+                ele: eles[i],
+                time: times[i],
+                speed: speeds[i],
+                course: courses[i],
+            })
+            .collect())
+    }
+}
+
+// -- Cache --
+
+/// Version of what the importers read from a file. It is part of every
+/// cache key; bump it whenever a parser change alters the imported data,
+/// so that entries written by the old parsers are rebuilt.
+pub const IMPORTER_VERSION: u32 = 2;
+
+/// Entries unused for this long are removed whenever an entry is
+/// stored, so those of edited or deleted source files do not pile up.
+const MAX_ENTRY_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Directory of encoded `GpxData`, keyed by a hash of the source file.
+///
+/// An edited source file has a different key, entries from an older
+/// [`IMPORTER_VERSION`] have a different key prefix, and entries from
+/// an older [`FORMAT_VERSION`] fail to decode; all of them are rebuilt,
+/// and [`prune`](Self::prune) removes the stale ones. Any cache failure
+/// falls back to parsing.
+pub struct TrackCache {
+    dir: PathBuf,
+}
+
+impl TrackCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Cache key of a source file: the importer version, the file's
+    /// length and its 64-bit FNV-1a hash.
+    pub fn key(source: &[u8]) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &b in source {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        format!("{}{:x}-{hash:016x}", Self::key_prefix(), source.len())
+    }
+
+    fn key_prefix() -> String {
+        format!("v{IMPORTER_VERSION}-")
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.ndkb"))
+    }
+
+    /// Look up a cached entry. Missing or unreadable entries are `None`.
+    ///
+    /// A hit marks the entry as used, which keeps it from being pruned.
+    pub fn get(&self, key: &str) -> Option<GpxData> {
+        let path = self.path(key);
+        let data = decode(&fs::read(&path).ok()?).ok()?;
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(data)
+    }
+
+    /// Store an entry, replacing any previous one atomically.
+    pub fn put(&self, key: &str, data: &GpxData) -> Result<(), String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Cannot create cache dir {}: {e}", self.dir.display()))?;
+        let path = self.path(key);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, encode(data)).map_err(|e| format!("Cannot write cache entry: {e}"))?;
+        fs::rename(&tmp, &path).map_err(|e| format!("Cannot write cache entry: {e}"))?;
+        self.prune();
+        Ok(())
+    }
+
+    /// Remove entries written by another importer version and entries
+    /// unused for longer than [`MAX_ENTRY_AGE`]. Returns how many were
+    /// removed; entries that cannot be inspected are left alone.
+    pub fn prune(&self) -> usize {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return 0;
+        };
+        let prefix = Self::key_prefix();
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "ndkb") {
+                continue;
+            }
+            let current = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix));
+            let unused = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > MAX_ENTRY_AGE);
+            if (!current || unused) && fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Return the cached data for a source file, or import it (any
+    /// format supported by [`crate::import`]) and cache the result.
+    pub fn load(&self, source: &[u8]) -> Result<GpxData, String> {
+        let key = Self::key(source);
+        if let Some(data) = self.get(&key) {
+            return Ok(data);
+        }
+        let data = crate::import::import(source)?.data;
+        // A read-only or full cache directory must not break loading
+        let _ = self.put(&key, &data);
+        Ok(data)
+    }
+
+    /// [`load`](Self::load) a file from disk.
+    pub fn load_file(&self, path: &str) -> Result<GpxData, String> {
+        let source = fs::read(path).map_err(|e| format!("Cannot open {path}: {e}"))?;
+        self.load(&source)
+    }
+}
+
+/// Load a file through the cache in `cache_dir` and return GPX JSON.
+pub fn load_file_cached_to_json(path: &str, cache_dir: &str) -> Result<String, String> {
+    let data = TrackCache::new(cache_dir).load_file(path)?;
+    serde_json::to_string(&data).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    fn sample_data() -> GpxData {
+        let track_points = (0..100)
+            .map(|i| Point {
+                ele: if i % 10 == 3 { None } else { Some(200.0 + i as f64 * 0.25) },
+                time: Some(1_714_557_600.0 + i as f64),
+                speed: Some(4.2),
+                ..pt(48.2082 + i as f64 * 1e-4, 16.3738 - i as f64 * 2e-4)
+            })
+            .collect();
+        GpxData {
+            tracks: vec![Track {
+                name: Some("Donauinsel".into()),
+                points: track_points,
+            }],
+            routes: vec![Route {
+                name: None,
+                points: vec![pt(-33.8688, 151.2093), pt(-33.8700, 151.2100)],
+            }],
+            waypoints: vec![Waypoint {
+                name: Some("Café ☕".into()),
+                point: Point { ele: Some(171.0), ..pt(48.2, 16.37) },
+                icon: Some("cafe".into()),
+            }],
+            version: Some(GpxVersion::Gpx10),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance, "{a} != {b}");
+    }
+
+    #[test]
+    fn round_trip_preserves_data() {
+        let data = sample_data();
+        let decoded = decode(&encode(&data)).unwrap();
+
+        assert_eq!(decoded.version, Some(GpxVersion::Gpx10));
+        assert_eq!(decoded.tracks[0].name.as_deref(), Some("Donauinsel"));
+        assert_eq!(decoded.routes[0].name, None);
+        assert_eq!(decoded.waypoints[0].name.as_deref(), Some("Café ☕"));
+        assert_eq!(decoded.waypoints[0].icon.as_deref(), Some("cafe"));
+        assert_eq!(decoded.waypoints[0].point.ele, Some(171.0));
+
+        for (a, b) in data.tracks[0].points.iter().zip(&decoded.tracks[0].points) {
+            assert_close(a.lat, b.lat, 1e-7);
+            assert_close(a.lon, b.lon, 1e-7);
+            assert_eq!(a.ele.is_some(), b.ele.is_some());
+            if let (Some(x), Some(y)) = (a.ele, b.ele) {
+                assert_close(x, y, 0.01);
+            }
+            assert_eq!(a.time, b.time);
+            assert_eq!(a.speed, b.speed);
+            assert_eq!(b.course, None);
+        }
+        let r = &decoded.routes[0].points[1];
+        assert_close(r.lat, -33.87, 1e-7);
+        assert_close(r.lon, 151.21, 1e-7);
+    }
+
+    #[test]
+    fn encoding_is_much_smaller_than_json() {
+        let data = sample_data();
+        let json = serde_json::to_string(&data).unwrap();
+        assert!(encode(&data).len() * 5 < json.len());
+    }
+
+    #[test]
+    fn point_list_round_trip() {
+        let points = sample_data().tracks.remove(0).points;
+        let decoded = decode_points(&encode_points(&points)).unwrap();
+        assert_eq!(decoded.len(), points.len());
+        assert_close(decoded[99].lat, points[99].lat, 1e-7);
+    }
+
+    #[test]
+    fn decode_rejects_corrupt_input() {
+        let bytes = encode(&sample_data());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(b"NDKB").is_err());
+        assert!(decode_points(&bytes).is_err());
+
+        let mut newer = bytes.clone();
+        newer[4] = FORMAT_VERSION + 1;
+        assert!(decode(&newer).unwrap_err().contains("unsupported version"));
+
+        // A huge point count must fail cleanly instead of allocating
+        let mut huge = header(MAGIC_POINTS);
+        put_varint(&mut huge, u64::MAX >> 1);
+        assert!(decode_points(&huge).is_err());
+    }
+
+    #[test]
+    fn cache_stores_and_reuses_entries() {
+        let dir = std::env::temp_dir().join(format!("ndkarte-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = TrackCache::new(&dir);
+        let gpx = br#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><name>Cached</name><trkseg>
+    <trkpt lat="48.0" lon="16.0"/><trkpt lat="48.1" lon="16.1"/>
+  </trkseg></trk>
+</gpx>"#;
+
+        let key = TrackCache::key(gpx);
+        assert!(cache.get(&key).is_none());
+        let first = cache.load(gpx).unwrap();
+        assert_eq!(first.tracks[0].name.as_deref(), Some("Cached"));
+        assert!(cache.get(&key).is_some());
+
+        // Replace the entry to prove the next load is served from cache
+        let mut marked = first.clone();
+        marked.tracks[0].name = Some("From cache".into());
+        cache.put(&key, &marked).unwrap();
+        let second = cache.load(gpx).unwrap();
+        assert_eq!(second.tracks[0].name.as_deref(), Some("From cache"));
+
+        assert_ne!(TrackCache::key(b"a"), TrackCache::key(b"b"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cache_prunes_stale_entries() {
+        let dir = std::env::temp_dir().join(format!("ndkarte-prune-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = TrackCache::new(&dir);
+        let data = sample_data();
+
+        let (fresh, old) = (TrackCache::key(b"fresh"), TrackCache::key(b"old"));
+        assert!(fresh.starts_with(&format!("v{IMPORTER_VERSION}-")));
+        cache.put(&old, &data).unwrap();
+        let month_ago = SystemTime::now() - MAX_ENTRY_AGE - Duration::from_secs(60);
+        let file = fs::File::options().append(true).open(cache.path(&old)).unwrap();
+        file.set_modified(month_ago).unwrap();
+        drop(file);
+        // An entry keyed by an earlier importer version
+        fs::copy(cache.path(&old), dir.join("v1-5-0123456789abcdef.ndkb")).unwrap();
+        fs::write(dir.join("notes.txt"), "keep").unwrap();
+
+        // Storing an entry prunes the other two
+        cache.put(&fresh, &data).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["notes.txt".to_string(), format!("{fresh}.ndkb")]);
+        assert!(cache.get(&fresh).is_some());
+        assert_eq!(cache.prune(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,431 @@
+//! Climb and pass detection along a track.
+//!
+//! Finds significant climbs and descents in a smoothed elevation
+//! profile (see `profile`), names them after a nearby waypoint where
+//! one exists (typically the pass or summit), and reports the next one
+//! ahead of the rider, e.g. "next pass in 12 km, 640 m climb at 7 %".
+
+use serde::{Deserialize, Serialize};
+
+use crate::gpx::{Point, Waypoint};
+use crate::nav::haversine;
+use crate::profile::{
+    build_profile, cumulative_distances, position_at, ProfileOptions, ProfileSample, Sampling,
+};
+
+/// Whether a section goes up or down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClimbKind {
+    Climb,
+    Descent,
+}
+
+/// Thresholds for [`detect_climbs`].
+#[derive(Debug, Clone, Copy)]
+pub struct ClimbOptions {
+    /// Minimum elevation gain (or loss, for descents) in meters.
+    pub min_gain_m: f64,
+    /// Minimum average grade in percent, ignoring sign.
+    pub min_grade_pct: f64,
+    /// Counter-movements smaller than this do not end a section, so
+    /// a short dip on the way up stays part of one climb.
+    pub tolerance_m: f64,
+    /// Waypoints within this distance of the top name the section.
+    pub name_radius_m: f64,
+}
+
+impl Default for ClimbOptions {
+    fn default() -> Self {
+        Self {
+            min_gain_m: 100.0,
+            min_grade_pct: 3.0,
+            tolerance_m: 20.0,
+            name_radius_m: 500.0,
+        }
+    }
+}
+
+/// A detected climb or descent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Climb {
+    pub kind: ClimbKind,
+    /// Distance along the track where the section starts, in meters.
+    pub start_m: f64,
+    /// Distance along the track where the section ends, in meters.
+    pub end_m: f64,
+    pub start_ele_m: f64,
+    pub end_ele_m: f64,
+    /// Elevation gained (climb) or lost (descent), always positive.
+    pub gain_m: f64,
+    /// Average grade in percent, negative for descents.
+    pub avg_grade_pct: f64,
+    /// Steepest grade over the profile sample spacing, negative for
+    /// descents.
+    pub max_grade_pct: f64,
+    /// The highest point: the end of a climb, the start of a descent.
+    pub top: Point,
+    /// Name of the nearest waypoint to the top, if one is close enough.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Climb {
+    pub fn length_m(&self) -> f64 {
+        self.end_m - self.start_m
+    }
+}
+
+/// The next section ahead of the rider.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpcomingClimb {
+    #[serde(flatten)]
+    pub climb: Climb,
+    /// Distance from the rider to the start, 0 once it has begun.
+    pub distance_to_start_m: f64,
+    /// Whether the rider is already on this section.
+    pub in_progress: bool,
+    /// Distance from the rider to the end of the section.
+    pub remaining_m: f64,
+}
+
+/// Profile sample spacing used for detection, in meters.
+const SAMPLE_SPACING_M: f64 = 50.0;
+
+/// Smoothing window used for detection, in meters. Wide enough to
+/// remove GPS elevation jitter, narrow enough to keep hairpins.
+const SMOOTHING_M: f64 = 150.0;
+
+/// Detect climbs and descents along a track, in track order.
+///
+/// Needs point elevations; returns an empty list without them.
+pub fn detect_climbs(
+    points: &[Point],
+    waypoints: &[Waypoint],
+    options: &ClimbOptions,
+) -> Vec<Climb> {
+    let profile_options = ProfileOptions {
+        sampling: Sampling::Spacing(SAMPLE_SPACING_M),
+        smoothing_m: SMOOTHING_M,
+        filter_elevation: true,
+    };
+    let Some(profile) = build_profile(points, &profile_options, None) else {
+        return Vec::new();
+    };
+    let eles: Vec<f64> = profile.samples.iter().map(|s| s.ele_m).collect();
+    let cumulative = cumulative_distances(points);
+
+    turning_points(&eles, options.tolerance_m)
+        .windows(2)
+        .filter_map(|w| {
+            let (a, b) = trim_flat_ends(&profile.samples, w[0], w[1]);
+            let (sa, sb) = (&profile.samples[a], &profile.samples[b]);
+            let gain = (sb.ele_m - sa.ele_m).abs();
+            let length = sb.distance_m - sa.distance_m;
+            if length <= 0.0 || gain < options.min_gain_m {
+                return None;
+            }
+            let avg_grade_pct = (sb.ele_m - sa.ele_m) / length * 100.0;
+            if avg_grade_pct.abs() < options.min_grade_pct {
+                return None;
+            }
+
+            let kind = if sb.ele_m > sa.ele_m {
+                ClimbKind::Climb
+            } else {
+                ClimbKind::Descent
+            };
+            let steps = &profile.samples[a + 1..=b];
+            let max_grade_pct = match kind {
+                ClimbKind::Climb => steps.iter().map(|s| s.grade_pct).fold(f64::MIN, f64::max),
+                ClimbKind::Descent => steps.iter().map(|s| s.grade_pct).fold(f64::MAX, f64::min),
+            };
+            let top_m = match kind {
+                ClimbKind::Climb => sb.distance_m,
+                ClimbKind::Descent => sa.distance_m,
+            };
+            let mut top = position_at(points, &cumulative, top_m);
+            top.ele = Some(sa.ele_m.max(sb.ele_m));
+            let name = nearest_name(&top, waypoints, options.name_radius_m);
+
+            Some(Climb {
+                kind,
+                start_m: sa.distance_m,
+                end_m: sb.distance_m,
+                start_ele_m: sa.ele_m,
+                end_ele_m: sb.ele_m,
+                gain_m: gain,
+                avg_grade_pct,
+                max_grade_pct,
+                top,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// The first section of `kind` that the rider has not finished yet.
+pub fn next_climb(
+    climbs: &[Climb],
+    distance_along_m: f64,
+    kind: ClimbKind,
+) -> Option<UpcomingClimb> {
+    let climb = climbs
+        .iter()
+        .find(|c| c.kind == kind && c.end_m > distance_along_m)?;
+    Some(UpcomingClimb {
+        climb: climb.clone(),
+        distance_to_start_m: (climb.start_m - distance_along_m).max(0.0),
+        in_progress: climb.start_m <= distance_along_m,
+        remaining_m: climb.end_m - distance_along_m,
+    })
+}
+
+/// Detect climbs from JSON input.
+/// Returns a JSON array of climbs and descents.
+pub fn detect_climbs_json(
+    points_json: &str,
+    waypoints_json: &str,
+    min_gain_m: f64,
+    min_grade_pct: f64,
+) -> Result<String, String> {
+    let points: Vec<Point> = serde_json::from_str(points_json)
+        .map_err(|e| format!("Points JSON parse failed: {e}"))?;
+    let waypoints: Vec<Waypoint> = serde_json::from_str(waypoints_json)
+        .map_err(|e| format!("Waypoints JSON parse failed: {e}"))?;
+    let options = ClimbOptions {
+        min_gain_m,
+        min_grade_pct,
+        ..ClimbOptions::default()
+    };
+    let climbs = detect_climbs(&points, &waypoints, &options);
+    serde_json::to_string(&climbs).map_err(|e| format!("JSON serialize failed: {e}"))
+}
+
+/// Find the next climb from JSON input.
+/// Returns the upcoming climb as a JSON object, or `null` if none is left.
+pub fn next_climb_json(climbs_json: &str, distance_along_m: f64) -> Result<String, String> {
+    let climbs: Vec<Climb> = serde_json::from_str(climbs_json)
+        .map_err(|e| format!("Climbs JSON parse failed: {e}"))?;
+    let next = next_climb(&climbs, distance_along_m, ClimbKind::Climb);
+    serde_json::to_string(&next).map_err(|e| format!("JSON serialize failed: {e}"))
+}
+
+/// Grade below which the ends of a section count as flat, in percent.
+const FLAT_GRADE_PCT: f64 = 1.0;
+
+/// Move the ends of a section inwards past flat stretches, so that a
+/// climb starts where the road starts rising rather than at the
+/// beginning of the valley floor before it.
+fn trim_flat_ends(samples: &[ProfileSample], mut a: usize, mut b: usize) -> (usize, usize) {
+    while a + 1 < b && samples[a + 1].grade_pct.abs() < FLAT_GRADE_PCT {
+        a += 1;
+    }
+    while b > a + 1 && samples[b].grade_pct.abs() < FLAT_GRADE_PCT {
+        b -= 1;
+    }
+    (a, b)
+}
+
+/// Indices of alternating minima and maxima, ignoring reversals
+/// smaller than `tolerance`. The first and last index are the start
+/// and end of the first and last rise or fall.
+fn turning_points(eles: &[f64], tolerance: f64) -> Vec<usize> {
+    #[derive(PartialEq)]
+    enum Trend {
+        Unknown,
+        Up,
+        Down,
+    }
+
+    let mut turns = Vec::new();
+    let mut trend = Trend::Unknown;
+    let (mut lo, mut hi) = (0, 0);
+    // Most extreme index in the current trend direction
+    let mut candidate = 0;
+
+    for (i, &e) in eles.iter().enumerate().skip(1) {
+        match trend {
+            Trend::Unknown => {
+                if e < eles[lo] {
+                    lo = i;
+                }
+                if e > eles[hi] {
+                    hi = i;
+                }
+                if e - eles[lo] >= tolerance {
+                    turns.push(lo);
+                    trend = Trend::Up;
+                    candidate = i;
+                } else if eles[hi] - e >= tolerance {
+                    turns.push(hi);
+                    trend = Trend::Down;
+                    candidate = i;
+                }
+            }
+            Trend::Up => {
+                if e >= eles[candidate] {
+                    candidate = i;
+                } else if eles[candidate] - e >= tolerance {
+                    turns.push(candidate);
+                    trend = Trend::Down;
+                    candidate = i;
+                }
+            }
+            Trend::Down => {
+                if e <= eles[candidate] {
+                    candidate = i;
+                } else if e - eles[candidate] >= tolerance {
+                    turns.push(candidate);
+                    trend = Trend::Up;
+                    candidate = i;
+                }
+            }
+        }
+    }
+    if trend != Trend::Unknown {
+        turns.push(candidate);
+    }
+    turns
+}
+
+fn nearest_name(top: &Point, waypoints: &[Waypoint], radius_m: f64) -> Option<String> {
+    waypoints
+        .iter()
+        .filter(|w| w.name.is_some())
+        .map(|w| (haversine(top, &w.point), w))
+        .filter(|(d, _)| *d <= radius_m)
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .and_then(|(_, w)| w.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A track due north with one point every ~111 m at the given
+    /// elevations.
+    fn track(eles: &[f64]) -> Vec<Point> {
+        eles.iter()
+            .enumerate()
+            .map(|(i, &ele)| Point {
+                lat: 46.0 + i as f64 * 0.001,
+                lon: 11.0,
+                ele: Some(ele),
+                time: None,
+                speed: None,
+                course: None,
+            })
+            .collect()
+    }
+
+    /// Flat, an 8 % climb of 400 m with a 10 m dip halfway, flat, and
+    /// a 6 % descent of 300 m.
+    fn alpine_eles() -> Vec<f64> {
+        let mut eles = vec![1000.0; 10];
+        let mut ele = 1000.0;
+        for i in 0..45 {
+            ele += if i == 22 { -10.0 } else { 9.33 };
+            eles.push(ele);
+        }
+        eles.extend(std::iter::repeat_n(ele, 10));
+        for _ in 0..45 {
+            ele -= 6.67;
+            eles.push(ele);
+        }
+        eles.extend(std::iter::repeat_n(ele, 10));
+        eles
+    }
+
+    fn waypoint(name: &str, point: &Point) -> Waypoint {
+        Waypoint {
+            name: Some(name.into()),
+            point: point.clone(),
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn turning_points_ignore_small_reversals() {
+        let eles = [0.0, 10.0, 5.0, 50.0, 45.0, 0.0];
+        assert_eq!(turning_points(&eles, 20.0), vec![0, 3, 5]);
+        assert_eq!(turning_points(&eles, 1.0), vec![0, 1, 2, 3, 5]);
+        assert!(turning_points(&[5.0, 6.0, 5.0], 20.0).is_empty());
+    }
+
+    #[test]
+    fn detects_climb_and_descent() {
+        let points = track(&alpine_eles());
+        let pass = &points[57];
+        let waypoints = vec![waypoint("Passo Giau", pass), waypoint("Far away", &points[0])];
+
+        let climbs = detect_climbs(&points, &waypoints, &ClimbOptions::default());
+        assert_eq!(climbs.len(), 2, "{climbs:?}");
+
+        let climb = &climbs[0];
+        assert_eq!(climb.kind, ClimbKind::Climb);
+        // One section despite the dip halfway up
+        assert!((climb.gain_m - 400.0).abs() < 30.0, "gain {}", climb.gain_m);
+        assert!((climb.avg_grade_pct - 8.0).abs() < 1.5, "grade {}", climb.avg_grade_pct);
+        assert!(climb.max_grade_pct >= climb.avg_grade_pct);
+        assert_eq!(climb.name.as_deref(), Some("Passo Giau"));
+        assert!(haversine(&climb.top, pass) < 500.0);
+
+        let descent = &climbs[1];
+        assert_eq!(descent.kind, ClimbKind::Descent);
+        assert!(descent.avg_grade_pct < -4.0);
+        assert!(descent.max_grade_pct <= descent.avg_grade_pct);
+        assert!(descent.start_m >= climb.end_m);
+    }
+
+    #[test]
+    fn thresholds_filter_sections() {
+        let points = track(&alpine_eles());
+        let strict = ClimbOptions {
+            min_gain_m: 500.0,
+            ..ClimbOptions::default()
+        };
+        assert!(detect_climbs(&points, &[], &strict).is_empty());
+
+        let steep = ClimbOptions {
+            min_grade_pct: 7.0,
+            ..ClimbOptions::default()
+        };
+        let climbs = detect_climbs(&points, &[], &steep);
+        assert_eq!(climbs.len(), 1);
+        assert_eq!(climbs[0].kind, ClimbKind::Climb);
+        assert_eq!(climbs[0].name, None);
+
+        let flat = track(&[100.0; 20]);
+        assert!(detect_climbs(&flat, &[], &ClimbOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn next_climb_relative_to_rider() {
+        let points = track(&alpine_eles());
+        let climbs = detect_climbs(&points, &[], &ClimbOptions::default());
+        let climb = &climbs[0];
+
+        let ahead = next_climb(&climbs, 100.0, ClimbKind::Climb).unwrap();
+        assert!(!ahead.in_progress);
+        assert!((ahead.distance_to_start_m - (climb.start_m - 100.0)).abs() < 1e-9);
+
+        let midway = (climb.start_m + climb.end_m) / 2.0;
+        let on_it = next_climb(&climbs, midway, ClimbKind::Climb).unwrap();
+        assert!(on_it.in_progress);
+        assert_eq!(on_it.distance_to_start_m, 0.0);
+        assert!((on_it.remaining_m - (climb.end_m - midway)).abs() < 1e-9);
+
+        assert!(next_climb(&climbs, climb.end_m + 1.0, ClimbKind::Climb).is_none());
+        assert!(next_climb(&climbs, climb.end_m + 1.0, ClimbKind::Descent).is_some());
+    }
+
+    #[test]
+    fn json_wrappers() {
+        let points = serde_json::to_string(&track(&alpine_eles())).unwrap();
+        let climbs = detect_climbs_json(&points, "[]", 100.0, 3.0).unwrap();
+        let next = next_climb_json(&climbs, 0.0).unwrap();
+        assert!(next.contains("\"kind\":\"climb\""));
+        assert_eq!(next_climb_json(&climbs, 1e9).unwrap(), "null");
+    }
+}
@@ -2,23 +2,296 @@
 //!
 //! Converts between GPX tracks (recorded paths with many dense points)
 //! and GPX routes (planned paths with sparse waypoints). Track-to-route
-//! uses the Ramer-Douglas-Peucker algorithm to simplify the point list.
-//! Route-to-track is a direct copy since routes are a subset of tracks.
+//! simplifies the point list with Ramer-Douglas-Peucker or
+//! Visvalingam-Whyatt, or down to a fixed point budget for devices that
+//! cap route length. The navigation-aware mode additionally pins turn
+//! apexes, points next to waypoints and segment boundaries, so
+//! `route_nav::generate_instructions` sees every junction. Route-to-track
+//! is a direct copy since routes are a subset of tracks.
+//!
+//! Resampling evens out point spacing: at fixed distance or time steps
+//! (thinning dense 1 s recordings), or by densifying long legs along
+//! great circles so projections and profiles have support points.
+//!
+//! Also encodes and decodes point lists as encoded polylines, the
+//! compact text format used by Google, OSRM and Valhalla for sharing
+//! and API responses.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use serde::Serialize;
+
+use crate::geodesic;
+use crate::gpx::{Point, Track, Route, Waypoint};
+use crate::nav::haversine;
+use crate::profile::cumulative_distances;
+use crate::route_nav::{bearing, relative_angle};
+
+/// Line simplification strategy for [`track_to_route`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Simplify {
+    /// Ramer-Douglas-Peucker with a distance tolerance in meters.
+    /// Typical values:
+    /// - 10.0: light simplification, keeps most detail
+    /// - 50.0: moderate, good for navigation waypoints
+    /// - 100.0: aggressive, only major direction changes
+    Rdp { tolerance_m: f64 },
+    /// Visvalingam-Whyatt: drop points whose effective triangle area is
+    /// below `min_area_m2`. Removes small wiggles more evenly than RDP.
+    Visvalingam { min_area_m2: f64 },
+    /// Keep at most this many points (at least the two endpoints),
+    /// dropping the least important ones by Visvalingam-Whyatt area.
+    /// Fits routes to devices that cap the point count (Garmin units
+    /// typically allow 50-250).
+    MaxPoints(usize),
+}
+
+impl Simplify {
+    /// Build a strategy from its name (`rdp`, `visvalingam` or
+    /// `max_points`) and parameter value, as passed over JNI.
+    pub fn from_name(name: &str, value: f64) -> Result<Self, String> {
+        match name {
+            "rdp" if value.is_finite() && value >= 0.0 => Ok(Simplify::Rdp { tolerance_m: value }),
+            "rdp" => Err(format!("Invalid tolerance {value}")),
+            "visvalingam" if value.is_finite() && value >= 0.0 => {
+                Ok(Simplify::Visvalingam { min_area_m2: value })
+            }
+            "visvalingam" => Err(format!("Invalid minimum area {value}")),
+            "max_points" if value >= 0.0 => Ok(Simplify::MaxPoints(value as usize)),
+            "max_points" => Err(format!("Invalid point budget {value}")),
+            _ => Err(format!("Unknown simplification strategy: {name}")),
+        }
+    }
+}
+
+/// Convert a track to a route by simplifying its points.
+pub fn track_to_route(track: &Track, strategy: Simplify) -> Route {
+    track_to_route_indexed(track, strategy).route
+}
+
+/// A simplified route and, for every route point, the index of the
+/// track point it came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimplifiedRoute {
+    #[serde(flatten)]
+    pub route: Route,
+    pub track_indices: Vec<usize>,
+}
 
-use crate::gpx::{Point, Track, Route};
+/// Like [`track_to_route`], but also reports which track points were
+/// kept so route points can be mapped back to track positions.
+pub fn track_to_route_indexed(track: &Track, strategy: Simplify) -> SimplifiedRoute {
+    SimplifiedRoute::from_indices(track, simplify_indices(&track.points, strategy))
+}
 
-/// Convert a track to a route by simplifying with Ramer-Douglas-Peucker.
+impl SimplifiedRoute {
+    /// The route through the given track points, named like the track.
+    fn from_indices(track: &Track, track_indices: Vec<usize>) -> Self {
+        Self {
+            route: Route {
+                name: track.name.clone(),
+                points: track_indices.iter().map(|&i| track.points[i].clone()).collect(),
+            },
+            track_indices,
+        }
+    }
+}
+
+/// Simplify a point list with the given strategy.
+pub fn simplify(points: &[Point], strategy: Simplify) -> Vec<Point> {
+    simplify_indices(points, strategy)
+        .into_iter()
+        .map(|i| points[i].clone())
+        .collect()
+}
+
+/// Indices of the points kept by the given strategy, in ascending order.
+pub fn simplify_indices(points: &[Point], strategy: Simplify) -> Vec<usize> {
+    match strategy {
+        Simplify::Rdp { tolerance_m } => rdp_indices(points, tolerance_m),
+        Simplify::Visvalingam { min_area_m2 } => vw_importance(points)
+            .iter()
+            .enumerate()
+            .filter(|&(_, &area)| area >= min_area_m2)
+            .map(|(i, _)| i)
+            .collect(),
+        Simplify::MaxPoints(max) => {
+            let max = max.max(2);
+            if points.len() <= max {
+                return (0..points.len()).collect();
+            }
+            let importance = vw_importance(points);
+            let mut order: Vec<usize> = (0..points.len()).collect();
+            order.sort_by(|&a, &b| importance[b].total_cmp(&importance[a]));
+            let mut keep = order[..max].to_vec();
+            keep.sort_unstable();
+            keep
+        }
+    }
+}
+
+/// Options for [`nav_simplify_indices`].
+#[derive(Debug, Clone, Copy)]
+pub struct NavSimplifyOptions {
+    /// RDP tolerance between the pinned points, in meters.
+    pub tolerance_m: f64,
+    /// Heading change above which the apex of a bend is kept, in degrees.
+    /// The default sits just above the 20 degree "slight turn" limit of
+    /// `route_nav`, so every announced turn keeps its apex.
+    pub turn_threshold_deg: f64,
+    /// Distance before and after a point over which headings are
+    /// measured, in meters, so GPS jitter does not count as turning.
+    pub turn_window_m: f64,
+    /// Waypoints farther than this from the track are ignored, in meters.
+    pub waypoint_radius_m: f64,
+}
+
+impl Default for NavSimplifyOptions {
+    fn default() -> Self {
+        Self {
+            tolerance_m: 10.0,
+            turn_threshold_deg: 25.0,
+            turn_window_m: 20.0,
+            waypoint_radius_m: 50.0,
+        }
+    }
+}
+
+/// Navigation-aware simplification, returning kept indices in order.
 ///
-/// `tolerance_m` controls simplification aggressiveness. Typical values:
-/// - 10.0: light simplification, keeps most detail
-/// - 50.0: moderate, good for navigation waypoints
-/// - 100.0: aggressive, only major direction changes
-pub fn track_to_route(track: &Track, tolerance_m: f64) -> Route {
-    let simplified = rdp_simplify(&track.points, tolerance_m);
-    Route {
-        name: track.name.clone(),
-        points: simplified,
+/// Always keeps the endpoints, both sides of every boundary in
+/// `segment_starts` (indices where a flattened track segment began),
+/// the apex of every bend turning more than the threshold, and the
+/// track point nearest to each waypoint. The stretches in between are
+/// simplified with RDP, so the result still meets the tolerance.
+pub fn nav_simplify_indices(
+    points: &[Point],
+    waypoints: &[Point],
+    segment_starts: &[usize],
+    options: &NavSimplifyOptions,
+) -> Vec<usize> {
+    let n = points.len();
+    if n <= 2 {
+        return (0..n).collect();
+    }
+
+    let mut pinned = vec![false; n];
+    pinned[0] = true;
+    pinned[n - 1] = true;
+    for &s in segment_starts.iter().filter(|&&s| s > 0 && s < n) {
+        pinned[s - 1] = true;
+        pinned[s] = true;
+    }
+    for i in turn_apexes(points, options) {
+        pinned[i] = true;
+    }
+    for wp in waypoints {
+        let nearest = points
+            .iter()
+            .map(|p| haversine(p, wp))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, d)) = nearest {
+            if d <= options.waypoint_radius_m {
+                pinned[i] = true;
+            }
+        }
+    }
+
+    let anchors: Vec<usize> = (0..n).filter(|&i| pinned[i]).collect();
+    let mut kept = vec![0];
+    for w in anchors.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        let stretch = rdp_indices(&points[start..=end], options.tolerance_m);
+        kept.extend(stretch.into_iter().skip(1).map(|i| start + i));
+    }
+    kept
+}
+
+/// Indices of the sharpest point of every bend that turns by more than
+/// the threshold.
+///
+/// A point's heading change is the angle between the bearing from the
+/// point `turn_window_m` behind it and the bearing to the point
+/// `turn_window_m` ahead. Consecutive points over the threshold that
+/// turn the same way form one bend, which keeps only its maximum; a
+/// change of direction, as in a short jog, starts a new bend.
+fn turn_apexes(points: &[Point], options: &NavSimplifyOptions) -> Vec<usize> {
+    let n = points.len();
+    let along = cumulative_distances(points);
+    let mut apexes = Vec::new();
+    // Current bend as (index, signed angle) of its sharpest point so far
+    let mut bend: Option<(usize, f64)> = None;
+    let (mut back, mut ahead) = (0, 0);
+
+    for i in 1..n - 1 {
+        while back + 1 < i && along[i] - along[back + 1] >= options.turn_window_m {
+            back += 1;
+        }
+        ahead = ahead.max(i + 1);
+        while ahead < n - 1 && along[ahead] - along[i] < options.turn_window_m {
+            ahead += 1;
+        }
+
+        let turning = if along[i] - along[back] > 0.0 && along[ahead] - along[i] > 0.0 {
+            let angle = relative_angle(
+                bearing(&points[back], &points[i]),
+                bearing(&points[i], &points[ahead]),
+            );
+            (angle.abs() > options.turn_threshold_deg).then_some(angle)
+        } else {
+            None
+        };
+
+        match (turning, bend) {
+            (Some(angle), Some((apex, max))) if angle.signum() != max.signum() => {
+                apexes.push(apex);
+                bend = Some((i, angle));
+            }
+            (Some(angle), Some((_, max))) if angle.abs() > max.abs() => bend = Some((i, angle)),
+            (Some(_), Some(_)) => {}
+            (Some(angle), None) => bend = Some((i, angle)),
+            (None, Some((apex, _))) => {
+                apexes.push(apex);
+                bend = None;
+            }
+            (None, None) => {}
+        }
     }
+    if let Some((apex, _)) = bend {
+        apexes.push(apex);
+    }
+    apexes
+}
+
+/// Navigation-aware track-to-route conversion from JSON.
+///
+/// `waypoints_json` is a waypoint array as in parsed GPX data and
+/// `segment_starts_json` an array of point indices (may be empty).
+pub fn nav_simplify_json(
+    track_json: &str,
+    waypoints_json: &str,
+    segment_starts_json: &str,
+    tolerance_m: f64,
+    turn_threshold_deg: f64,
+) -> Result<String, String> {
+    let track: Track = serde_json::from_str(track_json)
+        .map_err(|e| format!("Track JSON parse failed: {e}"))?;
+    let waypoints: Vec<Waypoint> = serde_json::from_str(waypoints_json)
+        .map_err(|e| format!("Waypoints JSON parse failed: {e}"))?;
+    let segment_starts: Vec<usize> = serde_json::from_str(segment_starts_json)
+        .map_err(|e| format!("Segment JSON parse failed: {e}"))?;
+    let options = NavSimplifyOptions {
+        tolerance_m,
+        turn_threshold_deg,
+        ..NavSimplifyOptions::default()
+    };
+    let waypoint_points: Vec<Point> = waypoints.into_iter().map(|wp| wp.point).collect();
+    let track_indices =
+        nav_simplify_indices(&track.points, &waypoint_points, &segment_starts, &options);
+    let result = SimplifiedRoute::from_indices(&track, track_indices);
+    serde_json::to_string(&result).map_err(|e| format!("JSON serialize failed: {e}"))
 }
 
 /// Convert a route to a track (direct copy of points).
@@ -34,73 +307,440 @@ pub fn route_to_track(route: &Route) -> Track {
     }
 }
 
-/// Ramer-Douglas-Peucker line simplification.
+/// Encode points as an encoded polyline.
 ///
-/// Uses perpendicular distance in a local planar approximation
-/// (latitude-cosine scaled), which is accurate for track segments
-/// with moderate distances between points.
-fn rdp_simplify(points: &[Point], tolerance_m: f64) -> Vec<Point> {
-    if points.len() <= 2 {
-        return points.to_vec();
+/// `precision` is the number of decimal places kept: 5 for the Google
+/// and OSRM default, 6 for OSRM `polyline6` and Valhalla. Elevation
+/// and time are not part of the format and are dropped.
+pub fn encode_polyline(points: &[Point], precision: u32) -> String {
+    let factor = 10f64.powi(precision as i32);
+    let mut out = String::new();
+    let (mut prev_lat, mut prev_lon) = (0i64, 0i64);
+
+    for p in points {
+        let lat = (p.lat * factor).round() as i64;
+        let lon = (p.lon * factor).round() as i64;
+        push_polyline_value(&mut out, lat - prev_lat);
+        push_polyline_value(&mut out, lon - prev_lon);
+        prev_lat = lat;
+        prev_lon = lon;
+    }
+    out
+}
+
+/// Decode an encoded polyline with the given precision (5 or 6).
+///
+/// Surrounding whitespace is ignored. Fails on characters outside the
+/// polyline alphabet, a truncated final value, or coordinates out of
+/// range, which usually means the precision is wrong.
+pub fn decode_polyline(encoded: &str, precision: u32) -> Result<Vec<Point>, String> {
+    let factor = 10f64.powi(precision as i32);
+    let mut bytes = encoded.trim().bytes();
+    let mut points = Vec::new();
+    let (mut lat, mut lon) = (0i64, 0i64);
+
+    while let Some(dlat) = next_polyline_value(&mut bytes)? {
+        let dlon = next_polyline_value(&mut bytes)?
+            .ok_or_else(|| "Polyline decode failed: missing longitude".to_string())?;
+        let out_of_range = || "Polyline decode failed: value out of range".to_string();
+        lat = lat.checked_add(dlat).ok_or_else(out_of_range)?;
+        lon = lon.checked_add(dlon).ok_or_else(out_of_range)?;
+
+        let point = Point {
+            lat: lat as f64 / factor,
+            lon: lon as f64 / factor,
+            ele: None,
+            time: None,
+            speed: None,
+            course: None,
+        };
+        if point.lat.abs() > 90.0 || point.lon.abs() > 180.0 {
+            return Err(format!(
+                "Polyline decode failed: coordinate ({}, {}) out of range for precision {precision}",
+                point.lat, point.lon
+            ));
+        }
+        points.push(point);
     }
+    Ok(points)
+}
 
-    let first = &points[0];
-    let last = &points[points.len() - 1];
+/// Encode a JSON point array as a polyline.
+/// Returns: { "polyline": "..." }
+pub fn json_to_polyline(points_json: &str, precision: u32) -> Result<String, String> {
+    let points: Vec<Point> = serde_json::from_str(points_json)
+        .map_err(|e| format!("Points JSON parse failed: {e}"))?;
+    let polyline = encode_polyline(&points, precision);
+    Ok(serde_json::json!({ "polyline": polyline }).to_string())
+}
+
+/// Decode a polyline into a JSON point array.
+pub fn polyline_to_json(encoded: &str, precision: u32) -> Result<String, String> {
+    let points = decode_polyline(encoded, precision)?;
+    serde_json::to_string(&points).map_err(|e| format!("JSON serialize failed: {e}"))
+}
 
-    // Find the point with maximum distance from the line
-    let mut max_dist = 0.0;
-    let mut max_idx = 0;
+/// Resample points every `spacing_m` meters along the track.
+///
+/// Keeps the first and last point; new points lie on the great circle
+/// between the original points, with elevation and time interpolated.
+/// A non-positive spacing returns the points unchanged. Fails when
+/// the result would exceed [`MAX_RESAMPLED_POINTS`].
+pub fn resample_by_distance(points: &[Point], spacing_m: f64) -> Result<Vec<Point>, String> {
+    if points.len() < 2 || !is_positive(spacing_m) {
+        return Ok(points.to_vec());
+    }
+    let along = cumulative_distances(points);
+    let total = along[along.len() - 1];
+    check_resampled_size(total / spacing_m + 2.0)?;
 
-    for (i, p) in points.iter().enumerate().skip(1).take(points.len() - 2) {
-        let dist = perpendicular_distance_m(p, first, last);
-        if dist > max_dist {
-            max_dist = dist;
-            max_idx = i;
+    let mut out = vec![points[0].clone()];
+    let mut seg = 0;
+    let mut k = 1.0;
+    // Skip a step that would land within a millimeter of the end
+    while k * spacing_m < total - 1e-3 {
+        let d = k * spacing_m;
+        while along[seg + 1] < d {
+            seg += 1;
         }
+        let len = along[seg + 1] - along[seg];
+        let t = if len > 0.0 { (d - along[seg]) / len } else { 0.0 };
+        out.push(interpolate(&points[seg], &points[seg + 1], t));
+        k += 1.0;
     }
+    out.push(points[points.len() - 1].clone());
+    Ok(out)
+}
 
-    if max_dist > tolerance_m {
-        // Recurse on both halves
-        let mut left = rdp_simplify(&points[..=max_idx], tolerance_m);
-        let right = rdp_simplify(&points[max_idx..], tolerance_m);
+/// Resample points every `interval_s` seconds of recording time.
+///
+/// Points without a timestamp are skipped. Keeps the first and last
+/// timed point. Fails when fewer than two points have a timestamp, the
+/// timestamps run backwards, or the result would exceed
+/// [`MAX_RESAMPLED_POINTS`].
+pub fn resample_by_time(points: &[Point], interval_s: f64) -> Result<Vec<Point>, String> {
+    let timed: Vec<&Point> = points.iter().filter(|p| p.time.is_some()).collect();
+    if timed.len() < 2 {
+        return Err("Time resampling needs at least two timestamped points".into());
+    }
+    let time = |p: &Point| p.time.unwrap_or(0.0);
+    if timed.windows(2).any(|w| time(w[1]) < time(w[0])) {
+        return Err("Timestamps are not in order".into());
+    }
+    if !is_positive(interval_s) {
+        return Err(format!("Invalid time interval {interval_s}"));
+    }
 
-        // Remove duplicate junction point
-        left.pop();
-        left.extend(right);
-        left
+    let start = time(timed[0]);
+    let end = time(timed[timed.len() - 1]);
+    check_resampled_size((end - start) / interval_s + 2.0)?;
+    let mut out = vec![timed[0].clone()];
+    let mut seg = 0;
+    let mut k = 1.0;
+    while start + k * interval_s < end - 1e-6 {
+        let t = start + k * interval_s;
+        while time(timed[seg + 1]) < t {
+            seg += 1;
+        }
+        let (a, b) = (timed[seg], timed[seg + 1]);
+        let span = time(b) - time(a);
+        let frac = if span > 0.0 { (t - time(a)) / span } else { 0.0 };
+        out.push(Point {
+            time: Some(t),
+            ..interpolate(a, b, frac)
+        });
+        k += 1.0;
+    }
+    out.push(timed[timed.len() - 1].clone());
+    Ok(out)
+}
+
+/// Insert points along the great circle of every segment longer than
+/// `max_segment_m`, so no segment exceeds it. Original points are kept.
+/// Fails when the result would exceed [`MAX_RESAMPLED_POINTS`].
+pub fn densify(points: &[Point], max_segment_m: f64) -> Result<Vec<Point>, String> {
+    if points.len() < 2 || !is_positive(max_segment_m) {
+        return Ok(points.to_vec());
+    }
+    let steps: Vec<f64> = points
+        .windows(2)
+        .map(|w| (haversine(&w[0], &w[1]) / max_segment_m).ceil().max(1.0))
+        .collect();
+    check_resampled_size(steps.iter().sum::<f64>() + 1.0)?;
+    let mut out = vec![points[0].clone()];
+    for (w, &steps) in points.windows(2).zip(&steps) {
+        let steps = steps as usize;
+        for k in 1..steps {
+            out.push(interpolate(&w[0], &w[1], k as f64 / steps as f64));
+        }
+        out.push(w[1].clone());
+    }
+    Ok(out)
+}
+
+/// Resample a JSON point array.
+///
+/// `mode` is `distance` (value = spacing in meters), `time` (value =
+/// interval in seconds) or `densify` (value = maximum segment length
+/// in meters).
+pub fn resample_json(points_json: &str, mode: &str, value: f64) -> Result<String, String> {
+    let points: Vec<Point> = serde_json::from_str(points_json)
+        .map_err(|e| format!("Points JSON parse failed: {e}"))?;
+    if mode != "time" && !is_positive(value) {
+        return Err(format!("Invalid resampling distance {value}"));
+    }
+    let resampled = match mode {
+        "distance" => resample_by_distance(&points, value)?,
+        "time" => resample_by_time(&points, value)?,
+        "densify" => densify(&points, value)?,
+        _ => return Err(format!("Unknown resampling mode: {mode}")),
+    };
+    serde_json::to_string(&resampled).map_err(|e| format!("JSON serialize failed: {e}"))
+}
+
+/// Whether a step size is usable: finite and above zero.
+fn is_positive(value: f64) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+/// Most points resampling or densifying may produce.
+pub const MAX_RESAMPLED_POINTS: usize = 1_000_000;
+
+fn check_resampled_size(count: f64) -> Result<(), String> {
+    if count <= MAX_RESAMPLED_POINTS as f64 {
+        Ok(())
     } else {
-        // All intermediate points are within tolerance
-        vec![first.clone(), last.clone()]
+        Err(format!(
+            "Resampling would produce {count:.0} points, more than {MAX_RESAMPLED_POINTS}"
+        ))
+    }
+}
+
+/// Point at fraction `t` of the great circle from A to B, with
+/// elevation, time and speed interpolated linearly.
+pub(crate) fn interpolate(a: &Point, b: &Point, t: f64) -> Point {
+    let lerp = |x: Option<f64>, y: Option<f64>| match (x, y) {
+        (Some(x), Some(y)) => Some(x + t * (y - x)),
+        _ => None,
+    };
+    let (lat, lon) = great_circle_fraction(a, b, t);
+    Point {
+        lat,
+        lon,
+        ele: lerp(a.ele, b.ele),
+        time: lerp(a.time, b.time),
+        speed: lerp(a.speed, b.speed),
+        course: None,
+    }
+}
+
+/// Latitude and longitude at fraction `t` of the great circle A-B.
+fn great_circle_fraction(a: &Point, b: &Point, t: f64) -> (f64, f64) {
+    let (lat1, lon1) = (a.lat.to_radians(), a.lon.to_radians());
+    let (lat2, lon2) = (b.lat.to_radians(), b.lon.to_radians());
+    let to_xyz = |lat: f64, lon: f64| [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()];
+    let (p, q) = (to_xyz(lat1, lon1), to_xyz(lat2, lon2));
+    let dot = (p[0] * q[0] + p[1] * q[1] + p[2] * q[2]).clamp(-1.0, 1.0);
+    let delta = dot.acos();
+    if delta < 1e-12 {
+        return (a.lat, a.lon);
+    }
+    let wa = ((1.0 - t) * delta).sin() / delta.sin();
+    let wb = (t * delta).sin() / delta.sin();
+    let x = wa * p[0] + wb * q[0];
+    let y = wa * p[1] + wb * q[1];
+    let z = wa * p[2] + wb * q[2];
+    (z.atan2(x.hypot(y)).to_degrees(), y.atan2(x).to_degrees())
+}
+
+fn push_polyline_value(out: &mut String, value: i64) {
+    // Zigzag: sign moves to the lowest bit
+    let mut v = ((value << 1) ^ (value >> 63)) as u64;
+    while v >= 0x20 {
+        out.push((((v & 0x1f) | 0x20) as u8 + 63) as char);
+        v >>= 5;
+    }
+    out.push((v as u8 + 63) as char);
+}
+
+fn next_polyline_value(bytes: &mut impl Iterator<Item = u8>) -> Result<Option<i64>, String> {
+    let mut v = 0u64;
+    let mut shift = 0;
+    loop {
+        let Some(b) = bytes.next() else {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err("Polyline decode failed: truncated value".to_string())
+            };
+        };
+        if !(63..=126).contains(&b) || shift > 60 {
+            return Err(format!("Polyline decode failed: invalid character '{}'", b as char));
+        }
+        let chunk = u64::from(b - 63);
+        v |= (chunk & 0x1f) << shift;
+        shift += 5;
+        if chunk < 0x20 {
+            return Ok(Some((v >> 1) as i64 ^ -((v & 1) as i64)));
+        }
+    }
+}
+
+/// Ramer-Douglas-Peucker line simplification, returning the indices
+/// of the kept points in ascending order.
+///
+/// Works on an explicit stack of index ranges instead of recursing, so
+/// memory use is bounded by the number of pending ranges and very long
+/// or pathological tracks cannot overflow the (small) JNI thread stack.
+///
+/// Uses perpendicular distance in a local planar approximation
+/// (latitude-cosine scaled), switching to the WGS84 geodesic for chords
+/// that are long, polar or cross the antimeridian.
+pub fn rdp_indices(points: &[Point], tolerance_m: f64) -> Vec<usize> {
+    let n = points.len();
+    if n <= 2 {
+        return (0..n).collect();
+    }
+
+    let mut keep = vec![false; n];
+    keep[0] = true;
+    keep[n - 1] = true;
+    let mut pending = vec![(0, n - 1)];
+
+    while let Some((start, end)) = pending.pop() {
+        // Find the point with maximum distance from the chord, on the
+        // ellipsoid for chords where the flat metric would rank wrongly
+        let (a, b) = (&points[start], &points[end]);
+        let on_ellipsoid = geodesic::needs_geodesic(a, b);
+        let mut max_dist = 0.0;
+        let mut max_idx = start;
+        for (i, p) in points.iter().enumerate().take(end).skip(start + 1) {
+            let dist = if on_ellipsoid {
+                geodesic::cross_track(p, a, b).cross_track_m.abs()
+            } else {
+                perpendicular_distance_m(p, a, b)
+            };
+            if dist > max_dist {
+                max_dist = dist;
+                max_idx = i;
+            }
+        }
+
+        if max_dist > tolerance_m {
+            keep[max_idx] = true;
+            if max_idx - start > 1 {
+                pending.push((start, max_idx));
+            }
+            if end - max_idx > 1 {
+                pending.push((max_idx, end));
+            }
+        }
+    }
+
+    (0..n).filter(|&i| keep[i]).collect()
+}
+
+/// Visvalingam-Whyatt importance of every point: the effective area in
+/// square meters at which elimination removes it.
+///
+/// Points are removed smallest triangle first, recomputing the
+/// neighbors' triangles after each removal. A point's area never drops
+/// below that of a point removed before it, so filtering by a threshold
+/// and keeping the N largest give the same nested results. Endpoints
+/// are never removed and get infinite importance.
+fn vw_importance(points: &[Point]) -> Vec<f64> {
+    let n = points.len();
+    let mut importance = vec![f64::INFINITY; n];
+    if n <= 2 {
+        return importance;
+    }
+
+    let cos_lat = points[0].lat.to_radians().cos();
+    let xy: Vec<(f64, f64)> = points
+        .iter()
+        .map(|p| (p.lon * 111_320.0 * cos_lat, p.lat * 111_320.0))
+        .collect();
+    let area = |a: usize, b: usize, c: usize| {
+        let (ax, ay) = xy[a];
+        let (bx, by) = xy[b];
+        let (cx, cy) = xy[c];
+        ((bx - ax) * (cy - ay) - (cx - ax) * (by - ay)).abs() / 2.0
+    };
+
+    let mut prev: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| i + 1).collect();
+    let mut current: Vec<f64> = (0..n)
+        .map(|i| if i == 0 || i == n - 1 { 0.0 } else { area(i - 1, i, i + 1) })
+        .collect();
+    // Min-heap of (area, index); stale entries are skipped on pop
+    let mut heap: BinaryHeap<_> =
+        (1..n - 1).map(|i| Reverse((TotalF64(current[i]), i))).collect();
+
+    let mut max_removed = 0.0f64;
+    while let Some(Reverse((TotalF64(a), i))) = heap.pop() {
+        if importance[i].is_finite() || a != current[i] {
+            continue;
+        }
+        max_removed = max_removed.max(a);
+        importance[i] = max_removed;
+
+        let (p, nx) = (prev[i], next[i]);
+        next[p] = nx;
+        prev[nx] = p;
+        for j in [p, nx] {
+            if j != 0 && j != n - 1 {
+                current[j] = area(prev[j], j, next[j]);
+                heap.push(Reverse((TotalF64(current[j]), j)));
+            }
+        }
+    }
+    importance
+}
+
+/// `f64` ordered by `total_cmp`, for use in a `BinaryHeap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TotalF64(f64);
+
+impl Eq for TotalF64 {}
+
+impl PartialOrd for TotalF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TotalF64 {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
     }
 }
 
 /// Perpendicular distance from point P to line segment A-B, in meters.
 ///
-/// Uses a planar approximation with latitude-cosine scaling.
+/// Uses a planar approximation with latitude-cosine scaling around A.
+/// Longitude differences are wrapped, so segments across the
+/// antimeridian stay short.
 fn perpendicular_distance_m(p: &Point, a: &Point, b: &Point) -> f64 {
     let cos_lat = ((a.lat + b.lat) / 2.0).to_radians().cos();
 
-    // Convert to approximate meters
+    // Convert to approximate meters relative to A
     let m_per_deg_lat = 111_320.0;
     let m_per_deg_lon = 111_320.0 * cos_lat;
+    let wrap = |dlon: f64| (dlon + 180.0).rem_euclid(360.0) - 180.0;
 
-    let ax = a.lon * m_per_deg_lon;
-    let ay = a.lat * m_per_deg_lat;
-    let bx = b.lon * m_per_deg_lon;
-    let by = b.lat * m_per_deg_lat;
-    let px = p.lon * m_per_deg_lon;
-    let py = p.lat * m_per_deg_lat;
+    let dx = wrap(b.lon - a.lon) * m_per_deg_lon;
+    let dy = (b.lat - a.lat) * m_per_deg_lat;
+    let px = wrap(p.lon - a.lon) * m_per_deg_lon;
+    let py = (p.lat - a.lat) * m_per_deg_lat;
 
-    let dx = bx - ax;
-    let dy = by - ay;
     let len_sq = dx * dx + dy * dy;
 
     if len_sq < 1e-10 {
-        return ((px - ax).powi(2) + (py - ay).powi(2)).sqrt();
+        return (px * px + py * py).sqrt();
     }
 
     // Perpendicular distance using cross product
-    let cross = ((px - ax) * dy - (py - ay) * dx).abs();
+    let cross = (px * dy - py * dx).abs();
     cross / len_sq.sqrt()
 }
 
@@ -110,7 +750,7 @@ mod tests {
     use crate::gpx::Point;
 
     fn pt(lat: f64, lon: f64) -> Point {
-        Point { lat, lon, ele: None }
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
     }
 
     #[test]
@@ -120,7 +760,7 @@ mod tests {
             points: vec![pt(48.0, 16.0), pt(48.001, 16.001), pt(48.0, 16.002)],
         };
 
-        let route = track_to_route(&track, 1000.0);
+        let route = track_to_route(&track, Simplify::Rdp { tolerance_m: 1000.0 });
         assert_eq!(route.name.as_deref(), Some("Test"));
         // With high tolerance, only endpoints remain
         assert_eq!(route.points.len(), 2);
@@ -142,7 +782,7 @@ mod tests {
             ],
         };
 
-        let route = track_to_route(&track, 10.0);
+        let route = track_to_route(&track, Simplify::Rdp { tolerance_m: 10.0 });
         // Corner point should be preserved with low tolerance
         assert!(route.points.len() >= 3,
             "Expected at least 3 points, got {}", route.points.len());
@@ -155,7 +795,7 @@ mod tests {
             points: vec![pt(48.0, 16.0), pt(48.001, 16.001), pt(48.0, 16.002)],
         };
 
-        let route = track_to_route(&track, 0.0);
+        let route = track_to_route(&track, Simplify::Rdp { tolerance_m: 0.0 });
         // With zero tolerance, all points are preserved
         assert_eq!(route.points.len(), 3);
     }
@@ -172,11 +812,70 @@ mod tests {
         assert_eq!(track.points.len(), 2);
     }
 
+    // Reference example from the Google polyline algorithm documentation
+    const GOOGLE_POLYLINE: &str = "_p~iF~ps|U_ulLnnqC_mqNvxq`@";
+
+    fn google_points() -> Vec<Point> {
+        vec![pt(38.5, -120.2), pt(40.7, -120.95), pt(43.252, -126.453)]
+    }
+
+    #[test]
+    fn encode_polyline_reference() {
+        assert_eq!(encode_polyline(&google_points(), 5), GOOGLE_POLYLINE);
+        assert_eq!(encode_polyline(&[], 5), "");
+    }
+
+    #[test]
+    fn decode_polyline_reference() {
+        let points = decode_polyline(&format!(" {GOOGLE_POLYLINE}\n"), 5).unwrap();
+        assert_eq!(points.len(), 3);
+        for (a, b) in points.iter().zip(google_points()) {
+            assert!((a.lat - b.lat).abs() < 1e-9);
+            assert!((a.lon - b.lon).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn polyline_precision_6_round_trip() {
+        let points = vec![pt(48.208_176, 16.373_819), pt(-33.868_820, 151.209_296)];
+        let encoded = encode_polyline(&points, 6);
+        let decoded = decode_polyline(&encoded, 6).unwrap();
+        assert!((decoded[0].lat - 48.208_176).abs() < 1e-9);
+        assert!((decoded[1].lon - 151.209_296).abs() < 1e-9);
+
+        // Decoding polyline6 data as precision 5 puts points out of range
+        assert!(decode_polyline(&encoded, 5).is_err());
+    }
+
+    #[test]
+    fn decode_polyline_rejects_bad_input() {
+        assert!(decode_polyline("_p~iF", 5).is_err()); // latitude only
+        assert!(decode_polyline("_p~i", 5).is_err()); // truncated value
+        assert!(decode_polyline("_p~iF ~ps|U", 5).is_err()); // space
+        assert!(decode_polyline("", 5).unwrap().is_empty());
+
+        // Deltas that overflow the running sum
+        let mut crafted = String::new();
+        for value in [1, 0, i64::MAX, 0] {
+            push_polyline_value(&mut crafted, value);
+        }
+        let err = decode_polyline(&crafted, 5).unwrap_err();
+        assert_eq!(err, "Polyline decode failed: value out of range");
+    }
+
+    #[test]
+    fn polyline_json_wrappers() {
+        let json = json_to_polyline(r#"[{"lat": 38.5, "lon": -120.2}]"#, 5).unwrap();
+        assert_eq!(json, r#"{"polyline":"_p~iF~ps|U"}"#);
+        let points = polyline_to_json("_p~iF~ps|U", 5).unwrap();
+        assert_eq!(points, r#"[{"lat":38.5,"lon":-120.2}]"#);
+    }
+
     #[test]
     fn rdp_simplify_two_points() {
         let points = vec![pt(0.0, 0.0), pt(1.0, 1.0)];
-        let result = rdp_simplify(&points, 100.0);
-        assert_eq!(result.len(), 2);
+        let result = rdp_indices(&points, 100.0);
+        assert_eq!(result, vec![0, 1]);
     }
 
     #[test]
@@ -190,7 +889,312 @@ mod tests {
             pt(48.0, 16.02),
         ];
 
-        let result = rdp_simplify(&points, 10.0);
-        assert_eq!(result.len(), 2);
+        let result = rdp_indices(&points, 10.0);
+        assert_eq!(result, vec![0, 4]);
+    }
+
+    /// A zigzag with growing amplitude along a straight east-west line.
+    fn zigzag() -> Vec<Point> {
+        (0..21)
+            .map(|i| {
+                let amp = if i % 2 == 0 { 0.0 } else { 0.00001 * i as f64 };
+                pt(48.0 + amp, 16.0 + i as f64 * 0.001)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn visvalingam_drops_small_wiggles_first() {
+        let points = zigzag();
+        let importance = vw_importance(&points);
+        assert!(importance[0].is_infinite() && importance[20].is_infinite());
+        // The biggest wiggle outlives the smallest one
+        assert!(importance[19] > importance[1]);
+
+        let kept = simplify(&points, Simplify::Visvalingam { min_area_m2: 0.0 });
+        assert_eq!(kept.len(), points.len());
+        let kept = simplify(&points, Simplify::Visvalingam { min_area_m2: f64::MAX });
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[1].lon, points[20].lon);
+    }
+
+    #[test]
+    fn max_points_respects_budget() {
+        let points = zigzag();
+        for max in [0, 2, 5, 10, 21, 50] {
+            let kept = simplify(&points, Simplify::MaxPoints(max));
+            assert_eq!(kept.len(), max.clamp(2, points.len()), "budget {max}");
+            assert_eq!(kept[0].lon, points[0].lon);
+            assert_eq!(kept.last().unwrap().lon, points[20].lon);
+        }
+        // The largest wiggle is kept under a tight budget
+        let kept = simplify(&points, Simplify::MaxPoints(3));
+        assert_eq!(kept[1].lon, points[19].lon);
+    }
+
+    #[test]
+    fn strategy_from_name() {
+        assert_eq!(Simplify::from_name("rdp", 5.0), Ok(Simplify::Rdp { tolerance_m: 5.0 }));
+        assert_eq!(Simplify::from_name("max_points", 100.0), Ok(Simplify::MaxPoints(100)));
+        assert!(Simplify::from_name("max_points", -1.0).is_err());
+        assert!(Simplify::from_name("max_points", f64::NAN).is_err());
+        for name in ["rdp", "visvalingam"] {
+            assert!(Simplify::from_name(name, f64::NAN).is_err());
+            assert!(Simplify::from_name(name, f64::INFINITY).is_err());
+            assert!(Simplify::from_name(name, -1.0).is_err());
+            assert!(Simplify::from_name(name, 0.0).is_ok());
+        }
+        assert!(Simplify::from_name("douglas", 1.0).is_err());
+    }
+
+    #[test]
+    fn rdp_handles_pathological_track_without_recursion() {
+        // A spiral is the worst case for RDP: every split peels off a
+        // single point, which recursed once per point before.
+        let points: Vec<Point> = (0..20_000)
+            .map(|i| {
+                let a = i as f64 * 0.01;
+                let r = 0.00001 * i as f64;
+                pt(48.0 + r * a.sin(), 16.0 + r * a.cos())
+            })
+            .collect();
+        let kept = rdp_indices(&points, 0.0);
+        assert_eq!(kept.first(), Some(&0));
+        assert_eq!(kept.last(), Some(&19_999));
+        assert!(kept.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn indexed_route_maps_back_to_track() {
+        let track = Track {
+            name: None,
+            points: vec![
+                pt(48.0, 16.0),
+                pt(48.0, 16.01),
+                pt(48.0, 16.02),
+                pt(48.01, 16.02),
+                pt(48.02, 16.02),
+            ],
+        };
+        let simplified = track_to_route_indexed(&track, Simplify::Rdp { tolerance_m: 10.0 });
+        assert_eq!(simplified.track_indices, vec![0, 2, 4]);
+        for (p, &i) in simplified.route.points.iter().zip(&simplified.track_indices) {
+            assert_eq!((p.lat, p.lon), (track.points[i].lat, track.points[i].lon));
+        }
+        let json = serde_json::to_value(&simplified).unwrap();
+        assert_eq!(json["track_indices"], serde_json::json!([0, 2, 4]));
+        assert_eq!(json["points"].as_array().unwrap().len(), 3);
+    }
+
+    /// A straight 1 km approach, a hairpin whose apex lies between
+    /// densely recorded points, and a straight 1 km exit.
+    fn hairpin() -> Vec<Point> {
+        let mut points: Vec<Point> =
+            (0..=100).map(|i| pt(48.0, 16.0 + i as f64 * 0.000_135)).collect();
+        // Tight 180 degree bend of ~15 m radius to the north
+        let (clat, clon) = (48.000_135, 16.0135);
+        for k in 1..12 {
+            let a = (-90.0 + k as f64 * 15.0f64).to_radians();
+            points.push(pt(clat + 0.000_135 * a.sin(), clon + 0.0002 * a.cos()));
+        }
+        points.extend((0..=100).map(|i| pt(48.000_27, 16.0135 - i as f64 * 0.000_135)));
+        points
+    }
+
+    #[test]
+    fn nav_simplify_keeps_turn_apex() {
+        let points = hairpin();
+        let options = NavSimplifyOptions { tolerance_m: 50.0, ..NavSimplifyOptions::default() };
+        let kept = nav_simplify_indices(&points, &[], &[], &options);
+        // The apex (furthest east) point of the bend must survive
+        let apex = (0..points.len())
+            .max_by(|&a, &b| points[a].lon.total_cmp(&points[b].lon))
+            .unwrap();
+        assert!(kept.iter().any(|&i| i.abs_diff(apex) <= 1), "kept {kept:?}, apex {apex}");
+        assert!(kept.len() < 20);
+    }
+
+    #[test]
+    fn nav_simplify_pins_waypoints_and_segments() {
+        let points: Vec<Point> = (0..=100).map(|i| pt(48.0, 16.0 + i as f64 * 0.0001)).collect();
+        let shelter = pt(48.000_05, 16.0042);
+        let far = pt(48.01, 16.005);
+        let options = NavSimplifyOptions::default();
+        let kept = nav_simplify_indices(&points, &[shelter, far], &[70], &options);
+        assert_eq!(kept, vec![0, 42, 69, 70, 100]);
+    }
+
+    #[test]
+    fn nav_simplify_ignores_gps_jitter() {
+        // Straight line with 1 m sideways zigzag
+        let points: Vec<Point> = (0..=200)
+            .map(|i| {
+                let offset = if i % 2 == 0 { 0.0 } else { 0.000_009 };
+                pt(48.0 + offset, 16.0 + i as f64 * 0.000_05)
+            })
+            .collect();
+        let kept = nav_simplify_indices(&points, &[], &[], &NavSimplifyOptions::default());
+        assert_eq!(kept, vec![0, 200]);
+    }
+
+    #[test]
+    fn nav_simplify_keeps_both_corners_of_a_jog() {
+        // East in 10 m steps, 15 m north in 5 m steps at point 40, east again
+        let (m_lat, m_lon) = (1.0 / 111_200.0, 1.0 / 74_400.0);
+        let points: Vec<Point> = (0..=83)
+            .map(|i| {
+                let (x, y) = match i {
+                    0..=40 => (10.0 * i as f64, 0.0),
+                    41..=43 => (400.0, 5.0 * (i - 40) as f64),
+                    _ => (400.0 + 10.0 * (i - 43) as f64, 15.0),
+                };
+                pt(48.0 + y * m_lat, 16.0 + x * m_lon)
+            })
+            .collect();
+        let options = NavSimplifyOptions { tolerance_m: 20.0, ..NavSimplifyOptions::default() };
+        let kept = nav_simplify_indices(&points, &[], &[], &options);
+        assert_eq!(kept, vec![0, 40, 43, 83]);
+    }
+
+    #[test]
+    fn nav_simplify_json_round_trip() {
+        let track = Track { name: Some("Pass".into()), points: hairpin() };
+        let json = serde_json::to_string(&track).unwrap();
+        let out = nav_simplify_json(&json, "[]", "[]", 20.0, 30.0).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(v["name"], "Pass");
+        assert_eq!(
+            v["points"].as_array().unwrap().len(),
+            v["track_indices"].as_array().unwrap().len()
+        );
+        assert!(nav_simplify_json(&json, "{}", "[]", 20.0, 30.0).is_err());
+    }
+
+    #[test]
+    fn rdp_across_antimeridian() {
+        // Straight line over the antimeridian with a 1 km detour; the
+        // points beside the apex are ~500 m off the sub-chords
+        let points = vec![
+            pt(0.0, 179.9),
+            pt(0.0, 179.95),
+            pt(0.009, 180.0),
+            pt(0.0, -179.95),
+            pt(0.0, -179.9),
+        ];
+        assert_eq!(rdp_indices(&points, 400.0), vec![0, 1, 2, 3, 4]);
+        assert_eq!(rdp_indices(&points, 600.0), vec![0, 2, 4]);
+        assert_eq!(rdp_indices(&points, 2000.0), vec![0, 4]);
+    }
+
+    #[test]
+    fn rdp_long_chord_uses_ellipsoid() {
+        // On a 1100 km chord along 60°N the geodesic bulges ~40 km north
+        // of the parallel: a point on the great circle is on the chord,
+        // one on the parallel far off it. A flat metric sees it the
+        // other way round.
+        let (a, b) = (pt(60.0, 0.0), pt(60.0, 20.0));
+        let on_circle = interpolate(&a, &b, 0.5);
+        assert!(on_circle.lat > 60.3);
+        let points = vec![a.clone(), on_circle.clone(), b.clone()];
+        assert_eq!(rdp_indices(&points, 1000.0), vec![0, 2]);
+        let points = vec![a.clone(), pt(60.0, 10.0), b.clone()];
+        assert_eq!(rdp_indices(&points, 1000.0), vec![0, 1, 2]);
+
+        // The farthest point is chosen on the ellipsoid too
+        let points = vec![a.clone(), interpolate(&a, &b, 0.3), pt(60.0, 14.0), b];
+        assert_eq!(rdp_indices(&points, 1000.0)[..3], [0, 1, 2]);
+    }
+
+    fn timed(lat: f64, lon: f64, time: f64, ele: f64) -> Point {
+        Point { time: Some(time), ele: Some(ele), ..pt(lat, lon) }
+    }
+
+    #[test]
+    fn resample_by_distance_spacing() {
+        // ~1112 m along the equator, recorded unevenly
+        let points = vec![pt(0.0, 0.0), pt(0.0, 0.001), pt(0.0, 0.008), pt(0.0, 0.01)];
+        let resampled = resample_by_distance(&points, 100.0).unwrap();
+        assert_eq!(resampled.len(), 13);
+        for w in resampled[..12].windows(2) {
+            assert!((haversine(&w[0], &w[1]) - 100.0).abs() < 1e-6);
+        }
+        assert_eq!(resampled[12].lon, 0.01);
+        // Coarser than the track keeps just the endpoints
+        assert_eq!(resample_by_distance(&points, 5000.0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resample_interpolates_elevation_and_time() {
+        let points = vec![timed(0.0, 0.0, 0.0, 100.0), timed(0.0, 0.01, 100.0, 200.0)];
+        let quarter = haversine(&points[0], &points[1]) / 4.0;
+        let resampled = resample_by_distance(&points, quarter).unwrap();
+        assert_eq!(resampled.len(), 5);
+        assert!((resampled[2].ele.unwrap() - 150.0).abs() < 1e-3);
+        assert!((resampled[2].time.unwrap() - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn resample_by_time_steps() {
+        let points = vec![
+            timed(48.0, 16.0, 0.0, 100.0),
+            pt(48.0, 16.0005),
+            timed(48.0, 16.001, 1.0, 101.0),
+            timed(48.0, 16.011, 11.0, 111.0),
+        ];
+        let resampled = resample_by_time(&points, 5.0).unwrap();
+        let times: Vec<f64> = resampled.iter().map(|p| p.time.unwrap()).collect();
+        assert_eq!(times, vec![0.0, 5.0, 10.0, 11.0]);
+        assert!((resampled[1].lon - 16.005).abs() < 1e-6);
+        assert!((resampled[2].ele.unwrap() - 110.0).abs() < 1e-9);
+
+        assert!(resample_by_time(&[pt(48.0, 16.0), pt(48.0, 16.1)], 5.0).is_err());
+        let backwards = vec![timed(48.0, 16.0, 10.0, 0.0), timed(48.0, 16.1, 0.0, 0.0)];
+        assert!(resample_by_time(&backwards, 5.0).is_err());
+    }
+
+    #[test]
+    fn densify_follows_great_circle() {
+        // Along the 60th parallel's endpoints the great circle bulges north
+        let points = vec![pt(60.0, 0.0), pt(60.0, 90.0)];
+        let dense = densify(&points, 100_000.0).unwrap();
+        assert!(dense.len() > 40);
+        for w in dense.windows(2) {
+            assert!(haversine(&w[0], &w[1]) <= 100_000.0 + 1e-6);
+        }
+        let mid = &dense[dense.len() / 2];
+        assert!(mid.lat > 65.0, "mid lat {}", mid.lat);
+        assert_eq!(dense[0].lon, 0.0);
+        assert_eq!(dense[dense.len() - 1].lon, 90.0);
+        // Short segments stay as they are
+        assert_eq!(densify(&points, 1e8).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resample_json_modes() {
+        let json = serde_json::to_string(&vec![pt(0.0, 0.0), pt(0.0, 0.01)]).unwrap();
+        let out: Vec<Point> =
+            serde_json::from_str(&resample_json(&json, "densify", 500.0).unwrap()).unwrap();
+        assert_eq!(out.len(), 4);
+        assert!(resample_json(&json, "distance", 0.0).is_err());
+        assert!(resample_json(&json, "time", 1.0).is_err());
+        assert!(resample_json(&json, "cubic", 1.0).is_err());
+    }
+
+    #[test]
+    fn resampling_output_is_bounded() {
+        // 100 km track
+        let points = vec![pt(48.0, 16.0), pt(48.9, 16.0)];
+        assert!(densify(&points, 1e-6).is_err());
+        assert!(resample_by_distance(&points, 1e-6).is_err());
+        let json = serde_json::to_string(&points).unwrap();
+        let err = resample_json(&json, "densify", 1e-6).unwrap_err();
+        assert!(err.contains("more than 1000000"), "{err}");
+
+        let timed = vec![
+            Point { time: Some(0.0), ..pt(48.0, 16.0) },
+            Point { time: Some(86_400.0), ..pt(48.9, 16.0) },
+        ];
+        assert!(resample_by_time(&timed, 0.001).is_err());
+        assert_eq!(resample_by_time(&timed, 60.0).unwrap().len(), 1441);
     }
 }
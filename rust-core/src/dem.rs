@@ -0,0 +1,335 @@
+//! Elevation lookup from SRTM HGT digital elevation model tiles.
+//!
+//! Reads the 1x1 degree `.hgt` tiles that `MapDownloadManager` stores
+//! under `files/dem/<band>/` (e.g. `dem/N47/N47E011.hgt`), or directly
+//! in the DEM directory. A tile is a square grid of big-endian `i16`
+//! heights in meters, rows from north to south, 1201 samples wide for
+//! 3 arc-second data and 3601 for 1 arc-second data. Tiles are loaded
+//! lazily on first use and kept in memory.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::gpx::{in_range, GpxData, Point};
+
+/// Height value marking a missing sample.
+const VOID: i16 = -32768;
+
+/// Loaded tiles kept in memory before the cache is emptied. A 1
+/// arc-second tile takes 26 MB, so this bounds memory use to what a
+/// long multi-day tour needs at once.
+const MAX_CACHED_TILES: usize = 16;
+
+/// One 1x1 degree HGT tile.
+pub struct HgtTile {
+    /// Latitude of the southern edge.
+    lat: i32,
+    /// Longitude of the western edge.
+    lon: i32,
+    /// Samples per row and column.
+    size: usize,
+    heights: Vec<i16>,
+}
+
+impl HgtTile {
+    /// Read a tile whose south-west corner is at `lat`/`lon`.
+    ///
+    /// The grid size is derived from the data length, which must be
+    /// a square of at least 2x2 samples.
+    pub fn from_bytes(lat: i32, lon: i32, bytes: &[u8]) -> Result<Self, String> {
+        let samples = bytes.len() / 2;
+        let size = (samples as f64).sqrt().round() as usize;
+        if !bytes.len().is_multiple_of(2) || size < 2 || size * size != samples {
+            return Err(format!(
+                "HGT tile {} has invalid size {} bytes",
+                tile_name(lat, lon),
+                bytes.len()
+            ));
+        }
+        let heights = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        Ok(Self {
+            lat,
+            lon,
+            size,
+            heights,
+        })
+    }
+
+    /// Load a tile from disk.
+    pub fn load(path: &Path, lat: i32, lon: i32) -> Result<Self, String> {
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        Self::from_bytes(lat, lon, &bytes)
+    }
+
+    fn sample(&self, row: usize, col: usize) -> Option<f64> {
+        let h = self.heights[row * self.size + col];
+        (h != VOID).then_some(f64::from(h))
+    }
+
+    /// Bilinear-interpolated elevation at a position inside the tile.
+    ///
+    /// Void samples are left out and the remaining weights renormalized,
+    /// so a point next to a data hole still gets a height. Returns
+    /// `None` outside the tile or when all four neighbors are void.
+    pub fn elevation(&self, lat: f64, lon: f64) -> Option<f64> {
+        let cells = (self.size - 1) as f64;
+        // Row 0 is the northern edge
+        let y = (f64::from(self.lat + 1) - lat) * cells;
+        let x = (lon - f64::from(self.lon)) * cells;
+        if !(0.0..=cells).contains(&y) || !(0.0..=cells).contains(&x) {
+            return None;
+        }
+
+        let row = (y.floor() as usize).min(self.size - 2);
+        let col = (x.floor() as usize).min(self.size - 2);
+        let fy = y - row as f64;
+        let fx = x - col as f64;
+
+        let corners = [
+            (self.sample(row, col), (1.0 - fx) * (1.0 - fy)),
+            (self.sample(row, col + 1), fx * (1.0 - fy)),
+            (self.sample(row + 1, col), (1.0 - fx) * fy),
+            (self.sample(row + 1, col + 1), fx * fy),
+        ];
+        let (sum, weight) = corners
+            .iter()
+            .filter_map(|&(h, w)| h.map(|h| (h * w, w)))
+            .fold((0.0, 0.0), |(s, tw), (hw, w)| (s + hw, tw + w));
+
+        if weight > 1e-9 {
+            Some(sum / weight)
+        } else {
+            // The point sits on a void sample; fall back to any valid corner
+            corners.iter().find_map(|&(h, _)| h)
+        }
+    }
+}
+
+/// Tile name for a south-west corner, e.g. `N47E011` or `S34W071`.
+pub fn tile_name(lat: i32, lon: i32) -> String {
+    let ns = if lat >= 0 { 'N' } else { 'S' };
+    let ew = if lon >= 0 { 'E' } else { 'W' };
+    format!("{ns}{:02}{ew}{:03}", lat.unsigned_abs(), lon.unsigned_abs())
+}
+
+/// How [`Dem::apply`] treats points that already have an elevation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElevationMode {
+    /// Only set missing elevations.
+    Fill,
+    /// Replace every elevation the DEM covers, e.g. to correct noisy
+    /// barometric or GPS altitudes.
+    Replace,
+}
+
+/// Lazily loaded set of HGT tiles from a DEM directory.
+pub struct Dem {
+    dir: PathBuf,
+    /// Loaded tiles by south-west corner; `None` records a tile that is
+    /// missing or unreadable so it is not looked up again.
+    tiles: HashMap<(i32, i32), Option<HgtTile>>,
+}
+
+impl Dem {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            tiles: HashMap::new(),
+        }
+    }
+
+    fn tile(&mut self, lat: i32, lon: i32) -> Option<&HgtTile> {
+        if !self.tiles.contains_key(&(lat, lon)) {
+            if self.tiles.len() >= MAX_CACHED_TILES {
+                self.tiles.clear();
+            }
+            let tile = self.find_tile(lat, lon);
+            self.tiles.insert((lat, lon), tile);
+        }
+        self.tiles.get(&(lat, lon))?.as_ref()
+    }
+
+    fn find_tile(&self, lat: i32, lon: i32) -> Option<HgtTile> {
+        let name = tile_name(lat, lon);
+        let band = &name[..3];
+        [
+            self.dir.join(band).join(format!("{name}.hgt")),
+            self.dir.join(format!("{name}.hgt")),
+        ]
+        .iter()
+        .find(|path| path.is_file())
+        .and_then(|path| HgtTile::load(path, lat, lon).ok())
+    }
+
+    /// Elevation in meters at a position, or `None` without DEM data.
+    pub fn elevation(&mut self, lat: f64, lon: f64) -> Option<f64> {
+        if !in_range(lat, lon) {
+            return None;
+        }
+        let tile = self.tile(lat.floor() as i32, lon.floor() as i32)?;
+        tile.elevation(lat, lon)
+    }
+
+    /// Set point elevations from the DEM. Returns how many changed.
+    pub fn apply_to_points(&mut self, points: &mut [Point], mode: ElevationMode) -> usize {
+        let mut updated = 0;
+        for p in points {
+            if mode == ElevationMode::Fill && p.ele.is_some() {
+                continue;
+            }
+            if let Some(ele) = self.elevation(p.lat, p.lon) {
+                p.ele = Some(ele);
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// Set elevations on all tracks, routes and waypoints.
+    /// Returns how many points changed.
+    pub fn apply(&mut self, data: &mut GpxData, mode: ElevationMode) -> usize {
+        let mut updated = 0;
+        for track in &mut data.tracks {
+            updated += self.apply_to_points(&mut track.points, mode);
+        }
+        for route in &mut data.routes {
+            updated += self.apply_to_points(&mut route.points, mode);
+        }
+        for wp in &mut data.waypoints {
+            updated += self.apply_to_points(std::slice::from_mut(&mut wp.point), mode);
+        }
+        updated
+    }
+}
+
+/// GPX data with DEM elevations applied.
+#[derive(Debug, Serialize)]
+pub struct DemResult {
+    #[serde(flatten)]
+    pub data: GpxData,
+    /// Number of points whose elevation was set.
+    pub updated_points: usize,
+}
+
+/// Apply DEM elevations to GPX data given as JSON.
+pub fn apply_json(gpx_json: &str, dem_dir: &str, replace: bool) -> Result<String, String> {
+    let mut data: GpxData = serde_json::from_str(gpx_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    let mode = if replace {
+        ElevationMode::Replace
+    } else {
+        ElevationMode::Fill
+    };
+    let updated_points = Dem::new(dem_dir).apply(&mut data, mode);
+    let result = DemResult {
+        data,
+        updated_points,
+    };
+    serde_json::to_string(&result).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpx::Track;
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    /// 3x3 tile: heights rise by 100 m per column and 10 m per row
+    /// towards the south.
+    fn tile_bytes(void_center: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for row in 0..3i16 {
+            for col in 0..3i16 {
+                let h = if void_center && row == 1 && col == 1 {
+                    VOID
+                } else {
+                    1000 + col * 100 + row * 10
+                };
+                bytes.extend_from_slice(&h.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn tile_names() {
+        assert_eq!(tile_name(47, 11), "N47E011");
+        assert_eq!(tile_name(-34, -71), "S34W071");
+        assert_eq!(tile_name(0, -1), "N00W001");
+    }
+
+    #[test]
+    fn rejects_non_square_data() {
+        assert!(HgtTile::from_bytes(47, 11, &[0; 10]).is_err());
+        assert!(HgtTile::from_bytes(47, 11, &[0; 2]).is_err());
+    }
+
+    #[test]
+    fn exact_samples_and_corners() {
+        let tile = HgtTile::from_bytes(47, 11, &tile_bytes(false)).unwrap();
+        // North-west corner is row 0, col 0
+        assert_eq!(tile.elevation(48.0, 11.0), Some(1000.0));
+        // South-east corner is row 2, col 2
+        assert_eq!(tile.elevation(47.0, 12.0), Some(1220.0));
+        assert_eq!(tile.elevation(47.5, 11.5), Some(1110.0));
+        assert_eq!(tile.elevation(46.9, 11.5), None);
+    }
+
+    #[test]
+    fn bilinear_interpolation() {
+        let tile = HgtTile::from_bytes(47, 11, &tile_bytes(false)).unwrap();
+        // A quarter cell east and south of the north-west corner
+        let ele = tile.elevation(48.0 - 0.125, 11.125).unwrap();
+        assert!((ele - 1027.5).abs() < 1e-9, "got {ele}");
+    }
+
+    #[test]
+    fn void_samples_are_skipped() {
+        let tile = HgtTile::from_bytes(47, 11, &tile_bytes(true)).unwrap();
+        // Exactly on the void sample: a valid neighbor is used instead
+        assert!(tile.elevation(47.5, 11.5).is_some());
+        // Halfway between valid and void samples: only valid ones count
+        let ele = tile.elevation(47.75, 11.5).unwrap();
+        assert!((1000.0..=1220.0).contains(&ele));
+    }
+
+    #[test]
+    fn dem_reads_tiles_from_band_directories() {
+        let dir = std::env::temp_dir().join(format!("ndkarte-dem-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("N47")).unwrap();
+        std::fs::write(dir.join("N47").join("N47E011.hgt"), tile_bytes(false)).unwrap();
+        std::fs::write(dir.join("N46E011.hgt"), tile_bytes(false)).unwrap();
+
+        let mut dem = Dem::new(&dir);
+        assert_eq!(dem.elevation(47.5, 11.5), Some(1110.0));
+        assert_eq!(dem.elevation(46.5, 11.5), Some(1110.0));
+        assert_eq!(dem.elevation(10.0, 10.0), None);
+
+        let mut data = GpxData {
+            tracks: vec![Track {
+                name: None,
+                points: vec![Point { ele: Some(5.0), ..pt(47.5, 11.5) }, pt(47.5, 11.0)],
+            }],
+            ..GpxData::default()
+        };
+        assert_eq!(dem.apply(&mut data, ElevationMode::Fill), 1);
+        assert_eq!(data.tracks[0].points[0].ele, Some(5.0));
+        assert_eq!(data.tracks[0].points[1].ele, Some(1010.0));
+
+        assert_eq!(dem.apply(&mut data, ElevationMode::Replace), 2);
+        assert_eq!(data.tracks[0].points[0].ele, Some(1110.0));
+
+        let json = serde_json::to_string(&data).unwrap();
+        let result = apply_json(&json, dir.to_str().unwrap(), true).unwrap();
+        assert!(result.contains("\"updated_points\":2"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,294 @@
+//! Planned-vs-ridden deviation report.
+//!
+//! Finds where a recorded track left the planned route, how far it
+//! strayed and for how long, from the divergent stretches of
+//! [`overlap`]. The report can be exported as JSON or as a
+//! GPX file holding the plan as a route and each deviation as a track.
+
+use serde::Serialize;
+
+use crate::gpx::{write_gpx, GpxData, GpxVersion, Point, Route, Track};
+use crate::profile::cumulative_distances;
+use crate::similarity::{overlap, Stretch};
+
+/// A stretch of the ride away from the plan.
+#[derive(Debug, Clone, Serialize)]
+pub struct Deviation {
+    /// Index of the last point on the plan before leaving it, in the ride.
+    pub start_index: usize,
+    /// Index of the first point back on the plan, or the ride's last point.
+    pub end_index: usize,
+    /// Distance along the ride where the deviation starts, in meters.
+    pub start_m: f64,
+    /// Distance along the ride where the deviation ends, in meters.
+    pub end_m: f64,
+    /// Distance along the plan where the ride left it, in meters. Absent
+    /// when the ride started off the plan.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left_plan_m: Option<f64>,
+    /// Distance along the plan where the ride came back, in meters.
+    /// Absent when the ride never returned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejoined_plan_m: Option<f64>,
+    /// Farthest the ride got from the plan, in meters.
+    pub max_distance_m: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<f64>,
+    /// Time spent off the plan in seconds, when the ride has timestamps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_s: Option<f64>,
+}
+
+impl Deviation {
+    /// Length of the ride off the plan in meters.
+    pub fn length_m(&self) -> f64 {
+        self.end_m - self.start_m
+    }
+}
+
+/// Result of [`deviations`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviationReport {
+    pub deviations: Vec<Deviation>,
+    /// Total ride length off the plan in meters.
+    pub deviated_m: f64,
+    /// Total time off the plan in seconds, over deviations with timestamps.
+    pub deviated_s: f64,
+    /// Share of the plan's length the ride followed (0..1).
+    pub plan_covered_fraction: f64,
+}
+
+/// Compare a ride with its plan.
+///
+/// Ride points more than `tolerance_m` from the plan are off it;
+/// deviations shorter than `min_length_m` along the ride are treated as
+/// GPS noise and left out.
+pub fn deviations(
+    plan: &[Point],
+    ride: &[Point],
+    tolerance_m: f64,
+    min_length_m: f64,
+) -> DeviationReport {
+    let result = overlap(ride, plan, tolerance_m);
+    let along = cumulative_distances(ride);
+    let stretches = &result.stretches;
+    let deviations: Vec<Deviation> = stretches
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !s.shared)
+        .map(|(k, s)| {
+            let before = k.checked_sub(1).map(|k| &stretches[k]);
+            let after = stretches.get(k + 1);
+            deviation(ride, &along, &result.other_along_m, s, before, after)
+        })
+        .filter(|d| d.length_m() >= min_length_m)
+        .collect();
+
+    DeviationReport {
+        deviated_m: deviations.iter().map(Deviation::length_m).sum(),
+        deviated_s: deviations.iter().filter_map(|d| d.duration_s).sum(),
+        plan_covered_fraction: result.covered_fraction,
+        deviations,
+    }
+}
+
+/// Build a deviation from a divergent stretch and the shared stretches
+/// around it.
+///
+/// A divergent stretch starts at its first point off the plan; the
+/// deviation starts one point earlier, at the last point on the plan,
+/// so that it spans the whole detour. The plan positions are those of
+/// the ride's points at either end, not of the whole shared stretches,
+/// which may run back and forth along the plan.
+fn deviation(
+    ride: &[Point],
+    along: &[f64],
+    plan_along: &[Option<f64>],
+    off: &Stretch,
+    before: Option<&Stretch>,
+    after: Option<&Stretch>,
+) -> Deviation {
+    let start_index = if before.is_some() { off.start_index - 1 } else { off.start_index };
+    let start_time = ride[start_index].time;
+    let end_time = ride[off.end_index].time;
+    Deviation {
+        start_index,
+        end_index: off.end_index,
+        start_m: along[start_index],
+        end_m: off.end_m,
+        left_plan_m: before.and_then(|_| plan_along[start_index]),
+        rejoined_plan_m: after.and_then(|_| plan_along[off.end_index]),
+        max_distance_m: off.max_distance_m,
+        start_time,
+        end_time,
+        duration_s: start_time.zip(end_time).map(|(a, b)| b - a),
+    }
+}
+
+/// Write the plan as a route and every deviation as a track named
+/// "Deviation N", as GPX 1.1.
+pub fn deviations_gpx(plan: &[Point], ride: &[Point], report: &DeviationReport) -> String {
+    let data = GpxData {
+        routes: vec![Route {
+            name: Some("Plan".into()),
+            points: plan.to_vec(),
+        }],
+        tracks: report
+            .deviations
+            .iter()
+            .enumerate()
+            .map(|(i, d)| Track {
+                name: Some(format!("Deviation {} ({:.0} m)", i + 1, d.length_m())),
+                points: ride[d.start_index..=d.end_index].to_vec(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    write_gpx(&data, GpxVersion::Gpx11)
+}
+
+fn parse_points(json: &str) -> Result<Vec<Point>, String> {
+    let track: Track = serde_json::from_str(json)
+        .map_err(|e| format!("Track JSON parse failed: {e}"))?;
+    Ok(track.points)
+}
+
+fn check_tolerance(tolerance_m: f64) -> Result<(), String> {
+    if tolerance_m.is_finite() && tolerance_m > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("Tolerance must be positive, got {tolerance_m}"))
+    }
+}
+
+/// Deviation report for a plan and a ride given as JSON route or track
+/// objects; returns a JSON `DeviationReport`.
+pub fn deviations_json(
+    plan_json: &str,
+    ride_json: &str,
+    tolerance_m: f64,
+    min_length_m: f64,
+) -> Result<String, String> {
+    check_tolerance(tolerance_m)?;
+    let (plan, ride) = (parse_points(plan_json)?, parse_points(ride_json)?);
+    let report = deviations(&plan, &ride, tolerance_m, min_length_m);
+    serde_json::to_string(&report).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// Same as [`deviations_json`], returned as a GPX document.
+pub fn deviations_gpx_json(
+    plan_json: &str,
+    ride_json: &str,
+    tolerance_m: f64,
+    min_length_m: f64,
+) -> Result<String, String> {
+    check_tolerance(tolerance_m)?;
+    let (plan, ride) = (parse_points(plan_json)?, parse_points(ride_json)?);
+    let report = deviations(&plan, &ride, tolerance_m, min_length_m);
+    Ok(deviations_gpx(&plan, &ride, &report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nav::{haversine, track_length};
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    /// Plan: 0.03° east at 48°N in two legs. Ride: 31 points along it
+    /// one minute apart, with a 500 m detour north over points 5..=8
+    /// and a 45 m wobble at point 20.
+    fn plan_and_ride() -> (Vec<Point>, Vec<Point>) {
+        let plan = vec![pt(48.0, 16.0), pt(48.0, 16.015), pt(48.0, 16.03)];
+        let ride = (0..=30)
+            .map(|i| {
+                let lat = match i {
+                    5..=8 => 48.0045,
+                    20 => 48.0004,
+                    _ => 48.0,
+                };
+                Point { time: Some(i as f64 * 60.0), ..pt(lat, 16.0 + 0.001 * i as f64) }
+            })
+            .collect();
+        (plan, ride)
+    }
+
+    #[test]
+    fn finds_detour_and_ignores_short_wobbles() {
+        let (plan, ride) = plan_and_ride();
+        let report = deviations(&plan, &ride, 25.0, 200.0);
+        assert_eq!(report.deviations.len(), 1);
+        let d = &report.deviations[0];
+        assert_eq!((d.start_index, d.end_index), (4, 9));
+        assert!((d.left_plan_m.unwrap() - haversine(&plan[0], &ride[4])).abs() < 1.0);
+        assert!((d.rejoined_plan_m.unwrap() - haversine(&plan[0], &ride[9])).abs() < 1.0);
+        assert!(d.max_distance_m > 490.0 && d.max_distance_m < 510.0);
+        assert_eq!(d.duration_s, Some(300.0));
+        assert!((d.length_m() - track_length(&ride[4..=9])).abs() < 1e-6);
+        assert_eq!(report.deviated_s, 300.0);
+
+        // Without the minimum length the wobble shows up too
+        let all = deviations(&plan, &ride, 25.0, 0.0);
+        assert_eq!(all.deviations.len(), 2);
+        assert_eq!((all.deviations[1].start_index, all.deviations[1].end_index), (19, 21));
+    }
+
+    #[test]
+    fn out_and_back_ride_takes_plan_positions_at_the_detour() {
+        // Out to the plan's end and back to the start, with a detour
+        // north over points 51..=54 on the way back
+        let (plan, _) = plan_and_ride();
+        let ride: Vec<Point> = (0..=60)
+            .map(|i| {
+                let lat = if (51..=54).contains(&i) { 48.0045 } else { 48.0 };
+                let steps = if i <= 30 { i } else { 60 - i };
+                pt(lat, 16.0 + 0.001 * steps as f64)
+            })
+            .collect();
+        let report = deviations(&plan, &ride, 25.0, 200.0);
+        assert_eq!(report.deviations.len(), 1);
+        let d = &report.deviations[0];
+        assert_eq!((d.start_index, d.end_index), (50, 55));
+        assert!((d.left_plan_m.unwrap() - haversine(&plan[0], &ride[50])).abs() < 1.0);
+        assert!((d.rejoined_plan_m.unwrap() - haversine(&plan[0], &ride[55])).abs() < 1.0);
+    }
+
+    #[test]
+    fn ride_that_never_returns() {
+        let (plan, mut ride) = plan_and_ride();
+        for p in &mut ride[25..] {
+            p.lat = 48.01;
+        }
+        let report = deviations(&plan, &ride, 25.0, 200.0);
+        let last = report.deviations.last().unwrap();
+        assert_eq!(last.end_index, 30);
+        assert!(last.left_plan_m.is_some());
+        assert!(last.rejoined_plan_m.is_none());
+        assert!(report.plan_covered_fraction < 0.85);
+    }
+
+    #[test]
+    fn exports_json_and_gpx() {
+        let (plan, ride) = plan_and_ride();
+        let plan_json = serde_json::to_string(&Route { name: None, points: plan }).unwrap();
+        let ride_json = serde_json::to_string(&Track { name: None, points: ride }).unwrap();
+
+        let json = deviations_json(&plan_json, &ride_json, 25.0, 200.0).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(v["deviations"][0]["duration_s"], 300.0);
+
+        let gpx = deviations_gpx_json(&plan_json, &ride_json, 25.0, 200.0).unwrap();
+        let data = crate::gpx::parse_bytes(gpx.as_bytes()).unwrap();
+        assert_eq!(data.routes[0].points.len(), 3);
+        assert_eq!(data.tracks.len(), 1);
+        assert!(data.tracks[0].name.as_deref().unwrap().starts_with("Deviation 1 ("));
+        assert_eq!(data.tracks[0].points.len(), 6);
+
+        assert!(deviations_json(&plan_json, &ride_json, 0.0, 100.0).is_err());
+        assert!(deviations_gpx_json("[]", &ride_json, 25.0, 200.0).is_err());
+    }
+}
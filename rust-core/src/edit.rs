@@ -0,0 +1,401 @@
+//! Track editing: reverse, split, join, trim and crop.
+//!
+//! Every operation returns new tracks and leaves its input untouched.
+//! Track names and per-point data (elevation, time, speed, course) are
+//! carried over; points created at a cut are interpolated along the
+//! segment they fall on.
+
+use serde::{Deserialize, Serialize};
+
+use crate::convert::interpolate;
+use crate::gpx::{Bounds, Point, Track};
+use crate::nav::{haversine, project_on_track, track_length};
+use crate::profile::cumulative_distances;
+
+/// Where [`split`] cuts a track.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum SplitAt {
+    /// At an existing point, which ends the first part and starts the
+    /// second.
+    Index { index: usize },
+    /// At a distance along the track in meters.
+    Distance { distance_m: f64 },
+    /// At the track position nearest to a location, e.g. a tap on the map.
+    Nearest { lat: f64, lon: f64 },
+}
+
+/// A jump between joined tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Gap {
+    /// Index of the first point after the gap in the joined track.
+    pub index: usize,
+    /// Straight-line length of the gap in meters.
+    pub distance_m: f64,
+}
+
+/// Result of [`join`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Joined {
+    #[serde(flatten)]
+    pub track: Track,
+    /// Gaps longer than the threshold, in track order.
+    pub gaps: Vec<Gap>,
+}
+
+/// Consecutive tracks whose ends are closer than this are treated as
+/// sharing the join point, which is then kept only once.
+const SAME_POINT_M: f64 = 1.0;
+
+/// Reverse the direction of travel.
+///
+/// Courses are turned around. Timestamps stay with their points, so a
+/// reversed recording runs backwards in time.
+pub fn reverse(track: &Track) -> Track {
+    let points = track
+        .points
+        .iter()
+        .rev()
+        .map(|p| Point {
+            course: p.course.map(|c| (c + 180.0) % 360.0),
+            ..p.clone()
+        })
+        .collect();
+    Track {
+        name: track.name.clone(),
+        points,
+    }
+}
+
+/// Split a track in two. The cut point ends the first part and starts
+/// the second, so both parts stay connected.
+pub fn split(track: &Track, at: SplitAt) -> Result<(Track, Track), String> {
+    let points = &track.points;
+    if points.len() < 2 {
+        return Err("Track needs at least 2 points to split".into());
+    }
+    let (first, second) = match at {
+        SplitAt::Index { index } => {
+            if index == 0 || index >= points.len() - 1 {
+                return Err(format!("Split index {index} is not inside the track"));
+            }
+            (points[..=index].to_vec(), points[index..].to_vec())
+        }
+        SplitAt::Distance { distance_m } => {
+            let along = cumulative_distances(points);
+            let total = along[along.len() - 1];
+            if !(distance_m > 0.0 && distance_m < total) {
+                return Err(format!(
+                    "Split distance {distance_m} m is not inside the {total:.0} m track"
+                ));
+            }
+            (
+                slice_by_distance(points, &along, 0.0, distance_m),
+                slice_by_distance(points, &along, distance_m, total),
+            )
+        }
+        SplitAt::Nearest { lat, lon } => {
+            let target = Point {
+                lat,
+                lon,
+                ele: None,
+                time: None,
+                speed: None,
+                course: None,
+            };
+            let projection = project_on_track(&target, points)
+                .ok_or("Track needs at least 2 points to split")?;
+            let i = projection.segment_index;
+            let (a, b) = (&points[i], &points[i + 1]);
+            let seg = haversine(a, b);
+            let t = if seg > 0.0 {
+                haversine(a, &projection.point) / seg
+            } else {
+                0.0
+            };
+            let mut first = points[..=i].to_vec();
+            let mut second = points[i + 1..].to_vec();
+            if t >= 1.0 {
+                // The cut is on `b`, which already starts the second part
+                first.push(b.clone());
+            } else if t > 0.0 {
+                let cut = interpolate(a, b, t);
+                first.push(cut.clone());
+                second.insert(0, cut);
+            } else {
+                second.insert(0, a.clone());
+            }
+            // A position beyond either end projects onto it and leaves
+            // one part without length
+            if track_length(&first) <= 0.0 || track_length(&second) <= 0.0 {
+                return Err("Split position is at the end of the track".into());
+            }
+            (first, second)
+        }
+    };
+    Ok((
+        Track {
+            name: track.name.clone(),
+            points: first,
+        },
+        Track {
+            name: track.name.clone(),
+            points: second,
+        },
+    ))
+}
+
+/// Join tracks end to end, e.g. daily stages into one tour.
+///
+/// The joined track takes the first track's name. Where a track starts
+/// on the previous one's end point, the point is kept once; jumps longer
+/// than `max_gap_m` are reported.
+pub fn join(tracks: &[Track], max_gap_m: f64) -> Joined {
+    let mut points: Vec<Point> = Vec::new();
+    let mut gaps = Vec::new();
+    for track in tracks.iter().filter(|t| !t.points.is_empty()) {
+        let mut incoming = track.points.as_slice();
+        if let Some(last) = points.last() {
+            let gap = haversine(last, &incoming[0]);
+            if gap < SAME_POINT_M {
+                incoming = &incoming[1..];
+            } else if gap > max_gap_m {
+                gaps.push(Gap {
+                    index: points.len(),
+                    distance_m: gap,
+                });
+            }
+        }
+        points.extend_from_slice(incoming);
+    }
+    Joined {
+        track: Track {
+            name: tracks.iter().find_map(|t| t.name.clone()),
+            points,
+        },
+        gaps,
+    }
+}
+
+/// Cut `start_m` meters off the start and `end_m` meters off the end.
+pub fn trim(track: &Track, start_m: f64, end_m: f64) -> Result<Track, String> {
+    if track.points.len() < 2 {
+        return Err("Track needs at least 2 points to trim".into());
+    }
+    let along = cumulative_distances(&track.points);
+    let total = along[along.len() - 1];
+    let (from, to) = (start_m.max(0.0), total - end_m.max(0.0));
+    if from >= to {
+        return Err(format!("Trimming {start_m} m and {end_m} m leaves nothing of {total:.0} m"));
+    }
+    Ok(Track {
+        name: track.name.clone(),
+        points: slice_by_distance(&track.points, &along, from, to),
+    })
+}
+
+/// Keep only the points inside `bounds`.
+///
+/// A track that leaves and re-enters the box comes back as several
+/// tracks; stretches with a single point inside are dropped.
+pub fn crop(track: &Track, bounds: &Bounds) -> Vec<Track> {
+    track
+        .points
+        .split(|p| !bounds.contains(p))
+        .filter(|run| run.len() >= 2)
+        .map(|run| Track {
+            name: track.name.clone(),
+            points: run.to_vec(),
+        })
+        .collect()
+}
+
+/// Points between two distances along the track, with interpolated
+/// points at both cuts.
+fn slice_by_distance(points: &[Point], along: &[f64], from: f64, to: f64) -> Vec<Point> {
+    let mut out = vec![point_at(points, along, from)];
+    out.extend(
+        points
+            .iter()
+            .zip(along)
+            .filter(|&(_, &d)| d > from && d < to)
+            .map(|(p, _)| p.clone()),
+    );
+    out.push(point_at(points, along, to));
+    out
+}
+
+/// The point at distance `d` along the track: an existing point when
+/// `d` hits one exactly, otherwise an interpolated one.
+fn point_at(points: &[Point], along: &[f64], d: f64) -> Point {
+    if let Some(i) = along.iter().position(|&a| a == d) {
+        return points[i].clone();
+    }
+    let i = along.partition_point(|&a| a < d).clamp(1, points.len() - 1);
+    let (start, end) = (along[i - 1], along[i]);
+    let t = if end > start { (d - start) / (end - start) } else { 0.0 };
+    interpolate(&points[i - 1], &points[i], t)
+}
+
+fn parse_track(track_json: &str) -> Result<Track, String> {
+    serde_json::from_str(track_json).map_err(|e| format!("Track JSON parse failed: {e}"))
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| format!("JSON serialize failed: {e}"))
+}
+
+/// Reverse a track given as JSON.
+pub fn reverse_json(track_json: &str) -> Result<String, String> {
+    to_json(&reverse(&parse_track(track_json)?))
+}
+
+/// Split a track given as JSON. `at_json` is `{"index": n}`,
+/// `{"distance_m": d}` or `{"lat": .., "lon": ..}`; returns both parts
+/// as a JSON array.
+pub fn split_json(track_json: &str, at_json: &str) -> Result<String, String> {
+    let at: SplitAt = serde_json::from_str(at_json)
+        .map_err(|e| format!("Split position JSON parse failed: {e}"))?;
+    let (first, second) = split(&parse_track(track_json)?, at)?;
+    to_json(&[first, second])
+}
+
+/// Join a JSON array of tracks.
+pub fn join_json(tracks_json: &str, max_gap_m: f64) -> Result<String, String> {
+    let tracks: Vec<Track> = serde_json::from_str(tracks_json)
+        .map_err(|e| format!("Tracks JSON parse failed: {e}"))?;
+    to_json(&join(&tracks, max_gap_m))
+}
+
+/// Trim a track given as JSON.
+pub fn trim_json(track_json: &str, start_m: f64, end_m: f64) -> Result<String, String> {
+    to_json(&trim(&parse_track(track_json)?, start_m, end_m)?)
+}
+
+/// Crop a track given as JSON; returns a JSON array of tracks.
+pub fn crop_json(track_json: &str, bounds: &Bounds) -> Result<String, String> {
+    to_json(&crop(&parse_track(track_json)?, bounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    /// Five points 0.001 degrees (~111 m) apart going north, with
+    /// elevation, time and course.
+    fn north() -> Track {
+        Track {
+            name: Some("Stage".into()),
+            points: (0..5)
+                .map(|i| Point {
+                    ele: Some(100.0 + i as f64 * 10.0),
+                    time: Some(i as f64 * 60.0),
+                    course: Some(0.0),
+                    ..pt(48.0 + i as f64 * 0.001, 16.0)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn reverse_turns_points_and_courses() {
+        let reversed = reverse(&north());
+        assert_eq!(reversed.name.as_deref(), Some("Stage"));
+        assert_eq!(reversed.points[0].lat, 48.004);
+        assert_eq!(reversed.points[0].ele, Some(140.0));
+        assert_eq!(reversed.points[0].course, Some(180.0));
+        assert_eq!(reverse(&reversed).points[0].course, Some(0.0));
+    }
+
+    #[test]
+    fn split_at_index_and_distance() {
+        let (a, b) = split(&north(), SplitAt::Index { index: 2 }).unwrap();
+        assert_eq!((a.points.len(), b.points.len()), (3, 3));
+        assert_eq!(a.points[2].lat, b.points[0].lat);
+        assert_eq!(b.name.as_deref(), Some("Stage"));
+        assert!(split(&north(), SplitAt::Index { index: 4 }).is_err());
+
+        let half = haversine(&north().points[0], &north().points[1]) / 2.0;
+        let (a, b) = split(&north(), SplitAt::Distance { distance_m: half }).unwrap();
+        assert_eq!((a.points.len(), b.points.len()), (2, 5));
+        assert!((a.points[1].lat - 48.0005).abs() < 1e-9);
+        assert_eq!(a.points[1].time, Some(30.0));
+        assert!(split(&north(), SplitAt::Distance { distance_m: 1e6 }).is_err());
+    }
+
+    #[test]
+    fn split_at_nearest_position() {
+        // Beside the track, a third of the way from point 1 to point 2
+        let (a, b) = split(&north(), SplitAt::Nearest { lat: 48.001_333, lon: 16.0005 }).unwrap();
+        assert_eq!(a.points.len(), 3);
+        assert_eq!(b.points.len(), 4);
+        assert!((a.points[2].lat - 48.001_333).abs() < 1e-6);
+        assert!((a.points[2].lon - 16.0).abs() < 1e-9);
+        assert_eq!(a.points[2].lat, b.points[0].lat);
+        // At the very start there is nothing to split off
+        assert!(split(&north(), SplitAt::Nearest { lat: 47.9, lon: 16.0 }).is_err());
+        // Nor beyond the end
+        let err = split(&north(), SplitAt::Nearest { lat: 48.1, lon: 16.0 }).unwrap_err();
+        assert_eq!(err, "Split position is at the end of the track");
+    }
+
+    #[test]
+    fn split_at_nearest_point_does_not_duplicate_it() {
+        let (a, b) = split(&north(), SplitAt::Nearest { lat: 48.002, lon: 16.0003 }).unwrap();
+        assert_eq!((a.points.len(), b.points.len()), (3, 3));
+        assert_eq!(a.points[2].lat, 48.002);
+        assert_eq!(b.points[0].lat, 48.002);
+        assert_ne!(b.points[1].lat, 48.002);
+    }
+
+    #[test]
+    fn join_merges_shared_points_and_reports_gaps() {
+        let (a, b) = split(&north(), SplitAt::Index { index: 2 }).unwrap();
+        let far = Track { name: None, points: vec![pt(48.1, 16.0), pt(48.2, 16.0)] };
+        let joined = join(&[a, b, far], 500.0);
+        assert_eq!(joined.track.name.as_deref(), Some("Stage"));
+        assert_eq!(joined.track.points.len(), 7);
+        assert_eq!(joined.gaps.len(), 1);
+        assert_eq!(joined.gaps[0].index, 5);
+        assert!(joined.gaps[0].distance_m > 10_000.0);
+    }
+
+    #[test]
+    fn trim_cuts_both_ends() {
+        let step = haversine(&north().points[0], &north().points[1]);
+        let trimmed = trim(&north(), step, step / 2.0).unwrap();
+        assert_eq!(trimmed.points.len(), 4);
+        assert_eq!(trimmed.points[0].lat, 48.001);
+        assert!((trimmed.points[3].lat - 48.0035).abs() < 1e-9);
+        assert!(trim(&north(), 300.0, 300.0).is_err());
+    }
+
+    #[test]
+    fn crop_splits_on_leaving_the_box() {
+        let mut track = north();
+        track.points[2].lon = 17.0;
+        let bounds = Bounds { min_lat: 47.0, min_lon: 15.0, max_lat: 49.0, max_lon: 16.5 };
+        let pieces = crop(&track, &bounds);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].points.len(), 2);
+        assert_eq!(pieces[1].points[0].lat, 48.003);
+    }
+
+    #[test]
+    fn json_wrappers() {
+        let json = serde_json::to_string(&north()).unwrap();
+        let parts: Vec<Track> =
+            serde_json::from_str(&split_json(&json, r#"{"distance_m": 200}"#).unwrap()).unwrap();
+        assert_eq!(parts.len(), 2);
+        let joined = join_json(&serde_json::to_string(&parts).unwrap(), 50.0).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&joined).unwrap();
+        assert_eq!(v["points"].as_array().unwrap().len(), 6);
+        assert_eq!(v["gaps"].as_array().unwrap().len(), 0);
+        assert!(split_json(&json, r#"{"at": 1}"#).is_err());
+        assert!(reverse_json("[]").is_err());
+    }
+}
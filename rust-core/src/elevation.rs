@@ -0,0 +1,384 @@
+//! Elevation noise filtering for recorded tracks.
+//!
+//! GPS elevation jitters by several meters from fix to fix, and even
+//! barometric altimeters drift and step. Summing the raw positive
+//! deltas overstates ascent badly, and everything downstream (profiles,
+//! climb detection, `nav::project_on_track` interpolation) inherits the
+//! noise. This module cleans track elevations in place with a chain of
+//! filters, so the rest of the pipeline works on the cleaned values.
+//!
+//! Points without an elevation are skipped by every filter and keep
+//! `ele: None`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::dem::Dem;
+use crate::gpx::{GpxData, Point};
+use crate::nav::haversine;
+
+/// Largest accepted median window, in points.
+pub const MAX_MEDIAN_WINDOW: usize = 1001;
+
+/// One elevation filtering step.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ElevationFilter {
+    /// Replace each elevation with the median of a centered window of
+    /// this many points. Removes isolated spikes without flattening
+    /// real slopes.
+    Median { window: usize },
+    /// One-dimensional Kalman filter over distance. The variance grows by
+    /// `process_noise` (m² per meter travelled) between points and each
+    /// reading has a standard deviation of `measurement_noise_m`.
+    Kalman {
+        process_noise: f64,
+        measurement_noise_m: f64,
+    },
+    /// Keep the last accepted elevation until a reading differs from it
+    /// by at least `threshold_m`. Suppresses jitter that would otherwise
+    /// count as ascent.
+    Hysteresis { threshold_m: f64 },
+    /// Shift all elevations by the median difference to the DEM. Keeps
+    /// the recorded relative shape (precise on barometric devices) while
+    /// fixing the absolute offset (which barometers get wrong).
+    DemOffset,
+}
+
+impl ElevationFilter {
+    /// Reject parameters that make no sense or would not fit in memory,
+    /// e.g. a median window taken from bad JSON.
+    pub fn check(&self) -> Result<(), String> {
+        let ok = match *self {
+            ElevationFilter::Median { window } => (1..=MAX_MEDIAN_WINDOW).contains(&window),
+            ElevationFilter::Kalman {
+                process_noise,
+                measurement_noise_m,
+            } => {
+                process_noise.is_finite()
+                    && process_noise >= 0.0
+                    && measurement_noise_m.is_finite()
+                    && measurement_noise_m >= 0.0
+            }
+            ElevationFilter::Hysteresis { threshold_m } => {
+                threshold_m.is_finite() && threshold_m >= 0.0
+            }
+            ElevationFilter::DemOffset => true,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(format!("Invalid elevation filter: {self:?}"))
+        }
+    }
+
+    /// A chain that works well for phone GPS recordings.
+    pub fn recommended() -> Vec<ElevationFilter> {
+        vec![
+            ElevationFilter::Median { window: 5 },
+            ElevationFilter::Kalman {
+                process_noise: 0.05,
+                measurement_noise_m: 5.0,
+            },
+            ElevationFilter::Hysteresis { threshold_m: 3.0 },
+        ]
+    }
+}
+
+/// Run `filters` in order over the elevations of `points`.
+///
+/// `dem` is required by [`ElevationFilter::DemOffset`]; without one that
+/// step fails. A DEM without coverage for the track leaves it unchanged.
+pub fn filter_points(
+    points: &mut [Point],
+    filters: &[ElevationFilter],
+    mut dem: Option<&mut Dem>,
+) -> Result<(), String> {
+    for filter in filters {
+        filter.check()?;
+    }
+    for filter in filters {
+        match *filter {
+            ElevationFilter::Median { window } => median(points, window),
+            ElevationFilter::Kalman {
+                process_noise,
+                measurement_noise_m,
+            } => kalman(points, process_noise, measurement_noise_m),
+            ElevationFilter::Hysteresis { threshold_m } => hysteresis(points, threshold_m),
+            ElevationFilter::DemOffset => {
+                let dem = dem
+                    .as_deref_mut()
+                    .ok_or("DEM offset filter needs a DEM directory")?;
+                dem_offset(points, dem);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Filter the elevations of every track.
+pub fn filter_tracks(
+    data: &mut GpxData,
+    filters: &[ElevationFilter],
+    mut dem: Option<&mut Dem>,
+) -> Result<(), String> {
+    for track in &mut data.tracks {
+        filter_points(&mut track.points, filters, dem.as_deref_mut())?;
+    }
+    Ok(())
+}
+
+/// Total ascent and descent in meters, summed over consecutive points
+/// that both have an elevation.
+pub fn ascent_descent(points: &[Point]) -> (f64, f64) {
+    let mut ascent = 0.0;
+    let mut descent = 0.0;
+    let mut last = None;
+    for ele in points.iter().filter_map(|p| p.ele) {
+        if let Some(prev) = last {
+            let delta = ele - prev;
+            if delta > 0.0 {
+                ascent += delta;
+            } else {
+                descent -= delta;
+            }
+        }
+        last = Some(ele);
+    }
+    (ascent, descent)
+}
+
+/// Filtered GPX data with the ascent over all tracks before and after.
+#[derive(Debug, Serialize)]
+pub struct FilterResult {
+    #[serde(flatten)]
+    pub data: GpxData,
+    pub ascent_before_m: f64,
+    pub ascent_after_m: f64,
+}
+
+/// Filter track elevations in GPX JSON.
+///
+/// `filters_json` is an array like
+/// `[{"method": "median", "window": 5}, {"method": "hysteresis", "threshold_m": 3}]`;
+/// an empty string selects [`ElevationFilter::recommended`].
+pub fn filter_json(
+    gpx_json: &str,
+    filters_json: &str,
+    dem_dir: Option<&str>,
+) -> Result<String, String> {
+    let mut data: GpxData = serde_json::from_str(gpx_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    let filters = if filters_json.trim().is_empty() {
+        ElevationFilter::recommended()
+    } else {
+        serde_json::from_str(filters_json)
+            .map_err(|e| format!("Filter JSON parse failed: {e}"))?
+    };
+    let mut dem = dem_dir.map(Dem::new);
+
+    let total_ascent = |data: &GpxData| -> f64 {
+        data.tracks.iter().map(|t| ascent_descent(&t.points).0).sum()
+    };
+    let ascent_before_m = total_ascent(&data);
+    filter_tracks(&mut data, &filters, dem.as_mut())?;
+    let result = FilterResult {
+        ascent_after_m: total_ascent(&data),
+        data,
+        ascent_before_m,
+    };
+    serde_json::to_string(&result).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// Indices of the points that carry an elevation.
+fn with_elevation(points: &[Point]) -> Vec<usize> {
+    (0..points.len()).filter(|&i| points[i].ele.is_some()).collect()
+}
+
+fn median(points: &mut [Point], window: usize) {
+    let idx = with_elevation(points);
+    let half = (window / 2).min(idx.len());
+    if half == 0 || idx.len() < 3 {
+        return;
+    }
+    let values: Vec<f64> = idx.iter().map(|&i| points[i].ele.unwrap_or(0.0)).collect();
+    let mut buf = Vec::with_capacity(2 * half + 1);
+    for (k, &i) in idx.iter().enumerate() {
+        // Shrink the window symmetrically at the ends so the first and
+        // last readings are kept as they are.
+        let h = half.min(k).min(values.len() - 1 - k);
+        buf.clear();
+        buf.extend_from_slice(&values[k - h..=k + h]);
+        buf.sort_by(f64::total_cmp);
+        points[i].ele = Some(buf[h]);
+    }
+}
+
+fn kalman(points: &mut [Point], process_noise: f64, measurement_noise_m: f64) {
+    let idx = with_elevation(points);
+    let Some(&first) = idx.first() else {
+        return;
+    };
+    let r = measurement_noise_m * measurement_noise_m;
+    let mut estimate = points[first].ele.unwrap_or(0.0);
+    let mut variance = r;
+    for w in idx.windows(2) {
+        let (prev, i) = (w[0], w[1]);
+        variance += process_noise.max(0.0) * haversine(&points[prev], &points[i]);
+        let reading = points[i].ele.unwrap_or(estimate);
+        let gain = if variance + r > 0.0 {
+            variance / (variance + r)
+        } else {
+            1.0
+        };
+        estimate += gain * (reading - estimate);
+        variance *= 1.0 - gain;
+        points[i].ele = Some(estimate);
+    }
+}
+
+fn hysteresis(points: &mut [Point], threshold_m: f64) {
+    let mut accepted: Option<f64> = None;
+    for p in points.iter_mut() {
+        let Some(ele) = p.ele else {
+            continue;
+        };
+        match accepted {
+            Some(a) if (ele - a).abs() < threshold_m => p.ele = Some(a),
+            _ => accepted = Some(ele),
+        }
+    }
+}
+
+fn dem_offset(points: &mut [Point], dem: &mut Dem) {
+    let mut diffs: Vec<f64> = points
+        .iter()
+        .filter_map(|p| Some(p.ele? - dem.elevation(p.lat, p.lon)?))
+        .collect();
+    if diffs.is_empty() {
+        return;
+    }
+    diffs.sort_by(f64::total_cmp);
+    let offset = diffs[diffs.len() / 2];
+    for p in points.iter_mut() {
+        if let Some(ele) = p.ele.as_mut() {
+            *ele -= offset;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dem::tile_name;
+
+    fn pt(lat: f64, ele: Option<f64>) -> Point {
+        Point { lat, lon: 11.0, ele, time: None, speed: None, course: None }
+    }
+
+    /// A steady 100 m climb over ~1.1 km with ±4 m alternating noise.
+    fn noisy_climb() -> Vec<Point> {
+        (0..101)
+            .map(|i| {
+                let noise = if i % 2 == 0 { 4.0 } else { -4.0 };
+                pt(47.0 + i as f64 * 0.0001, Some(500.0 + i as f64 + noise))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ascent_descent_sums_deltas() {
+        let points = vec![pt(47.0, Some(100.0)), pt(47.1, None), pt(47.2, Some(130.0)),
+            pt(47.3, Some(110.0))];
+        assert_eq!(ascent_descent(&points), (30.0, 20.0));
+    }
+
+    #[test]
+    fn median_removes_spike() {
+        let mut points: Vec<Point> = [100.0, 101.0, 180.0, 103.0, 104.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &e)| pt(47.0 + i as f64 * 0.001, Some(e)))
+            .collect();
+        filter_points(&mut points, &[ElevationFilter::Median { window: 3 }], None).unwrap();
+        let eles: Vec<f64> = points.iter().map(|p| p.ele.unwrap()).collect();
+        assert_eq!(eles, vec![100.0, 101.0, 103.0, 104.0, 104.0]);
+    }
+
+    #[test]
+    fn recommended_chain_brings_ascent_close_to_true_gain() {
+        let mut points = noisy_climb();
+        let raw = ascent_descent(&points).0;
+        assert!(raw > 400.0, "raw ascent {raw}");
+        filter_points(&mut points, &ElevationFilter::recommended(), None).unwrap();
+        let (ascent, descent) = ascent_descent(&points);
+        assert!((ascent - 100.0).abs() < 15.0, "filtered ascent {ascent}");
+        assert!(descent < 5.0, "filtered descent {descent}");
+    }
+
+    #[test]
+    fn hysteresis_holds_until_threshold() {
+        let mut points: Vec<Point> = [100.0, 102.0, 99.0, 104.0, 105.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &e)| pt(47.0 + i as f64 * 0.001, Some(e)))
+            .collect();
+        filter_points(&mut points, &[ElevationFilter::Hysteresis { threshold_m: 3.0 }], None)
+            .unwrap();
+        let eles: Vec<f64> = points.iter().map(|p| p.ele.unwrap()).collect();
+        assert_eq!(eles, vec![100.0, 100.0, 100.0, 104.0, 104.0]);
+    }
+
+    #[test]
+    fn filters_skip_missing_elevations() {
+        let mut points = vec![pt(47.0, Some(100.0)), pt(47.001, None), pt(47.002, Some(110.0))];
+        filter_points(&mut points, &ElevationFilter::recommended(), None).unwrap();
+        assert_eq!(points[1].ele, None);
+        assert!(points[2].ele.is_some());
+    }
+
+    #[test]
+    fn dem_offset_corrects_bias() {
+        let dir = std::env::temp_dir().join(format!("ndkarte-elevation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Flat 3-sample tile at 500 m
+        let bytes: Vec<u8> = std::iter::repeat_n(500i16.to_be_bytes(), 9).flatten().collect();
+        std::fs::write(dir.join(format!("{}.hgt", tile_name(47, 11))), bytes).unwrap();
+
+        let mut points = vec![pt(47.2, Some(540.0)), pt(47.3, Some(545.0)), pt(47.4, None)];
+        let mut dem = Dem::new(&dir);
+        filter_points(&mut points, &[ElevationFilter::DemOffset], Some(&mut dem)).unwrap();
+        assert_eq!(points[0].ele, Some(495.0));
+        assert_eq!(points[1].ele, Some(500.0));
+        assert_eq!(points[2].ele, None);
+
+        assert!(filter_points(&mut points, &[ElevationFilter::DemOffset], None).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn filter_json_reports_ascent() {
+        let data = GpxData {
+            tracks: vec![crate::gpx::Track { name: None, points: noisy_climb() }],
+            ..GpxData::default()
+        };
+        let json = serde_json::to_string(&data).unwrap();
+        let out = filter_json(&json, "", None).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert!(v["ascent_before_m"].as_f64().unwrap() > v["ascent_after_m"].as_f64().unwrap());
+        assert_eq!(v["tracks"][0]["points"].as_array().unwrap().len(), 101);
+        assert!(filter_json(&json, "[{\"method\": \"bogus\"}]", None).is_err());
+        let huge = r#"[{"method": "median", "window": 18446744073709551615}]"#;
+        assert!(filter_json(&json, huge, None).is_err());
+        let negative = r#"[{"method": "hysteresis", "threshold_m": -1}]"#;
+        assert!(filter_json(&json, negative, None).is_err());
+    }
+
+    #[test]
+    fn median_window_wider_than_track() {
+        let mut points = noisy_climb();
+        points.truncate(4);
+        median(&mut points, MAX_MEDIAN_WINDOW);
+        assert_eq!(points[0].ele, Some(504.0));
+        assert_eq!(points[3].ele, Some(499.0));
+    }
+}
@@ -0,0 +1,580 @@
+//! Garmin FIT activity and course file decoding.
+//!
+//! Decodes the binary FIT container (definition and data messages,
+//! compressed timestamp headers, chained files) and extracts:
+//!
+//! - `record` messages as track points with time and elevation
+//! - `course` messages for the track name
+//! - `course_point` messages as waypoints
+//! - `lap` messages as "Lap N" waypoints at each lap end, when the file
+//!   has more than one lap
+//!
+//! All other messages are skipped. The file CRC is not verified so
+//! that recordings cut short by a dead battery still load.
+
+use std::collections::HashMap;
+
+use crate::gpx::{GpxData, Point, Track, Waypoint};
+
+/// Seconds between the Unix epoch and the FIT epoch (1989-12-31 00:00 UTC).
+const FIT_EPOCH_OFFSET: f64 = 631_065_600.0;
+
+/// Degrees per semicircle (2^31 semicircles = 180 degrees).
+const DEG_PER_SEMICIRCLE: f64 = 180.0 / 2_147_483_648.0;
+
+// Global message numbers
+const MESG_LAP: u16 = 19;
+const MESG_RECORD: u16 = 20;
+const MESG_COURSE: u16 = 31;
+const MESG_COURSE_POINT: u16 = 32;
+
+// Field numbers shared by several messages
+const FIELD_TIMESTAMP: u8 = 253;
+
+/// Whether the bytes start with a FIT file header.
+pub fn is_fit(data: &[u8]) -> bool {
+    data.len() >= 12 && (data[0] == 12 || data[0] == 14) && &data[8..12] == b".FIT"
+}
+
+/// Layout of one field in a definition message.
+#[derive(Clone, Copy)]
+struct FieldDef {
+    number: u8,
+    size: u8,
+    base_type: u8,
+}
+
+/// A definition message, describing the data messages of a local type.
+struct Definition {
+    global: u16,
+    big_endian: bool,
+    fields: Vec<FieldDef>,
+    /// Total size of developer fields, which are skipped.
+    developer_size: usize,
+}
+
+/// A decoded field value.
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Text(String),
+}
+
+impl Value {
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(v) => Some(*v),
+            Value::Text(_) => None,
+        }
+    }
+}
+
+/// Parse a FIT file into `GpxData`.
+pub fn parse_fit(data: &[u8]) -> Result<GpxData, String> {
+    if !is_fit(data) {
+        return Err("FIT parse error: missing .FIT header".to_string());
+    }
+
+    let mut decoder = Decoder::default();
+    let mut offset = 0;
+    // FIT files may be chained back to back
+    while offset < data.len() && is_fit(&data[offset..]) {
+        let header_size = data[offset] as usize;
+        let size_bytes = [data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]];
+        let data_size = u32::from_le_bytes(size_bytes) as usize;
+        let truncated = || "FIT parse error: truncated header".to_string();
+        let start = offset.checked_add(header_size).ok_or_else(truncated)?;
+        if start > data.len() {
+            return Err(truncated());
+        }
+        let end = start.checked_add(data_size).map_or(data.len(), |end| end.min(data.len()));
+        decoder.definitions.clear();
+        decoder.decode_records(&data[start..end])?;
+        offset = end + 2;
+    }
+
+    Ok(decoder.finish())
+}
+
+/// Parse a FIT file and return the result as GPX JSON.
+pub fn parse_to_json(data: &[u8]) -> Result<String, String> {
+    let gpx_data = parse_fit(data)?;
+    serde_json::to_string(&gpx_data).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+#[derive(Default)]
+struct Decoder {
+    definitions: HashMap<u8, Definition>,
+    last_timestamp: Option<u32>,
+    name: Option<String>,
+    points: Vec<Point>,
+    course_points: Vec<Waypoint>,
+    laps: Vec<Point>,
+}
+
+impl Decoder {
+    /// Decode all messages in a data section.
+    ///
+    /// A message cut off by the end of the data ends decoding quietly,
+    /// keeping everything decoded up to that point.
+    fn decode_records(&mut self, mut buf: &[u8]) -> Result<(), String> {
+        while let Some((&header, rest)) = buf.split_first() {
+            buf = rest;
+
+            if header & 0x80 != 0 {
+                // Compressed timestamp header: 5-bit rolling offset
+                let local = (header >> 5) & 0x03;
+                let offset = u32::from(header & 0x1F);
+                let timestamp = self.last_timestamp.map(|last| {
+                    let mut t = (last & !0x1F) + offset;
+                    if offset < (last & 0x1F) {
+                        t += 0x20;
+                    }
+                    t
+                });
+                buf = self.decode_data(local, buf, timestamp)?;
+            } else if header & 0x40 != 0 {
+                buf = self.decode_definition(header, buf)?;
+            } else {
+                buf = self.decode_data(header & 0x0F, buf, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_definition<'a>(&mut self, header: u8, buf: &'a [u8]) -> Result<&'a [u8], String> {
+        let local = header & 0x0F;
+        let has_developer = header & 0x20 != 0;
+
+        let Some(fixed) = buf.get(..5) else {
+            return Ok(&[]);
+        };
+        let big_endian = fixed[1] == 1;
+        let global = if big_endian {
+            u16::from_be_bytes([fixed[2], fixed[3]])
+        } else {
+            u16::from_le_bytes([fixed[2], fixed[3]])
+        };
+        let count = fixed[4] as usize;
+        let mut rest = &buf[5..];
+
+        let Some(field_bytes) = rest.get(..count * 3) else {
+            return Ok(&[]);
+        };
+        let fields = field_bytes
+            .chunks_exact(3)
+            .map(|f| FieldDef {
+                number: f[0],
+                size: f[1],
+                base_type: f[2],
+            })
+            .collect();
+        rest = &rest[count * 3..];
+
+        let mut developer_size = 0;
+        if has_developer {
+            let Some(&dev_count) = rest.first() else {
+                return Ok(&[]);
+            };
+            let dev_count = dev_count as usize;
+            let Some(dev_bytes) = rest.get(1..1 + dev_count * 3) else {
+                return Ok(&[]);
+            };
+            developer_size = dev_bytes.chunks_exact(3).map(|f| f[1] as usize).sum();
+            rest = &rest[1 + dev_count * 3..];
+        }
+
+        self.definitions.insert(
+            local,
+            Definition {
+                global,
+                big_endian,
+                fields,
+                developer_size,
+            },
+        );
+        Ok(rest)
+    }
+
+    fn decode_data<'a>(
+        &mut self,
+        local: u8,
+        buf: &'a [u8],
+        compressed_timestamp: Option<u32>,
+    ) -> Result<&'a [u8], String> {
+        let def = self
+            .definitions
+            .get(&local)
+            .ok_or_else(|| format!("FIT parse error: data for undefined local message {local}"))?;
+        let global = def.global;
+
+        let mut values: HashMap<u8, Value> = HashMap::new();
+        let mut rest = buf;
+        for field in &def.fields {
+            let size = field.size as usize;
+            let Some(bytes) = rest.get(..size) else {
+                return Ok(&[]);
+            };
+            if let Some(v) = decode_value(bytes, field.base_type, def.big_endian) {
+                values.insert(field.number, v);
+            }
+            rest = &rest[size..];
+        }
+        let Some(rest) = rest.get(def.developer_size..) else {
+            return Ok(&[]);
+        };
+
+        let timestamp = match values.get(&FIELD_TIMESTAMP).and_then(Value::as_int) {
+            Some(t) => Some(t as u32),
+            None => compressed_timestamp,
+        };
+        if let Some(t) = timestamp {
+            self.last_timestamp = Some(t);
+        }
+
+        self.handle_message(global, &values, timestamp);
+        Ok(rest)
+    }
+
+    fn handle_message(&mut self, global: u16, values: &HashMap<u8, Value>, timestamp: Option<u32>) {
+        let int = |n: u8| values.get(&n).and_then(Value::as_int);
+        let time = timestamp.map(|t| f64::from(t) + FIT_EPOCH_OFFSET);
+
+        match global {
+            MESG_RECORD => {
+                // Fields: 0 position_lat, 1 position_long, 2 altitude, 78 enhanced_altitude
+                if let Some(mut point) = position(int(0), int(1)) {
+                    point.ele = int(78).or(int(2)).map(|a| a as f64 / 5.0 - 500.0);
+                    point.time = time;
+                    self.points.push(point);
+                }
+            }
+            MESG_LAP => {
+                // Fields: 5 end_position_lat, 6 end_position_long
+                if let Some(mut point) = position(int(5), int(6)) {
+                    point.time = time;
+                    self.laps.push(point);
+                }
+            }
+            MESG_COURSE => {
+                // Field 5: name
+                if let Some(Value::Text(name)) = values.get(&5) {
+                    self.name = Some(name.clone());
+                }
+            }
+            MESG_COURSE_POINT => {
+                // Fields: 1 timestamp, 2 position_lat, 3 position_long, 6 name
+                if let Some(mut point) = position(int(2), int(3)) {
+                    point.time = int(1).map(|t| t as f64 + FIT_EPOCH_OFFSET);
+                    let name = match values.get(&6) {
+                        Some(Value::Text(name)) => Some(name.clone()),
+                        _ => None,
+                    };
+                    self.course_points.push(Waypoint {
+                        name,
+                        point,
+                        icon: None,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> GpxData {
+        let mut waypoints = self.course_points;
+        if self.laps.len() > 1 {
+            waypoints.extend(self.laps.into_iter().enumerate().map(|(i, point)| Waypoint {
+                name: Some(format!("Lap {}", i + 1)),
+                point,
+                icon: None,
+            }));
+        }
+
+        let tracks = if self.points.is_empty() {
+            Vec::new()
+        } else {
+            vec![Track {
+                name: self.name,
+                points: self.points,
+            }]
+        };
+
+        GpxData {
+            tracks,
+            routes: Vec::new(),
+            waypoints,
+            version: None,
+        }
+    }
+}
+
+/// Build a point from semicircle coordinates, if both are valid.
+fn position(lat: Option<i64>, lon: Option<i64>) -> Option<Point> {
+    let lat = lat? as f64 * DEG_PER_SEMICIRCLE;
+    let lon = lon? as f64 * DEG_PER_SEMICIRCLE;
+    Some(Point {
+        lat,
+        lon,
+        ele: None,
+        time: None,
+        speed: None,
+        course: None,
+    })
+}
+
+/// Decode a single field value, returning None for the type's invalid marker.
+///
+/// Only the base types used by the messages above are decoded; arrays
+/// and other types yield None.
+fn decode_value(bytes: &[u8], base_type: u8, big_endian: bool) -> Option<Value> {
+    macro_rules! int {
+        ($t:ty, $invalid:expr) => {{
+            let raw: [u8; std::mem::size_of::<$t>()] = bytes.try_into().ok()?;
+            let v = if big_endian {
+                <$t>::from_be_bytes(raw)
+            } else {
+                <$t>::from_le_bytes(raw)
+            };
+            (v != $invalid).then_some(Value::Int(v as i64))
+        }};
+    }
+
+    match base_type & 0x1F {
+        // enum, uint8, byte
+        0x00 | 0x02 | 0x0D => int!(u8, 0xFF),
+        0x01 => int!(i8, 0x7F),
+        0x03 => int!(i16, 0x7FFF),
+        0x04 => int!(u16, 0xFFFF),
+        0x05 => int!(i32, 0x7FFF_FFFF),
+        0x06 => int!(u32, 0xFFFF_FFFF),
+        0x07 => {
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            let text = String::from_utf8_lossy(&bytes[..end]).into_owned();
+            (!text.is_empty()).then_some(Value::Text(text))
+        }
+        0x0A => int!(u8, 0),
+        0x0B => int!(u16, 0),
+        0x0C => int!(u32, 0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds minimal FIT files for tests.
+    struct FitBuilder {
+        records: Vec<u8>,
+    }
+
+    impl FitBuilder {
+        fn new() -> Self {
+            Self { records: Vec::new() }
+        }
+
+        fn define(&mut self, local: u8, global: u16, fields: &[(u8, u8, u8)]) -> &mut Self {
+            self.records.push(0x40 | local);
+            self.records.extend_from_slice(&[0, 0]);
+            self.records.extend_from_slice(&global.to_le_bytes());
+            self.records.push(fields.len() as u8);
+            for &(number, size, base_type) in fields {
+                self.records.extend_from_slice(&[number, size, base_type]);
+            }
+            self
+        }
+
+        fn data(&mut self, header: u8, bytes: &[u8]) -> &mut Self {
+            self.records.push(header);
+            self.records.extend_from_slice(bytes);
+            self
+        }
+
+        fn build(&self) -> Vec<u8> {
+            let mut out = vec![14, 0x10, 0, 0];
+            out.extend_from_slice(&(self.records.len() as u32).to_le_bytes());
+            out.extend_from_slice(b".FIT");
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&self.records);
+            out.extend_from_slice(&[0, 0]);
+            out
+        }
+    }
+
+    fn semicircles(deg: f64) -> [u8; 4] {
+        ((deg / DEG_PER_SEMICIRCLE).round() as i32).to_le_bytes()
+    }
+
+    fn record(timestamp: u32, lat: f64, lon: f64, altitude_m: f64) -> Vec<u8> {
+        let mut bytes = timestamp.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&semicircles(lat));
+        bytes.extend_from_slice(&semicircles(lon));
+        bytes.extend_from_slice(&(((altitude_m + 500.0) * 5.0) as u16).to_le_bytes());
+        bytes
+    }
+
+    /// timestamp (uint32), position_lat/long (sint32), altitude (uint16)
+    const RECORD_FIELDS: [(u8, u8, u8); 4] =
+        [(253, 4, 0x86), (0, 4, 0x85), (1, 4, 0x85), (2, 2, 0x84)];
+
+    #[test]
+    fn detects_signature() {
+        let fit = FitBuilder::new().build();
+        assert!(is_fit(&fit));
+        assert!(!is_fit(b"<?xml version=\"1.0\"?><gpx/>"));
+        assert!(parse_fit(b"<gpx/>").is_err());
+    }
+
+    #[test]
+    fn decodes_records() {
+        let fit = FitBuilder::new()
+            .define(0, MESG_RECORD, &RECORD_FIELDS)
+            .data(0, &record(1_000_000_000, 48.2082, 16.3738, 171.0))
+            .data(0, &record(1_000_000_005, 48.2090, 16.3750, 173.4))
+            .build();
+
+        let data = parse_fit(&fit).unwrap();
+        let points = &data.tracks[0].points;
+        assert_eq!(points.len(), 2);
+        assert!((points[0].lat - 48.2082).abs() < 1e-6);
+        assert!((points[0].lon - 16.3738).abs() < 1e-6);
+        assert_eq!(points[0].ele, Some(171.0));
+        assert!((points[1].ele.unwrap() - 173.4).abs() < 0.1);
+        assert_eq!(points[0].time, Some(1_000_000_000.0 + FIT_EPOCH_OFFSET));
+    }
+
+    #[test]
+    fn skips_records_without_position() {
+        let mut no_fix = record(1_000_000_000, 0.0, 0.0, 100.0);
+        no_fix[4..8].copy_from_slice(&0x7FFF_FFFFi32.to_le_bytes());
+        let fit = FitBuilder::new()
+            .define(0, MESG_RECORD, &RECORD_FIELDS)
+            .data(0, &no_fix)
+            .data(0, &record(1_000_000_001, 48.0, 16.0, 100.0))
+            .build();
+
+        let data = parse_fit(&fit).unwrap();
+        assert_eq!(data.tracks[0].points.len(), 1);
+    }
+
+    #[test]
+    fn decodes_compressed_timestamps() {
+        // Record layout without the timestamp field
+        let fields = [(0, 4, 0x85), (1, 4, 0x85)];
+        let mut position = semicircles(48.0).to_vec();
+        position.extend_from_slice(&semicircles(16.0));
+
+        let fit = FitBuilder::new()
+            .define(0, MESG_RECORD, &RECORD_FIELDS)
+            .define(1, MESG_RECORD, &fields)
+            .data(0, &record(1_000_000_030, 48.0, 16.0, 0.0)) // 30 = 0b11110
+            .data(0x80 | (1 << 5) | 0x1F, &position) // +1 s
+            .data(0x80 | (1 << 5) | 0x02, &position) // wraps to +4 s
+            .build();
+
+        let data = parse_fit(&fit).unwrap();
+        let times: Vec<f64> = data.tracks[0]
+            .points
+            .iter()
+            .map(|p| p.time.unwrap() - FIT_EPOCH_OFFSET)
+            .collect();
+        assert_eq!(times, vec![1_000_000_030.0, 1_000_000_031.0, 1_000_000_034.0]);
+    }
+
+    #[test]
+    fn decodes_course_name_and_points() {
+        let mut name = b"Grossglockner".to_vec();
+        name.resize(16, 0);
+        let mut course_point = 1_000_000_000u32.to_le_bytes().to_vec();
+        course_point.extend_from_slice(&semicircles(47.07));
+        course_point.extend_from_slice(&semicircles(12.84));
+        let mut cp_name = b"Summit".to_vec();
+        cp_name.resize(8, 0);
+        course_point.extend_from_slice(&cp_name);
+
+        let fit = FitBuilder::new()
+            .define(0, MESG_COURSE, &[(5, 16, 0x07)])
+            .data(0, &name)
+            .define(1, MESG_COURSE_POINT, &[(1, 4, 0x86), (2, 4, 0x85), (3, 4, 0x85), (6, 8, 0x07)])
+            .data(1, &course_point)
+            .define(2, MESG_RECORD, &RECORD_FIELDS)
+            .data(2, &record(1_000_000_000, 47.0, 12.8, 2000.0))
+            .build();
+
+        let data = parse_fit(&fit).unwrap();
+        assert_eq!(data.tracks[0].name.as_deref(), Some("Grossglockner"));
+        assert_eq!(data.waypoints.len(), 1);
+        assert_eq!(data.waypoints[0].name.as_deref(), Some("Summit"));
+        assert!((data.waypoints[0].point.lat - 47.07).abs() < 1e-6);
+    }
+
+    #[test]
+    fn multiple_laps_become_waypoints() {
+        let lap = |lat: f64| {
+            let mut bytes = semicircles(lat).to_vec();
+            bytes.extend_from_slice(&semicircles(16.0));
+            bytes
+        };
+        let fit = FitBuilder::new()
+            .define(0, MESG_LAP, &[(5, 4, 0x85), (6, 4, 0x85)])
+            .data(0, &lap(48.0))
+            .data(0, &lap(48.1))
+            .build();
+
+        let data = parse_fit(&fit).unwrap();
+        assert!(data.tracks.is_empty());
+        let names: Vec<_> = data.waypoints.iter().map(|w| w.name.clone().unwrap()).collect();
+        assert_eq!(names, vec!["Lap 1", "Lap 2"]);
+    }
+
+    #[test]
+    fn skips_developer_fields() {
+        let mut fit = FitBuilder::new();
+        // Definition with one developer field of 3 bytes
+        fit.records.push(0x60);
+        fit.records.extend_from_slice(&[0, 0]);
+        fit.records.extend_from_slice(&MESG_RECORD.to_le_bytes());
+        fit.records.push(RECORD_FIELDS.len() as u8);
+        for (number, size, base_type) in RECORD_FIELDS {
+            fit.records.extend_from_slice(&[number, size, base_type]);
+        }
+        fit.records.extend_from_slice(&[1, 0, 3, 0]);
+        let mut with_dev = record(1_000_000_000, 48.0, 16.0, 100.0);
+        with_dev.extend_from_slice(&[9, 9, 9]);
+        fit.data(0, &with_dev).data(0, &with_dev);
+
+        let data = parse_fit(&fit.build()).unwrap();
+        assert_eq!(data.tracks[0].points.len(), 2);
+    }
+
+    #[test]
+    fn truncated_file_keeps_complete_records() {
+        let mut fit = FitBuilder::new()
+            .define(0, MESG_RECORD, &RECORD_FIELDS)
+            .data(0, &record(1_000_000_000, 48.0, 16.0, 100.0))
+            .data(0, &record(1_000_000_001, 48.1, 16.0, 100.0))
+            .build();
+        // Cut the last record short but keep the declared size
+        fit.truncate(fit.len() - 6);
+
+        let data = parse_fit(&fit).unwrap();
+        assert_eq!(data.tracks[0].points.len(), 1);
+    }
+
+    #[test]
+    fn header_longer_than_file_is_error() {
+        let mut fit = [0u8; 12];
+        fit[0] = 14;
+        fit[8..12].copy_from_slice(b".FIT");
+        assert!(is_fit(&fit));
+        assert_eq!(parse_fit(&fit).unwrap_err(), "FIT parse error: truncated header");
+        assert!(crate::import::import(&fit).is_err());
+    }
+
+    #[test]
+    fn undefined_local_message_is_error() {
+        let fit = FitBuilder::new().data(3, &[0, 0]).build();
+        assert!(parse_fit(&fit).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+//! Geodesics on the WGS84 ellipsoid.
+//!
+//! The flat latitude-cosine approximations in `nav` and `convert` are
+//! fast and accurate for the dense legs of recorded tracks, and
+//! `nav::haversine` is within 0.5% on a sphere. Sparse routes with legs
+//! of tens of kilometers, polar tracks and legs across the antimeridian
+//! need the real ellipsoid. This module implements Vincenty's inverse
+//! and direct formulae (sub-millimeter on WGS84) and cross-track /
+//! along-track distances to a geodesic using the iterative method of
+//! Baselga and Martinez-Llario (2018).
+
+use crate::gpx::Point;
+use crate::nav::{haversine, EARTH_RADIUS_M};
+
+/// WGS84 semi-major axis in meters.
+const A: f64 = 6_378_137.0;
+/// WGS84 flattening.
+const F: f64 = 1.0 / 298.257_223_563;
+/// WGS84 semi-minor axis in meters.
+const B: f64 = A * (1.0 - F);
+
+/// Legs longer than this use geodesic math instead of the flat
+/// approximation, in meters.
+pub const LONG_LEG_M: f64 = 10_000.0;
+
+/// Beyond this latitude the latitude-cosine scaling degrades quickly.
+const POLAR_LAT: f64 = 80.0;
+
+/// Result of the inverse problem between two points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Inverse {
+    /// Geodesic distance in meters.
+    pub distance_m: f64,
+    /// Forward azimuth at the first point, degrees clockwise from north.
+    pub azimuth1_deg: f64,
+    /// Forward azimuth at the second point, degrees clockwise from north.
+    pub azimuth2_deg: f64,
+}
+
+/// Position of a point relative to the geodesic through A and B.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrossTrack {
+    /// Distance from A to the foot of the perpendicular, along the
+    /// geodesic towards B (negative if the foot lies behind A).
+    pub along_track_m: f64,
+    /// Perpendicular distance to the geodesic, positive to the right of
+    /// the direction of travel.
+    pub cross_track_m: f64,
+}
+
+/// Whether the leg A-B is too long, too polar or crosses the
+/// antimeridian for the flat approximation.
+pub fn needs_geodesic(a: &Point, b: &Point) -> bool {
+    (b.lon - a.lon).abs() > 180.0
+        || a.lat.abs().max(b.lat.abs()) > POLAR_LAT
+        || haversine(a, b) > LONG_LEG_M
+}
+
+/// Solve the inverse problem with Vincenty's formulae.
+///
+/// For nearly antipodal points, where the iteration does not converge,
+/// falls back to the spherical great circle.
+pub fn inverse(p1: &Point, p2: &Point) -> Inverse {
+    let l = normalize_rad((p2.lon - p1.lon).to_radians());
+    let u1 = ((1.0 - F) * p1.lat.to_radians().tan()).atan();
+    let u2 = ((1.0 - F) * p2.lat.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_l, cos_l) = lambda.sin_cos();
+        let sin_sigma = (cos_u2 * sin_l).hypot(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_l);
+        if sin_sigma == 0.0 {
+            return Inverse {
+                distance_m: 0.0,
+                azimuth1_deg: 0.0,
+                azimuth2_deg: 0.0,
+            };
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_l;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_l / sin_sigma;
+        let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+        // Equatorial lines have cos²α = 0
+        let cos_2sm = if cos2_alpha != 0.0 {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha
+        } else {
+            0.0
+        };
+        let c = F / 16.0 * cos2_alpha * (4.0 + F * (4.0 - 3.0 * cos2_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sm + c * cos_sigma * (-1.0 + 2.0 * cos_2sm * cos_2sm)));
+        if lambda.abs() > std::f64::consts::PI {
+            break;
+        }
+        if (lambda - previous).abs() < 1e-12 {
+            let u_sq = cos2_alpha * (A * A - B * B) / (B * B);
+            let (big_a, big_b) = series(u_sq);
+            let delta_sigma = delta_sigma(big_b, sin_sigma, cos_sigma, cos_2sm);
+            let (sin_l, cos_l) = lambda.sin_cos();
+            let az1 = (cos_u2 * sin_l).atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_l);
+            let az2 = (cos_u1 * sin_l).atan2(-sin_u1 * cos_u2 + cos_u1 * sin_u2 * cos_l);
+            return Inverse {
+                distance_m: B * big_a * (sigma - delta_sigma),
+                azimuth1_deg: to_azimuth_deg(az1),
+                azimuth2_deg: to_azimuth_deg(az2),
+            };
+        }
+    }
+
+    spherical_inverse(p1, p2)
+}
+
+/// Geodesic distance in meters.
+pub fn distance(p1: &Point, p2: &Point) -> f64 {
+    inverse(p1, p2).distance_m
+}
+
+/// Solve the direct problem with Vincenty's formulae: the point reached
+/// from `start` after `distance_m` along the initial `azimuth_deg`, and
+/// the forward azimuth there.
+pub fn direct(start: &Point, azimuth_deg: f64, distance_m: f64) -> (Point, f64) {
+    let alpha1 = azimuth_deg.to_radians();
+    let (sin_a1, cos_a1) = alpha1.sin_cos();
+    let tan_u1 = (1.0 - F) * start.lat.to_radians().tan();
+    let cos_u1 = 1.0 / (1.0 + tan_u1 * tan_u1).sqrt();
+    let sin_u1 = tan_u1 * cos_u1;
+    let sigma1 = tan_u1.atan2(cos_a1);
+    let sin_alpha = cos_u1 * sin_a1;
+    let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+    let u_sq = cos2_alpha * (A * A - B * B) / (B * B);
+    let (big_a, big_b) = series(u_sq);
+
+    let mut sigma = distance_m / (B * big_a);
+    let (mut sin_sigma, mut cos_sigma, mut cos_2sm);
+    let mut iterations = 0;
+    loop {
+        cos_2sm = (2.0 * sigma1 + sigma).cos();
+        (sin_sigma, cos_sigma) = sigma.sin_cos();
+        let previous = sigma;
+        sigma = distance_m / (B * big_a) + delta_sigma(big_b, sin_sigma, cos_sigma, cos_2sm);
+        iterations += 1;
+        if (sigma - previous).abs() < 1e-12 || iterations >= 100 {
+            break;
+        }
+    }
+    (sin_sigma, cos_sigma) = sigma.sin_cos();
+    cos_2sm = (2.0 * sigma1 + sigma).cos();
+
+    let x = sin_u1 * sin_sigma - cos_u1 * cos_sigma * cos_a1;
+    let lat = (sin_u1 * cos_sigma + cos_u1 * sin_sigma * cos_a1)
+        .atan2((1.0 - F) * sin_alpha.hypot(x));
+    let lambda = (sin_sigma * sin_a1).atan2(cos_u1 * cos_sigma - sin_u1 * sin_sigma * cos_a1);
+    let c = F / 16.0 * cos2_alpha * (4.0 + F * (4.0 - 3.0 * cos2_alpha));
+    let l = lambda
+        - (1.0 - c)
+            * F
+            * sin_alpha
+            * (sigma
+                + c * sin_sigma * (cos_2sm + c * cos_sigma * (-1.0 + 2.0 * cos_2sm * cos_2sm)));
+    let lon = normalize_rad(start.lon.to_radians() + l);
+    let azimuth2 = sin_alpha.atan2(-x);
+
+    let point = Point {
+        lat: lat.to_degrees(),
+        lon: lon.to_degrees(),
+        ele: None,
+        time: None,
+        speed: None,
+        course: None,
+    };
+    (point, to_azimuth_deg(azimuth2))
+}
+
+/// Cross-track and along-track distance of `p` relative to the
+/// geodesic from `a` through `b`.
+///
+/// Walks along the geodesic to the foot of the perpendicular, correcting
+/// the along-track distance with the right spherical triangle formed by
+/// the current foot estimate and `p` until the correction is below
+/// 0.1 mm.
+pub fn cross_track(p: &Point, a: &Point, b: &Point) -> CrossTrack {
+    let leg = inverse(a, b);
+    if leg.distance_m < 1e-9 {
+        // No direction; treat the distance to A as perpendicular
+        return CrossTrack {
+            along_track_m: 0.0,
+            cross_track_m: distance(a, p),
+        };
+    }
+
+    let mut along = 0.0;
+    let mut cross = 0.0;
+    for _ in 0..50 {
+        let (foot, azimuth) = direct(a, leg.azimuth1_deg, along);
+        let to_p = inverse(&foot, p);
+        if to_p.distance_m < 1e-9 {
+            cross = 0.0;
+            break;
+        }
+        let angle = (to_p.azimuth1_deg - azimuth).to_radians();
+        // Spherical correction step; the final result does not depend
+        // on the radius
+        let c = to_p.distance_m / EARTH_RADIUS_M;
+        let step = EARTH_RADIUS_M * (c.sin() * angle.cos()).atan2(c.cos());
+        along += step;
+        cross = to_p.distance_m.copysign(angle.sin());
+        if step.abs() < 1e-4 {
+            break;
+        }
+    }
+    CrossTrack {
+        along_track_m: along,
+        cross_track_m: cross,
+    }
+}
+
+/// Nearest point to `p` on the geodesic segment A-B, with its distance
+/// from A as a fraction of the segment length.
+pub fn project_on_segment(p: &Point, a: &Point, b: &Point) -> (Point, f64) {
+    let leg = inverse(a, b);
+    if leg.distance_m < 1e-9 {
+        return (a.clone(), 0.0);
+    }
+    let along = cross_track(p, a, b).along_track_m;
+    if along <= 0.0 {
+        return (a.clone(), 0.0);
+    }
+    if along >= leg.distance_m {
+        return (b.clone(), 1.0);
+    }
+    let (foot, _) = direct(a, leg.azimuth1_deg, along);
+    (foot, along / leg.distance_m)
+}
+
+/// Vincenty's A and B series coefficients for a given u².
+fn series(u_sq: f64) -> (f64, f64) {
+    let a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+    let b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+    (a, b)
+}
+
+fn delta_sigma(big_b: f64, sin_sigma: f64, cos_sigma: f64, cos_2sm: f64) -> f64 {
+    big_b
+        * sin_sigma
+        * (cos_2sm
+            + big_b / 4.0
+                * (cos_sigma * (-1.0 + 2.0 * cos_2sm * cos_2sm)
+                    - big_b / 6.0
+                        * cos_2sm
+                        * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                        * (-3.0 + 4.0 * cos_2sm * cos_2sm)))
+}
+
+/// Great-circle fallback for nearly antipodal points.
+fn spherical_inverse(p1: &Point, p2: &Point) -> Inverse {
+    Inverse {
+        distance_m: haversine(p1, p2),
+        azimuth1_deg: crate::route_nav::bearing(p1, p2),
+        azimuth2_deg: (crate::route_nav::bearing(p2, p1) + 180.0) % 360.0,
+    }
+}
+
+/// Wrap an angle to [-π, π].
+fn normalize_rad(angle: f64) -> f64 {
+    let pi = std::f64::consts::PI;
+    (angle + pi).rem_euclid(2.0 * pi) - pi
+}
+
+/// Convert an azimuth in radians to degrees in [0, 360).
+fn to_azimuth_deg(angle: f64) -> f64 {
+    angle.to_degrees().rem_euclid(360.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    fn dms(d: f64, m: f64, s: f64) -> f64 {
+        d.signum() * (d.abs() + m / 60.0 + s / 3600.0)
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    /// Vincenty's worked example used by Geoscience Australia:
+    /// Flinders Peak to Buninyong.
+    fn flinders_peak() -> Point {
+        pt(dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440))
+    }
+
+    fn buninyong() -> Point {
+        pt(dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390))
+    }
+
+    #[test]
+    fn inverse_reference_line() {
+        let result = inverse(&flinders_peak(), &buninyong());
+        assert_close(result.distance_m, 54_972.271, 0.001);
+        assert_close(result.azimuth1_deg, dms(306.0, 52.0, 5.37), 1e-5);
+        // Published reverse azimuth 127°10'25.07", i.e. forward + 180°
+        assert_close(result.azimuth2_deg, dms(127.0, 10.0, 25.07) + 180.0, 1e-5);
+    }
+
+    #[test]
+    fn direct_reference_line() {
+        let (end, _) = direct(&flinders_peak(), dms(306.0, 52.0, 5.37), 54_972.271);
+        let expected = buninyong();
+        assert_close(end.lat, expected.lat, 1e-7);
+        assert_close(end.lon, expected.lon, 1e-7);
+    }
+
+    #[test]
+    fn meridian_and_equator_lengths() {
+        // Quarter meridian of WGS84
+        assert_close(distance(&pt(0.0, 0.0), &pt(90.0, 0.0)), 10_001_965.729, 0.001);
+        // One degree of equator is a * pi / 180
+        assert_close(distance(&pt(0.0, 0.0), &pt(0.0, 1.0)), 111_319.491, 0.001);
+        // Across the antimeridian the short way round
+        assert_close(distance(&pt(0.0, 179.0), &pt(0.0, -179.0)), 222_638.982, 0.001);
+        assert_eq!(distance(&pt(47.0, 11.0), &pt(47.0, 11.0)), 0.0);
+    }
+
+    #[test]
+    fn nearly_antipodal_points_fall_back() {
+        let d = distance(&pt(0.0, 0.0), &pt(0.5, 179.7));
+        assert!((19_900_000.0..20_100_000.0).contains(&d), "got {d}");
+    }
+
+    #[test]
+    fn cross_track_to_equator() {
+        // The perpendicular from (1, 5) to the equator is a meridian
+        let result = cross_track(&pt(1.0, 5.0), &pt(0.0, 0.0), &pt(0.0, 10.0));
+        assert_close(result.along_track_m, 5.0 * 111_319.490_793, 0.001);
+        // Meridian arc from 0 to 1 degree; north of an eastbound line is left
+        assert_close(result.cross_track_m, -110_574.389, 0.001);
+
+        let right = cross_track(&pt(-1.0, 5.0), &pt(0.0, 0.0), &pt(0.0, 10.0));
+        assert_close(right.cross_track_m, 110_574.389, 0.001);
+    }
+
+    #[test]
+    fn cross_track_across_antimeridian() {
+        let result = cross_track(&pt(0.1, -179.5), &pt(0.0, 179.0), &pt(0.0, -178.0));
+        assert_close(result.along_track_m, 1.5 * 111_319.490_793, 0.01);
+        assert!(result.cross_track_m < 0.0);
+    }
+
+    #[test]
+    fn projection_clamps_to_segment() {
+        let a = pt(60.0, 10.0);
+        let b = pt(61.0, 12.0);
+        let (foot, t) = project_on_segment(&pt(59.0, 8.0), &a, &b);
+        assert_eq!((foot.lat, foot.lon, t), (a.lat, a.lon, 0.0));
+        let (_, t) = project_on_segment(&pt(60.6, 11.0), &a, &b);
+        assert!(t > 0.0 && t < 1.0);
+    }
+
+    #[test]
+    fn long_legs_need_geodesic() {
+        assert!(!needs_geodesic(&pt(47.0, 11.0), &pt(47.01, 11.01)));
+        assert!(needs_geodesic(&pt(47.0, 11.0), &pt(47.5, 11.0)));
+        assert!(needs_geodesic(&pt(0.0, 179.99), &pt(0.0, -179.99)));
+        assert!(needs_geodesic(&pt(85.0, 0.0), &pt(85.0, 0.01)));
+    }
+}
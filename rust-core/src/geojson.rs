@@ -0,0 +1,367 @@
+//! GeoJSON import/export.
+//!
+//! Converts `GpxData` into a GeoJSON FeatureCollection ready for a
+//! MapLibre GeoJSON source, with styling properties (`kind`, `color`,
+//! `icon`) matching the overlay colors used by the map layer. Also
+//! parses GeoJSON from web planners back into tracks, routes, and
+//! waypoints.
+//!
+//! Point timestamps are carried in a `coordTimes` property, the same
+//! convention used by common GPX-to-GeoJSON converters.
+
+use serde_json::{json, Map, Value};
+
+use crate::gpx::{
+    format_timestamp, in_range, parse_timestamp, GpxData, Point, Route, Track, Waypoint,
+};
+
+/// Line color for tracks.
+const TRACK_COLOR: &str = "#2196F3";
+/// Line color for routes.
+const ROUTE_COLOR: &str = "#FF9800";
+/// Fallback circle color for waypoints without a known icon.
+const WAYPOINT_COLOR: &str = "#F44336";
+
+/// Circle color for a waypoint icon (GPX `<sym>`).
+fn icon_color(icon: Option<&str>) -> &'static str {
+    match icon {
+        Some("fuel") => "#FF9800",
+        Some("food") => "#8BC34A",
+        Some("hotel") => "#9C27B0",
+        Some("photo") => "#00BCD4",
+        Some("danger") => "#F44336",
+        Some("info") => "#2196F3",
+        _ => WAYPOINT_COLOR,
+    }
+}
+
+/// Convert `GpxData` into a GeoJSON FeatureCollection.
+///
+/// Tracks and routes with fewer than 2 points are skipped, since a
+/// LineString needs at least two positions.
+pub fn to_geojson(data: &GpxData) -> Value {
+    let mut features = Vec::new();
+
+    for track in data.tracks.iter().filter(|t| t.points.len() >= 2) {
+        features.push(line_feature(&track.points, track.name.as_deref(), "track", TRACK_COLOR));
+    }
+    for route in data.routes.iter().filter(|r| r.points.len() >= 2) {
+        features.push(line_feature(&route.points, route.name.as_deref(), "route", ROUTE_COLOR));
+    }
+    for wp in &data.waypoints {
+        let mut properties = Map::new();
+        properties.insert("kind".into(), json!("waypoint"));
+        if let Some(name) = &wp.name {
+            properties.insert("name".into(), json!(name));
+        }
+        if let Some(icon) = &wp.icon {
+            properties.insert("icon".into(), json!(icon));
+        }
+        properties.insert("color".into(), json!(icon_color(wp.icon.as_deref())));
+        if let Some(time) = wp.point.time.and_then(format_timestamp) {
+            properties.insert("time".into(), json!(time));
+        }
+        features.push(json!({
+            "type": "Feature",
+            "geometry": { "type": "Point", "coordinates": position(&wp.point) },
+            "properties": properties,
+        }));
+    }
+
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+fn line_feature(points: &[Point], name: Option<&str>, kind: &str, color: &str) -> Value {
+    let mut properties = Map::new();
+    properties.insert("kind".into(), json!(kind));
+    if let Some(name) = name {
+        properties.insert("name".into(), json!(name));
+    }
+    properties.insert("color".into(), json!(color));
+
+    if points.iter().all(|p| p.time.is_some()) {
+        let times: Vec<Value> = points
+            .iter()
+            .map(|p| p.time.and_then(format_timestamp).map_or(Value::Null, Value::from))
+            .collect();
+        properties.insert("coordTimes".into(), Value::Array(times));
+    }
+
+    let coordinates: Vec<Value> = points.iter().map(position).collect();
+    json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": coordinates },
+        "properties": properties,
+    })
+}
+
+/// A GeoJSON position: `[lon, lat]` or `[lon, lat, ele]`.
+fn position(p: &Point) -> Value {
+    match p.ele {
+        Some(ele) => json!([p.lon, p.lat, ele]),
+        None => json!([p.lon, p.lat]),
+    }
+}
+
+/// Parse a GeoJSON document into `GpxData`.
+///
+/// Accepts a FeatureCollection, a single Feature, or a bare geometry.
+/// Points and MultiPoints become waypoints; LineStrings become tracks,
+/// or routes when the feature has `"kind": "route"`. MultiLineStrings
+/// are flattened into a single point list, like multi-segment GPX
+/// tracks. Polygons are ignored.
+pub fn parse_geojson(data: &[u8]) -> Result<GpxData, String> {
+    let root: Value =
+        serde_json::from_slice(data).map_err(|e| format!("GeoJSON parse error: {e}"))?;
+    let mut out = GpxData::default();
+    add_object(&root, &Map::new(), &mut out)?;
+    Ok(out)
+}
+
+fn add_object(
+    value: &Value,
+    properties: &Map<String, Value>,
+    out: &mut GpxData,
+) -> Result<(), String> {
+    let kind = value["type"]
+        .as_str()
+        .ok_or_else(|| "GeoJSON parse error: object without \"type\"".to_string())?;
+
+    match kind {
+        "FeatureCollection" => {
+            let features = value["features"]
+                .as_array()
+                .ok_or_else(|| "GeoJSON parse error: \"features\" is not an array".to_string())?;
+            for feature in features {
+                add_object(feature, &Map::new(), out)?;
+            }
+        }
+        "Feature" => {
+            if value["geometry"].is_null() {
+                return Ok(());
+            }
+            let properties = value["properties"].as_object().cloned().unwrap_or_default();
+            add_object(&value["geometry"], &properties, out)?;
+        }
+        "GeometryCollection" => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                add_object(geometry, properties, out)?;
+            }
+        }
+        "Point" => out.waypoints.push(waypoint(&value["coordinates"], properties)?),
+        "MultiPoint" => {
+            for coords in positions(&value["coordinates"])? {
+                out.waypoints.push(waypoint(coords, properties)?);
+            }
+        }
+        "LineString" => add_line(parse_line(&value["coordinates"])?, properties, out),
+        "MultiLineString" => {
+            let mut points = Vec::new();
+            for line in positions(&value["coordinates"])? {
+                points.extend(parse_line(line)?);
+            }
+            add_line(points, properties, out);
+        }
+        "Polygon" | "MultiPolygon" => {}
+        other => return Err(format!("GeoJSON parse error: unknown type \"{other}\"")),
+    }
+    Ok(())
+}
+
+fn waypoint(coords: &Value, properties: &Map<String, Value>) -> Result<Waypoint, String> {
+    let mut point = position_from_json(coords)?;
+    point.time = properties
+        .get("time")
+        .and_then(Value::as_str)
+        .and_then(parse_timestamp);
+    Ok(Waypoint {
+        name: string_property(properties, "name"),
+        point,
+        icon: string_property(properties, "icon").or_else(|| string_property(properties, "sym")),
+    })
+}
+
+fn add_line(mut points: Vec<Point>, properties: &Map<String, Value>, out: &mut GpxData) {
+    if let Some(times) = properties.get("coordTimes").and_then(Value::as_array) {
+        for (p, t) in points.iter_mut().zip(times) {
+            p.time = t.as_str().and_then(parse_timestamp);
+        }
+    }
+
+    let name = string_property(properties, "name");
+    if properties.get("kind").and_then(Value::as_str) == Some("route") {
+        out.routes.push(Route { name, points });
+    } else {
+        out.tracks.push(Track { name, points });
+    }
+}
+
+fn string_property(properties: &Map<String, Value>, key: &str) -> Option<String> {
+    properties.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+fn positions(value: &Value) -> Result<&Vec<Value>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| "GeoJSON parse error: \"coordinates\" is not an array".to_string())
+}
+
+fn parse_line(value: &Value) -> Result<Vec<Point>, String> {
+    positions(value)?.iter().map(position_from_json).collect()
+}
+
+/// A `[lon, lat, alt?]` position, checked to lie on the globe.
+fn position_from_json(value: &Value) -> Result<Point, String> {
+    let coords = value.as_array().map(Vec::as_slice).unwrap_or_default();
+    match coords {
+        [lon, lat, rest @ ..] => match (lon.as_f64(), lat.as_f64()) {
+            (Some(lon), Some(lat)) if !in_range(lat, lon) => Err(format!(
+                "GeoJSON parse error: position {value} out of range lat={lat} lon={lon}"
+            )),
+            (Some(lon), Some(lat)) => Ok(Point {
+                lat,
+                lon,
+                ele: rest.first().and_then(Value::as_f64),
+                time: None,
+                speed: None,
+                course: None,
+            }),
+            _ => Err(format!("GeoJSON parse error: bad position {value}")),
+        },
+        _ => Err(format!("GeoJSON parse error: bad position {value}")),
+    }
+}
+
+/// Parse GeoJSON and return the result as GPX JSON.
+pub fn parse_to_json(data: &[u8]) -> Result<String, String> {
+    let gpx_data = parse_geojson(data)?;
+    serde_json::to_string(&gpx_data).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// Convert GPX data given as JSON into a GeoJSON FeatureCollection string.
+pub fn json_to_geojson(gpx_json: &str) -> Result<String, String> {
+    let data: GpxData = serde_json::from_str(gpx_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    Ok(to_geojson(&data).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    fn sample_data() -> GpxData {
+        GpxData {
+            tracks: vec![Track {
+                name: Some("Recorded".into()),
+                points: vec![
+                    Point { ele: Some(200.0), time: Some(1000.0), ..pt(48.0, 16.0) },
+                    Point { ele: Some(210.0), time: Some(1010.0), ..pt(48.1, 16.1) },
+                ],
+            }],
+            routes: vec![Route {
+                name: Some("Plan".into()),
+                points: vec![pt(47.0, 15.0), pt(47.5, 15.5)],
+            }],
+            waypoints: vec![Waypoint {
+                name: Some("Station".into()),
+                point: pt(47.2, 11.4),
+                icon: Some("fuel".into()),
+            }],
+            version: None,
+        }
+    }
+
+    #[test]
+    fn export_feature_collection_with_styling() {
+        let geojson = to_geojson(&sample_data());
+
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(features[0]["geometry"]["coordinates"][0], json!([16.0, 48.0, 200.0]));
+        assert_eq!(features[0]["properties"]["color"], TRACK_COLOR);
+        assert_eq!(features[0]["properties"]["coordTimes"][1], "1970-01-01T00:16:50Z");
+
+        assert_eq!(features[1]["properties"]["kind"], "route");
+        assert_eq!(features[1]["properties"]["color"], ROUTE_COLOR);
+        assert!(features[1]["properties"].get("coordTimes").is_none());
+
+        assert_eq!(features[2]["geometry"]["type"], "Point");
+        assert_eq!(features[2]["properties"]["icon"], "fuel");
+        assert_eq!(features[2]["properties"]["color"], "#FF9800");
+    }
+
+    #[test]
+    fn export_skips_single_point_lines() {
+        let data = GpxData {
+            tracks: vec![Track { name: None, points: vec![pt(48.0, 16.0)] }],
+            ..Default::default()
+        };
+        assert!(to_geojson(&data)["features"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = sample_data();
+        let text = to_geojson(&original).to_string();
+        let parsed = parse_geojson(text.as_bytes()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_planner_geojson() {
+        let text = r#"{
+          "type": "FeatureCollection",
+          "features": [
+            { "type": "Feature", "properties": { "name": "Day 1" },
+              "geometry": { "type": "MultiLineString",
+                "coordinates": [[[16.0, 48.0], [16.1, 48.1]], [[16.2, 48.2], [16.3, 48.3]]] } },
+            { "type": "Feature", "properties": null,
+              "geometry": { "type": "MultiPoint", "coordinates": [[11.0, 47.0], [12.0, 47.5]] } },
+            { "type": "Feature", "properties": {},
+              "geometry": { "type": "Polygon", "coordinates": [] } },
+            { "type": "Feature", "properties": {}, "geometry": null }
+          ]
+        }"#;
+
+        let data = parse_geojson(text.as_bytes()).unwrap();
+        assert_eq!(data.tracks.len(), 1);
+        assert_eq!(data.tracks[0].name.as_deref(), Some("Day 1"));
+        assert_eq!(data.tracks[0].points.len(), 4);
+        assert_eq!(data.waypoints.len(), 2);
+        assert_eq!(data.waypoints[1].point.lat, 47.5);
+    }
+
+    #[test]
+    fn parse_bare_geometry() {
+        let text = r#"{ "type": "LineString", "coordinates": [[16.0, 48.0], [16.1, 48.1]] }"#;
+        let data = parse_geojson(text.as_bytes()).unwrap();
+        assert_eq!(data.tracks[0].points.len(), 2);
+    }
+
+    #[test]
+    fn parse_rejects_invalid() {
+        assert!(parse_geojson(b"not json").is_err());
+        assert!(parse_geojson(br#"{ "type": "Point", "coordinates": [1] }"#).is_err());
+        assert!(parse_geojson(br#"{ "features": [] }"#).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_positions() {
+        let line = br#"{ "type": "LineString", "coordinates": [[200.0, 95.0], [16.0, 48.0]] }"#;
+        let err = parse_geojson(line).unwrap_err();
+        assert!(err.contains("out of range"), "{err}");
+        let point = br#"{ "type": "Point", "coordinates": [16.0, -91.0] }"#;
+        assert!(parse_geojson(point).is_err());
+    }
+}
@@ -1,19 +1,70 @@
-//! GPX 1.1 file parsing.
+//! GPX 1.0 and 1.1 file parsing and writing.
 //!
 //! Wraps the `gpx` crate and extracts tracks, routes, and waypoints
 //! into serializable structures that can cross the JNI boundary as JSON
-//! or be used directly by a non-Android frontend.
+//! or be used directly by a non-Android frontend. GPX 1.0 files, and
+//! GPX 1.1 files with Garmin TrackPointExtension data, are read with
+//! the streaming reader instead, which maps their speed and course into
+//! the point model, and the source version is kept so that files can
+//! be written back in it.
+//!
+//! A lenient mode reads the XML event stream directly (see `stream`)
+//! and recovers what it can from damaged or non-conforming files,
+//! reporting each repair as a warning.
 
 use serde::{Deserialize, Serialize};
 use std::io::Read;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use xml::escape::escape_str_pcdata;
+use xml::reader::ParserConfig;
+use xml::Encoding;
+
+use crate::stream::{collect, GpxReader};
 
-/// A geographic coordinate with optional elevation.
+/// A geographic coordinate with optional elevation and timestamp.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Point {
     pub lat: f64,
     pub lon: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ele: Option<f64>,
+    /// Recording time in seconds since the Unix epoch (UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<f64>,
+    /// Ground speed in meters per second, as logged by the receiver.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    /// Course over ground in degrees clockwise from true north.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub course: Option<f64>,
+}
+
+/// Whether a latitude and longitude lie within their valid ranges.
+pub(crate) fn in_range(lat: f64, lon: f64) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+/// Parse latitude and longitude text, as found in GPX attributes and
+/// KML coordinates, into a point without further data.
+///
+/// Fails on unparseable and out-of-range values, with a reason that
+/// callers wrap in their own message.
+pub(crate) fn parse_position(lat: &str, lon: &str) -> Result<Point, String> {
+    let (Ok(lat_v), Ok(lon_v)) = (lat.trim().parse::<f64>(), lon.trim().parse::<f64>()) else {
+        return Err(format!("unparseable lat=\"{lat}\" lon=\"{lon}\""));
+    };
+    if !in_range(lat_v, lon_v) {
+        return Err(format!("out of range lat={lat_v} lon={lon_v}"));
+    }
+    Ok(Point {
+        lat: lat_v,
+        lon: lon_v,
+        ele: None,
+        time: None,
+        speed: None,
+        course: None,
+    })
 }
 
 /// A named sequence of points representing a recorded path.
@@ -46,17 +97,75 @@ pub struct Waypoint {
     pub icon: Option<String>,
 }
 
+/// Geographic bounding box in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl Bounds {
+    /// A zero-size box around a single point.
+    pub fn from_point(p: &Point) -> Self {
+        Self {
+            min_lat: p.lat,
+            min_lon: p.lon,
+            max_lat: p.lat,
+            max_lon: p.lon,
+        }
+    }
+
+    /// Grow the box to include a point.
+    pub fn extend(&mut self, p: &Point) {
+        self.min_lat = self.min_lat.min(p.lat);
+        self.min_lon = self.min_lon.min(p.lon);
+        self.max_lat = self.max_lat.max(p.lat);
+        self.max_lon = self.max_lon.max(p.lon);
+    }
+
+    /// Whether a point lies inside or on the edge of the box.
+    pub fn contains(&self, p: &Point) -> bool {
+        (self.min_lat..=self.max_lat).contains(&p.lat)
+            && (self.min_lon..=self.max_lon).contains(&p.lon)
+    }
+}
+
+/// GPX schema version of a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GpxVersion {
+    #[serde(rename = "1.0")]
+    Gpx10,
+    #[serde(rename = "1.1")]
+    Gpx11,
+}
+
 /// All data extracted from a GPX file.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GpxData {
     pub tracks: Vec<Track>,
     pub routes: Vec<Route>,
     pub waypoints: Vec<Waypoint>,
+    /// Version of the GPX file the data was read from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<GpxVersion>,
 }
 
 /// Parse a GPX file from any reader and return structured data.
-pub fn parse<R: Read>(reader: R) -> Result<GpxData, String> {
-    let gpx = gpx::read(reader).map_err(|e| format!("GPX parse error: {e}"))?;
+///
+/// GPX 1.1 goes through the `gpx` crate; GPX 1.0 goes through the
+/// strict streaming reader, which understands its point elements.
+pub fn parse<R: Read>(mut reader: R) -> Result<GpxData, String> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("GPX read error: {e}"))?;
+    parse_bytes(&bytes)
+}
+
+fn parse_gpx11(data: &[u8]) -> Result<GpxData, String> {
+    let gpx = gpx::read(data).map_err(|e| format!("GPX parse error: {e}"))?;
 
     let tracks = gpx
         .tracks
@@ -66,11 +175,7 @@ pub fn parse<R: Read>(reader: R) -> Result<GpxData, String> {
                 .segments
                 .iter()
                 .flat_map(|seg| seg.points.iter())
-                .map(|wp| Point {
-                    lat: wp.point().y(),
-                    lon: wp.point().x(),
-                    ele: wp.elevation,
-                })
+                .map(point_from)
                 .collect();
 
             Track {
@@ -87,11 +192,7 @@ pub fn parse<R: Read>(reader: R) -> Result<GpxData, String> {
             let points = r
                 .points
                 .iter()
-                .map(|wp| Point {
-                    lat: wp.point().y(),
-                    lon: wp.point().x(),
-                    ele: wp.elevation,
-                })
+                .map(point_from)
                 .collect();
 
             Route {
@@ -106,11 +207,7 @@ pub fn parse<R: Read>(reader: R) -> Result<GpxData, String> {
         .iter()
         .map(|wp| Waypoint {
             name: wp.name.clone(),
-            point: Point {
-                lat: wp.point().y(),
-                lon: wp.point().x(),
-                ele: wp.elevation,
-            },
+            point: point_from(wp),
             icon: wp.symbol.clone(),
         })
         .collect();
@@ -119,12 +216,75 @@ pub fn parse<R: Read>(reader: R) -> Result<GpxData, String> {
         tracks,
         routes,
         waypoints,
+        version: Some(GpxVersion::Gpx11),
     })
 }
 
+fn point_from(wp: &gpx::Waypoint) -> Point {
+    Point {
+        lat: wp.point().y(),
+        lon: wp.point().x(),
+        ele: wp.elevation,
+        time: wp.time.map(|t| unix_seconds(t.into())),
+        speed: wp.speed,
+        course: None,
+    }
+}
+
+fn unix_seconds(t: OffsetDateTime) -> f64 {
+    t.unix_timestamp_nanos() as f64 / 1e9
+}
+
+/// Parse an ISO 8601 timestamp into seconds since the Unix epoch.
+///
+/// Timestamps without a UTC offset, as written by some loggers,
+/// are taken to be UTC.
+pub fn parse_timestamp(s: &str) -> Option<f64> {
+    let s = s.trim();
+    OffsetDateTime::parse(s, &Rfc3339)
+        .or_else(|_| OffsetDateTime::parse(&format!("{s}Z"), &Rfc3339))
+        .ok()
+        .map(unix_seconds)
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+pub fn format_timestamp(t: f64) -> Option<String> {
+    let nanos = (t * 1e9).round() as i128;
+    OffsetDateTime::from_unix_timestamp_nanos(nanos)
+        .ok()?
+        .format(&Rfc3339)
+        .ok()
+}
+
 /// Parse GPX from a byte slice. Convenience wrapper for JNI.
 pub fn parse_bytes(data: &[u8]) -> Result<GpxData, String> {
-    parse(data)
+    parse_version(data, declared_version(data))
+}
+
+/// Parse GPX whose version is already known.
+///
+/// The `gpx` crate skips `<extensions>`, so GPX 1.1 files carrying
+/// Garmin TrackPointExtension speed and course are read by the strict
+/// streaming reader instead, in the same single pass as GPX 1.0.
+pub(crate) fn parse_version(data: &[u8], version: GpxVersion) -> Result<GpxData, String> {
+    let extended = data.windows(TPX_ELEMENT.len()).any(|w| w == TPX_ELEMENT);
+    if version == GpxVersion::Gpx11 && !extended {
+        return parse_gpx11(data);
+    }
+    let mut parsed = collect(&mut GpxReader::new(data))?;
+    parsed.version.get_or_insert(version);
+    Ok(parsed)
+}
+
+const TPX_ELEMENT: &[u8] = b"TrackPointExtension";
+
+/// Version declared by the root element, from its `version` attribute
+/// or namespace; GPX 1.1 when it declares neither.
+fn declared_version(data: &[u8]) -> GpxVersion {
+    let mut reader = GpxReader::new(data);
+    // The root element is read before the first event is returned
+    let _ = reader.next();
+    reader.version().unwrap_or(GpxVersion::Gpx11)
 }
 
 /// Parse GPX and return the result as a JSON string.
@@ -133,6 +293,266 @@ pub fn parse_to_json(data: &[u8]) -> Result<String, String> {
     serde_json::to_string(&gpx_data).map_err(|e| format!("JSON serialize error: {e}"))
 }
 
+// -- Writing --
+
+const GPX10_NS: &str = "http://www.topografix.com/GPX/1/0";
+const GPX11_NS: &str = "http://www.topografix.com/GPX/1/1";
+const TPX_NS: &str = "http://www.garmin.com/xmlschemas/TrackPointExtension/v2";
+
+/// Serialize data as a GPX document of the given version.
+///
+/// Multi-segment tracks were flattened on import, so each track is
+/// written as a single `<trkseg>`. Point speed and course become
+/// `<speed>` and `<course>` elements in GPX 1.0, which has them, and
+/// Garmin TrackPointExtension v2 elements in GPX 1.1, which does not.
+pub fn write_gpx(data: &GpxData, version: GpxVersion) -> String {
+    let (number, ns) = match version {
+        GpxVersion::Gpx10 => ("1.0", GPX10_NS),
+        GpxVersion::Gpx11 => ("1.1", GPX11_NS),
+    };
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<gpx version=\"{number}\" creator=\"NDKarte {}\" xmlns=\"{ns}\"",
+        crate::VERSION
+    ));
+    if version == GpxVersion::Gpx11 {
+        out.push_str(&format!(" xmlns:gpxtpx=\"{TPX_NS}\""));
+    }
+    out.push_str(">\n");
+
+    for wp in &data.waypoints {
+        push_point(&mut out, "wpt", &wp.point, wp.name.as_deref(), wp.icon.as_deref(), version);
+    }
+    for route in &data.routes {
+        out.push_str("<rte>\n");
+        push_name(&mut out, route.name.as_deref());
+        for p in &route.points {
+            push_point(&mut out, "rtept", p, None, None, version);
+        }
+        out.push_str("</rte>\n");
+    }
+    for track in &data.tracks {
+        out.push_str("<trk>\n");
+        push_name(&mut out, track.name.as_deref());
+        out.push_str("<trkseg>\n");
+        for p in &track.points {
+            push_point(&mut out, "trkpt", p, None, None, version);
+        }
+        out.push_str("</trkseg>\n</trk>\n");
+    }
+
+    out.push_str("</gpx>\n");
+    out
+}
+
+/// Convert GPX data given as JSON into a GPX document.
+///
+/// Written in the version the data was read from, or GPX 1.1 when
+/// the data did not come from a GPX file.
+pub fn json_to_gpx(gpx_json: &str) -> Result<String, String> {
+    let data: GpxData = serde_json::from_str(gpx_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    Ok(write_gpx(&data, data.version.unwrap_or(GpxVersion::Gpx11)))
+}
+
+fn push_name(out: &mut String, name: Option<&str>) {
+    if let Some(name) = name {
+        out.push_str(&format!("<name>{}</name>\n", escape_str_pcdata(name)));
+    }
+}
+
+/// Write one point element with its children in schema order.
+fn push_point(
+    out: &mut String,
+    element: &str,
+    p: &Point,
+    name: Option<&str>,
+    icon: Option<&str>,
+    version: GpxVersion,
+) {
+    out.push_str(&format!("<{element} lat=\"{}\" lon=\"{}\">", p.lat, p.lon));
+    if let Some(ele) = p.ele {
+        out.push_str(&format!("<ele>{ele}</ele>"));
+    }
+    if let Some(time) = p.time.and_then(format_timestamp) {
+        out.push_str(&format!("<time>{time}</time>"));
+    }
+    if version == GpxVersion::Gpx10 {
+        if let Some(course) = p.course {
+            out.push_str(&format!("<course>{course}</course>"));
+        }
+        if let Some(speed) = p.speed {
+            out.push_str(&format!("<speed>{speed}</speed>"));
+        }
+    }
+    if let Some(name) = name {
+        out.push_str(&format!("<name>{}</name>", escape_str_pcdata(name)));
+    }
+    if let Some(icon) = icon {
+        out.push_str(&format!("<sym>{}</sym>", escape_str_pcdata(icon)));
+    }
+    if version == GpxVersion::Gpx11 && (p.speed.is_some() || p.course.is_some()) {
+        out.push_str("<extensions><gpxtpx:TrackPointExtension>");
+        if let Some(speed) = p.speed {
+            out.push_str(&format!("<gpxtpx:speed>{speed}</gpxtpx:speed>"));
+        }
+        if let Some(course) = p.course {
+            out.push_str(&format!("<gpxtpx:course>{course}</gpxtpx:course>"));
+        }
+        out.push_str("</gpxtpx:TrackPointExtension></extensions>");
+    }
+    out.push_str(&format!("</{element}>\n"));
+}
+
+// -- Lenient parsing --
+
+/// Category of a problem that [`parse_lenient`] recovered from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Garbage before the first `<` (other than a byte order mark) was removed.
+    StrippedPrefix,
+    /// The file was not valid UTF-8 and was decoded as ISO-8859-1.
+    EncodingFallback,
+    /// A stray `&` or an unknown entity reference was escaped.
+    RepairedEntity,
+    /// A point had missing, malformed or out-of-range coordinates and was dropped.
+    InvalidCoordinate,
+    /// A child value such as `<ele>` could not be parsed and was ignored.
+    InvalidValue,
+    /// A point appeared outside of any track or route and was dropped.
+    SkippedElement,
+    /// The file ended before all elements were closed.
+    Truncated,
+    /// The XML became unreadable; everything after this position was skipped.
+    SkippedContent,
+}
+
+/// A single problem found while parsing leniently.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseWarning {
+    pub kind: WarningKind,
+    /// 1-based line number in the source file, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    pub message: String,
+}
+
+/// Result of [`parse_lenient`]: everything that could be recovered plus
+/// a list of what was repaired or dropped.
+///
+/// Serializes with the `GpxData` fields at the top level so that
+/// consumers of `parse_to_json` can read it unchanged.
+#[derive(Debug, Clone, Serialize)]
+pub struct LenientParse {
+    #[serde(flatten)]
+    pub data: GpxData,
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// Parse GPX from a byte slice, repairing common defects instead of failing.
+///
+/// Handles byte order marks and leading garbage, ISO-8859-1 content,
+/// unescaped ampersands and HTML entities, missing namespaces, GPX 1.0
+/// headers, points with invalid coordinates, and truncated files. Fails
+/// only if no `<gpx>` root element can be found at all.
+pub fn parse_lenient(data: &[u8]) -> Result<LenientParse, String> {
+    let mut warnings = Vec::new();
+    let source = repair_source(data, &mut warnings);
+
+    // The repaired text is always UTF-8, whatever the declaration says
+    let config = ParserConfig::new()
+        .cdata_to_characters(true)
+        .override_encoding(Some(Encoding::Utf8))
+        .ignore_invalid_encoding_declarations(true);
+    let mut reader = GpxReader::with_config(source.as_bytes(), config, true);
+    let data = collect(&mut reader)?;
+    warnings.extend(reader.take_warnings());
+
+    Ok(LenientParse { data, warnings })
+}
+
+/// Parse GPX leniently and return data plus warnings as a JSON string.
+pub fn parse_lenient_to_json(data: &[u8]) -> Result<String, String> {
+    let result = parse_lenient(data)?;
+    serde_json::to_string(&result).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// The entity names predefined by XML 1.0.
+const XML_ENTITIES: [&str; 5] = ["amp", "lt", "gt", "quot", "apos"];
+
+/// Normalize raw file bytes into UTF-8 text that xml-rs can read.
+///
+/// Strips anything before the first `<`, decodes as ISO-8859-1 when
+/// the bytes are not valid UTF-8, and escapes every `&` that does not
+/// start a predefined or numeric entity reference.
+fn repair_source(data: &[u8], warnings: &mut Vec<ParseWarning>) -> String {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let start = data.iter().position(|&b| b == b'<').unwrap_or(data.len());
+    if data[..start].iter().any(|b| !b.is_ascii_whitespace()) {
+        warnings.push(ParseWarning {
+            kind: WarningKind::StrippedPrefix,
+            line: Some(1),
+            message: format!("removed {start} bytes before the XML content"),
+        });
+    }
+    let data = &data[start..];
+
+    let text = match std::str::from_utf8(data) {
+        Ok(s) => s.to_string(),
+        Err(e) => {
+            warnings.push(ParseWarning {
+                kind: WarningKind::EncodingFallback,
+                line: None,
+                message: format!(
+                    "invalid UTF-8 at byte {}, decoded as ISO-8859-1",
+                    e.valid_up_to()
+                ),
+            });
+            data.iter().map(|&b| b as char).collect()
+        }
+    };
+
+    let mut out = String::with_capacity(text.len());
+    let mut line = 1;
+    for (i, c) in text.char_indices() {
+        if c == '\n' {
+            line += 1;
+        }
+        if c == '&' && !is_valid_entity_ref(&text[i + 1..]) {
+            let entity: String = text[i..]
+                .chars()
+                .take_while(|c| !c.is_whitespace())
+                .take(12)
+                .collect();
+            warnings.push(ParseWarning {
+                kind: WarningKind::RepairedEntity,
+                line: Some(line),
+                message: format!("escaped \"{entity}\""),
+            });
+            out.push_str("&amp;");
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Whether the text after an `&` forms a reference xml-rs will accept.
+fn is_valid_entity_ref(rest: &str) -> bool {
+    let Some(end) = rest.find(';').filter(|&end| end <= 12) else {
+        return false;
+    };
+    let name = &rest[..end];
+    if let Some(hex) = name.strip_prefix("#x") {
+        !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else if let Some(dec) = name.strip_prefix('#') {
+        !dec.is_empty() && dec.chars().all(|c| c.is_ascii_digit())
+    } else {
+        XML_ENTITIES.contains(&name)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,6 +658,35 @@ mod tests {
         assert_eq!(data.tracks[0].points[0].ele, None);
     }
 
+    #[test]
+    fn parse_track_point_time() {
+        let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test"
+     xmlns="http://www.topografix.com/GPX/1/1">
+  <trk>
+    <trkseg>
+      <trkpt lat="48.0" lon="16.0"><time>2024-05-01T10:00:00Z</time></trkpt>
+      <trkpt lat="48.1" lon="16.1"><time>2024-05-01T10:00:01.5Z</time></trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+        let strict = parse_bytes(gpx.as_bytes()).unwrap();
+        let lenient = parse_lenient(gpx.as_bytes()).unwrap().data;
+        for data in [strict, lenient] {
+            let points = &data.tracks[0].points;
+            assert_eq!(points[0].time, Some(1_714_557_600.0));
+            assert_eq!(points[1].time, Some(1_714_557_601.5));
+        }
+    }
+
+    #[test]
+    fn parse_timestamp_without_offset_is_utc() {
+        assert_eq!(parse_timestamp("2024-05-01T10:00:00"), Some(1_714_557_600.0));
+        assert_eq!(parse_timestamp("2024-05-01T12:00:00+02:00"), Some(1_714_557_600.0));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
     #[test]
     fn parse_multi_segment_track() {
         let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -260,4 +709,227 @@ mod tests {
         // Multi-segment tracks are flattened into a single point list
         assert_eq!(data.tracks[0].points.len(), 4);
     }
+
+    const GPX_10: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.0" creator="logger" xmlns="http://www.topografix.com/GPX/1/0">
+  <time>2024-05-01T10:00:00Z</time>
+  <wpt lat="48.2" lon="16.3"><name>Cafe</name><sym>Restaurant</sym></wpt>
+  <trk>
+    <name>Commute</name>
+    <trkseg>
+      <trkpt lat="48.2082" lon="16.3738">
+        <ele>171</ele>
+        <time>2024-05-01T10:00:00Z</time>
+        <course>87.5</course>
+        <speed>4.2</speed>
+      </trkpt>
+      <trkpt lat="48.2090" lon="16.3750"><speed>4.4</speed></trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+    #[test]
+    fn parse_gpx_10_speed_and_course() {
+        let data = parse_bytes(GPX_10.as_bytes()).unwrap();
+
+        assert_eq!(data.version, Some(GpxVersion::Gpx10));
+        assert_eq!(data.tracks[0].name.as_deref(), Some("Commute"));
+        let p = &data.tracks[0].points[0];
+        assert_eq!(p.ele, Some(171.0));
+        assert_eq!(p.speed, Some(4.2));
+        assert_eq!(p.course, Some(87.5));
+        assert_eq!(data.tracks[0].points[1].course, None);
+        assert_eq!(data.waypoints[0].icon.as_deref(), Some("Restaurant"));
+    }
+
+    #[test]
+    fn parse_records_gpx_11_version() {
+        let data = parse_bytes(MINIMAL_GPX.as_bytes()).unwrap();
+        assert_eq!(data.version, Some(GpxVersion::Gpx11));
+    }
+
+    #[test]
+    fn write_gpx_10_round_trip() {
+        let data = parse_bytes(GPX_10.as_bytes()).unwrap();
+        let written = write_gpx(&data, GpxVersion::Gpx10);
+        assert!(written.contains("version=\"1.0\""));
+        assert!(written.contains("<course>87.5</course><speed>4.2</speed>"));
+
+        let again = parse_bytes(written.as_bytes()).unwrap();
+        assert_eq!(again.version, Some(GpxVersion::Gpx10));
+        assert_eq!(again.tracks[0].points[0].speed, Some(4.2));
+        assert_eq!(again.tracks[0].points[0].time, Some(1_714_557_600.0));
+        assert_eq!(again.waypoints[0].name.as_deref(), Some("Cafe"));
+    }
+
+    #[test]
+    fn write_gpx_11_keeps_speed_in_extension() {
+        let data = parse_bytes(GPX_10.as_bytes()).unwrap();
+        let written = write_gpx(&data, GpxVersion::Gpx11);
+
+        // The gpx crate must accept the output as valid GPX 1.1
+        let validated = parse_gpx11(written.as_bytes()).unwrap();
+        assert_eq!(validated.tracks[0].points.len(), 2);
+
+        let strict = parse_bytes(written.as_bytes()).unwrap();
+        assert_eq!(strict.version, Some(GpxVersion::Gpx11));
+        assert_eq!(strict.tracks[0].points.len(), 2);
+        assert_eq!(strict.tracks[0].points[0].speed, Some(4.2));
+        assert_eq!(strict.tracks[0].points[0].course, Some(87.5));
+        assert_eq!(strict.tracks[0].points[1].speed, Some(4.4));
+        assert_eq!(strict.tracks[0].points[1].course, None);
+        assert_eq!(strict.waypoints[0].name.as_deref(), Some("Cafe"));
+
+        let streamed = crate::stream::parse_streaming(written.as_bytes()).unwrap();
+        assert_eq!(streamed.tracks[0].points[0].speed, Some(4.2));
+        assert_eq!(streamed.tracks[0].points[0].course, Some(87.5));
+
+        let imported = crate::import::import(written.as_bytes()).unwrap();
+        assert_eq!(imported.data.tracks[0].points[0].speed, Some(4.2));
+    }
+
+    #[test]
+    fn negative_speed_is_unknown() {
+        let gpx = r#"<gpx version="1.0" xmlns="http://www.topografix.com/GPX/1/0">
+  <trk><trkseg>
+    <trkpt lat="48.0" lon="16.0"><speed>-1</speed></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        let strict = parse_bytes(gpx.as_bytes()).unwrap();
+        let lenient = parse_lenient(gpx.as_bytes()).unwrap();
+        assert!(lenient.warnings.is_empty());
+        for data in [strict, lenient.data] {
+            assert_eq!(data.tracks[0].points[0].speed, None);
+        }
+    }
+
+    #[test]
+    fn json_to_gpx_uses_source_version() {
+        let json = parse_to_json(GPX_10.as_bytes()).unwrap();
+        assert!(json.contains("\"version\":\"1.0\""));
+        let written = json_to_gpx(&json).unwrap();
+        assert!(written.contains("xmlns=\"http://www.topografix.com/GPX/1/0\""));
+
+        let plain = r#"{"tracks": [], "routes": [], "waypoints": []}"#;
+        assert!(json_to_gpx(plain).unwrap().contains("version=\"1.1\""));
+    }
+
+    #[test]
+    fn lenient_parses_clean_file_without_warnings() {
+        let result = parse_lenient(MINIMAL_GPX.as_bytes()).unwrap();
+
+        assert!(result.warnings.is_empty());
+        assert_eq!(result.data.tracks[0].points.len(), 3);
+        assert_eq!(result.data.tracks[0].name.as_deref(), Some("Test Track"));
+        assert_eq!(result.data.routes[0].points.len(), 2);
+        assert_eq!(result.data.waypoints[0].icon.as_deref(), Some("fuel"));
+        assert_eq!(result.data.tracks[0].points[0].ele, Some(171.0));
+    }
+
+    #[test]
+    fn lenient_repairs_bom_and_ampersand() {
+        let gpx = "\u{feff}<?xml version=\"1.0\"?>
+<gpx version=\"1.1\" xmlns=\"http://www.topografix.com/GPX/1/1\">
+  <wpt lat=\"48.0\" lon=\"16.0\"><name>Fish & Chips&nbsp;&amp; more</name></wpt>
+</gpx>";
+
+        assert!(parse_bytes(gpx.as_bytes()).is_err());
+        let result = parse_lenient(gpx.as_bytes()).unwrap();
+
+        assert_eq!(
+            result.data.waypoints[0].name.as_deref(),
+            Some("Fish & Chips&nbsp;& more")
+        );
+        let repaired = result
+            .warnings
+            .iter()
+            .filter(|w| w.kind == WarningKind::RepairedEntity)
+            .count();
+        assert_eq!(repaired, 2);
+        assert_eq!(result.warnings[0].line, Some(3));
+    }
+
+    #[test]
+    fn lenient_reads_gpx_10_without_namespace() {
+        let gpx = r#"<gpx version="1.0" creator="logger">
+  <trk><trkseg>
+    <trkpt lat="48.0" lon="16.0"><ele>200</ele><speed>12.5</speed></trkpt>
+    <trkpt lat="48.1" lon="16.1"></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        let result = parse_lenient(gpx.as_bytes()).unwrap();
+        assert!(result.warnings.is_empty());
+        assert_eq!(result.data.tracks[0].points.len(), 2);
+        assert_eq!(result.data.tracks[0].points[0].ele, Some(200.0));
+    }
+
+    #[test]
+    fn lenient_drops_invalid_coordinates() {
+        let gpx = r#"<gpx version="1.1">
+  <trk><trkseg>
+    <trkpt lat="48.0" lon="16.0"><ele>abc</ele></trkpt>
+    <trkpt lat="95.0" lon="16.0"></trkpt>
+    <trkpt lon="16.0"></trkpt>
+    <trkpt lat="48.1" lon="16.1"></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        let result = parse_lenient(gpx.as_bytes()).unwrap();
+        assert_eq!(result.data.tracks[0].points.len(), 2);
+        assert_eq!(result.data.tracks[0].points[0].ele, None);
+
+        let kinds: Vec<_> = result.warnings.iter().map(|w| w.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                WarningKind::InvalidValue,
+                WarningKind::InvalidCoordinate,
+                WarningKind::InvalidCoordinate,
+            ]
+        );
+    }
+
+    #[test]
+    fn lenient_recovers_truncated_file() {
+        let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><name>Cut off</name><trkseg>
+    <trkpt lat="48.0" lon="16.0"></trkpt>
+    <trkpt lat="48.1" lon="16.1"></trkpt>
+    <trkpt lat="48.2" lon="16.2"><ele>3"#;
+
+        let result = parse_lenient(gpx.as_bytes()).unwrap();
+        assert_eq!(result.data.tracks.len(), 1);
+        assert_eq!(result.data.tracks[0].name.as_deref(), Some("Cut off"));
+        assert_eq!(result.data.tracks[0].points.len(), 3);
+        assert_eq!(result.warnings.last().unwrap().kind, WarningKind::Truncated);
+    }
+
+    #[test]
+    fn lenient_decodes_latin1() {
+        let mut gpx = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><gpx><wpt lat=\"47.0\" lon=\"11.0\"><name>".to_vec();
+        gpx.extend_from_slice(b"Gasthof Kr\xF6ll");
+        gpx.extend_from_slice(b"</name></wpt></gpx>");
+
+        let result = parse_lenient(&gpx).unwrap();
+        assert_eq!(result.data.waypoints[0].name.as_deref(), Some("Gasthof Kröll"));
+        assert_eq!(result.warnings[0].kind, WarningKind::EncodingFallback);
+    }
+
+    #[test]
+    fn lenient_rejects_non_gpx() {
+        assert!(parse_lenient(b"not xml at all").is_err());
+        assert!(parse_lenient(b"<kml><Document/></kml>").is_err());
+    }
+
+    #[test]
+    fn lenient_json_has_gpx_data_fields() {
+        let json = parse_lenient_to_json(MINIMAL_GPX.as_bytes()).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert!(parsed["tracks"].is_array());
+        assert!(parsed["warnings"].is_array());
+    }
 }
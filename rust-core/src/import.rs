@@ -0,0 +1,277 @@
+//! Unified import with file format detection.
+//!
+//! Sniffs the content of a file, never its name, and dispatches to the
+//! matching parser. Gzip-compressed files (e.g. `.gpx.gz`) are
+//! decompressed first and the inner content is sniffed the same way.
+
+use serde::Serialize;
+use std::io::Read;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::gpx::{GpxData, GpxVersion};
+
+/// Upper bound for decompressed gzip content, so that a tiny malicious
+/// archive cannot exhaust memory.
+const MAX_DECOMPRESSED_BYTES: u64 = 512 * 1024 * 1024;
+
+/// How many leading bytes of a text file are inspected to find the
+/// root element or the first JSON token.
+const SNIFF_LEN: usize = 4096;
+
+/// A file format recognized by [`detect_format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Gpx10,
+    Gpx11,
+    Kml,
+    Kmz,
+    #[serde(rename = "geojson")]
+    GeoJson,
+    Fit,
+    Tcx,
+}
+
+/// Result of [`import`]: the detected format and the parsed data.
+#[derive(Debug, Clone, Serialize)]
+pub struct Import {
+    pub format: Format,
+    /// Whether the file was gzip-compressed.
+    pub compressed: bool,
+    #[serde(flatten)]
+    pub data: GpxData,
+}
+
+/// Detect the format of uncompressed file content.
+///
+/// Returns `None` for content that matches none of the known formats,
+/// including gzip data (see [`is_gzip`]).
+pub fn detect_format(data: &[u8]) -> Option<Format> {
+    if crate::fit::is_fit(data) {
+        return Some(Format::Fit);
+    }
+    if data.starts_with(b"PK\x03\x04") {
+        return Some(Format::Kmz);
+    }
+
+    let head = &data[..data.len().min(SNIFF_LEN)];
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+
+    if text.starts_with('{') || text.starts_with('[') {
+        return Some(Format::GeoJson);
+    }
+
+    let (root, tag) = root_element(text)?;
+    match root {
+        "gpx" if is_gpx_10(tag) => Some(Format::Gpx10),
+        "gpx" => Some(Format::Gpx11),
+        "kml" => Some(Format::Kml),
+        "TrainingCenterDatabase" => Some(Format::Tcx),
+        _ => None,
+    }
+}
+
+/// Whether the bytes start with the gzip magic number.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Detect the format of a file and parse it into `GpxData`.
+pub fn import(data: &[u8]) -> Result<Import, String> {
+    if is_gzip(data) {
+        let inflated = gunzip(data)?;
+        let (format, data) = parse_detected(&inflated)?;
+        return Ok(Import {
+            format,
+            compressed: true,
+            data,
+        });
+    }
+
+    let (format, data) = parse_detected(data)?;
+    Ok(Import {
+        format,
+        compressed: false,
+        data,
+    })
+}
+
+/// Import a file and return the result as a JSON string.
+pub fn import_to_json(data: &[u8]) -> Result<String, String> {
+    let imported = import(data)?;
+    serde_json::to_string(&imported).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+fn parse_detected(data: &[u8]) -> Result<(Format, GpxData), String> {
+    let format = detect_format(data).ok_or_else(|| {
+        "Unrecognized file format: expected GPX, KML, KMZ, GeoJSON, FIT or TCX".to_string()
+    })?;
+
+    let gpx_data = match format {
+        Format::Gpx10 => crate::gpx::parse_version(data, GpxVersion::Gpx10)?,
+        Format::Gpx11 => crate::gpx::parse_version(data, GpxVersion::Gpx11)?,
+        Format::Kml => crate::kml::parse_kml(data)?,
+        Format::Kmz => crate::kml::parse_kmz(data)?,
+        Format::GeoJson => crate::geojson::parse_geojson(data)?,
+        Format::Fit => crate::fit::parse_fit(data)?,
+        Format::Tcx => crate::tcx::parse_tcx(data)?,
+    };
+    Ok((format, gpx_data))
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    MultiGzDecoder::new(data)
+        .take(MAX_DECOMPRESSED_BYTES + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| format!("Gzip decompression failed: {e}"))?;
+    if out.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err("Gzip decompression failed: content too large".to_string());
+    }
+    Ok(out)
+}
+
+/// Find the root element of an XML document.
+///
+/// Skips the XML declaration, processing instructions, comments and a
+/// DOCTYPE. Returns the local name (namespace prefix removed) and the
+/// full start tag text, or `None` if the text is not XML.
+fn root_element(text: &str) -> Option<(&str, &str)> {
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("<?") {
+            rest = &after[after.find("?>")? + 2..];
+        } else if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->")? + 3..];
+        } else if let Some(after) = rest.strip_prefix("<!") {
+            rest = &after[after.find('>')? + 1..];
+        } else if rest.starts_with('<') {
+            break;
+        } else {
+            return None;
+        }
+    }
+
+    let tag = match rest.find('>') {
+        Some(end) => &rest[..end],
+        None => rest,
+    };
+    let name_end = tag[1..]
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .map_or(tag.len(), |i| i + 1);
+    let name = &tag[1..name_end];
+    let local = name.rsplit(':').next().unwrap_or(name);
+    if local.is_empty() {
+        return None;
+    }
+    Some((local, tag))
+}
+
+/// Whether a `<gpx ...>` start tag declares GPX 1.0, by its version
+/// attribute or, failing that, its namespace.
+fn is_gpx_10(tag: &str) -> bool {
+    let compact: String = tag.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.contains("version=\"1.0\"")
+        || compact.contains("version='1.0'")
+        || (!compact.contains("version=") && compact.contains("topografix.com/GPX/1/0"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const GPX_11: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- exported -->
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="48.2" lon="16.3"><name>Start</name></wpt>
+</gpx>"#;
+
+    const GPX_10: &str = r#"<?xml version="1.0"?>
+<gpx version="1.0" creator="logger" xmlns="http://www.topografix.com/GPX/1/0">
+  <wpt lat="48.2" lon="16.3"/>
+</gpx>"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn detects_xml_formats() {
+        assert_eq!(detect_format(GPX_11.as_bytes()), Some(Format::Gpx11));
+        assert_eq!(detect_format(GPX_10.as_bytes()), Some(Format::Gpx10));
+        assert_eq!(
+            detect_format(b"<kml:kml xmlns:kml=\"http://www.opengis.net/kml/2.2\"/>"),
+            Some(Format::Kml)
+        );
+        assert_eq!(
+            detect_format(b"\xef\xbb\xbf<TrainingCenterDatabase>"),
+            Some(Format::Tcx)
+        );
+        assert_eq!(detect_format(b"<html><body/></html>"), None);
+    }
+
+    #[test]
+    fn detects_gpx_10_by_namespace() {
+        let gpx = r#"<gpx xmlns="http://www.topografix.com/GPX/1/0"></gpx>"#;
+        assert_eq!(detect_format(gpx.as_bytes()), Some(Format::Gpx10));
+    }
+
+    #[test]
+    fn detects_binary_and_json_formats() {
+        assert_eq!(detect_format(b"  {\"type\": \"FeatureCollection\"}"), Some(Format::GeoJson));
+        assert_eq!(detect_format(b"PK\x03\x04rest"), Some(Format::Kmz));
+        let fit = [14, 0x10, 0, 0, 0, 0, 0, 0, b'.', b'F', b'I', b'T', 0, 0];
+        assert_eq!(detect_format(&fit), Some(Format::Fit));
+        assert_eq!(detect_format(b""), None);
+        assert_eq!(detect_format(b"lat,lon\n48.2,16.3"), None);
+    }
+
+    #[test]
+    fn import_gpx_reports_format() {
+        let imported = import(GPX_11.as_bytes()).unwrap();
+        assert_eq!(imported.format, Format::Gpx11);
+        assert!(!imported.compressed);
+        assert_eq!(imported.data.waypoints[0].name.as_deref(), Some("Start"));
+    }
+
+    #[test]
+    fn import_geojson() {
+        let geojson = br#"{"type": "Feature", "properties": {},
+            "geometry": {"type": "LineString", "coordinates": [[16.3, 48.2], [16.4, 48.3]]}}"#;
+        let imported = import(geojson).unwrap();
+        assert_eq!(imported.format, Format::GeoJson);
+        assert_eq!(imported.data.tracks[0].points.len(), 2);
+    }
+
+    #[test]
+    fn import_gzipped_gpx() {
+        let imported = import(&gzip(GPX_11.as_bytes())).unwrap();
+        assert_eq!(imported.format, Format::Gpx11);
+        assert!(imported.compressed);
+        assert_eq!(imported.data.waypoints.len(), 1);
+    }
+
+    #[test]
+    fn import_rejects_unknown_content() {
+        let err = import(b"just some text").unwrap_err();
+        assert!(err.starts_with("Unrecognized file format"));
+        assert!(import(&[0x1f, 0x8b, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn import_json_flattens_data() {
+        let json = import_to_json(GPX_10.as_bytes()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["format"], "gpx10");
+        assert_eq!(value["compressed"], false);
+        assert!(value["waypoints"].is_array());
+    }
+}
@@ -0,0 +1,555 @@
+//! KML and KMZ import/export.
+//!
+//! Maps Google Earth placemarks onto `GpxData`: `<Point>` becomes a
+//! waypoint, `<LineString>` and `<gx:Track>` become tracks (gx:Track
+//! keeps its timestamps). Geometries nested in `<MultiGeometry>` and
+//! `<Folder>`s are flattened. KMZ archives are unzipped in memory and
+//! the first `.kml` entry (normally `doc.kml`) is read.
+//!
+//! Export writes one placemark per track, route, and waypoint. Routes
+//! and waypoint icons are tagged in `<ExtendedData>` so that an
+//! exported file reads back into the same structure.
+
+use std::io::{Cursor, Read, Write};
+use xml::escape::escape_str_pcdata;
+use xml::reader::{ParserConfig, XmlEvent};
+
+use crate::gpx::{
+    format_timestamp, parse_position, parse_timestamp, GpxData, Point, Route, Track, Waypoint,
+};
+
+/// `<Data name=...>` key marking a placemark as a route or track.
+const TYPE_KEY: &str = "type";
+/// `<Data name=...>` key carrying the waypoint icon (GPX `<sym>`).
+const SYM_KEY: &str = "sym";
+
+/// Parse a KML document.
+pub fn parse_kml(data: &[u8]) -> Result<GpxData, String> {
+    let config = ParserConfig::new()
+        .cdata_to_characters(true)
+        .trim_whitespace(true);
+    let mut reader = config.create_reader(data);
+    let mut builder = KmlBuilder::default();
+    let mut seen_root = false;
+
+    loop {
+        match reader.next() {
+            Ok(XmlEvent::EndDocument) => break,
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                if !seen_root {
+                    if name.local_name != "kml" {
+                        return Err(format!(
+                            "KML parse error: root element is <{}>, not <kml>",
+                            name.local_name
+                        ));
+                    }
+                    seen_root = true;
+                }
+                let data_name = attributes
+                    .iter()
+                    .find(|a| a.name.local_name == "name")
+                    .map(|a| a.value.clone());
+                builder.start(name.local_name, data_name);
+            }
+            Ok(XmlEvent::Characters(s)) => builder.text.push_str(&s),
+            Ok(XmlEvent::EndElement { .. }) => builder.end()?,
+            Ok(_) => {}
+            Err(e) => return Err(format!("KML parse error: {e}")),
+        }
+    }
+
+    Ok(builder.data)
+}
+
+/// Parse a KMZ archive (zipped KML).
+pub fn parse_kmz(data: &[u8]) -> Result<GpxData, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|e| format!("KMZ archive error: {e}"))?;
+
+    let entry = (0..archive.len())
+        .filter_map(|i| archive.name_for_index(i).map(|n| (i, n.to_string())))
+        .filter(|(_, n)| n.to_ascii_lowercase().ends_with(".kml"))
+        .min_by_key(|(_, n)| (n != "doc.kml", n.matches('/').count()))
+        .map(|(i, _)| i)
+        .ok_or_else(|| "KMZ archive contains no .kml file".to_string())?;
+
+    let mut kml = Vec::new();
+    archive
+        .by_index(entry)
+        .and_then(|mut f| f.read_to_end(&mut kml).map_err(Into::into))
+        .map_err(|e| format!("KMZ archive error: {e}"))?;
+    parse_kml(&kml)
+}
+
+/// Write `GpxData` as a KML document.
+pub fn write_kml(data: &GpxData, name: Option<&str>) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<kml xmlns=\"http://www.opengis.net/kml/2.2\" \
+         xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n",
+    );
+    out.push_str("<Document>\n");
+    if let Some(name) = name {
+        out.push_str(&format!("<name>{}</name>\n", escape_str_pcdata(name)));
+    }
+
+    for wp in &data.waypoints {
+        out.push_str("<Placemark>\n");
+        push_name(&mut out, wp.name.as_deref());
+        if let Some(icon) = &wp.icon {
+            push_extended_data(&mut out, SYM_KEY, icon);
+        }
+        out.push_str(&format!(
+            "<Point><coordinates>{}</coordinates></Point>\n",
+            format_coordinate(&wp.point)
+        ));
+        out.push_str("</Placemark>\n");
+    }
+
+    for track in &data.tracks {
+        out.push_str("<Placemark>\n");
+        push_name(&mut out, track.name.as_deref());
+        // gx:Track pairs every <when> with a <gx:coord>, so it needs a
+        // writable time on every point
+        let whens: Option<Vec<String>> =
+            track.points.iter().map(|p| p.time.and_then(format_timestamp)).collect();
+        match whens {
+            Some(whens) if !whens.is_empty() => push_gx_track(&mut out, &track.points, &whens),
+            _ => push_line_string(&mut out, &track.points),
+        }
+        out.push_str("</Placemark>\n");
+    }
+
+    for route in &data.routes {
+        out.push_str("<Placemark>\n");
+        push_name(&mut out, route.name.as_deref());
+        push_extended_data(&mut out, TYPE_KEY, "route");
+        push_line_string(&mut out, &route.points);
+        out.push_str("</Placemark>\n");
+    }
+
+    out.push_str("</Document>\n</kml>\n");
+    out
+}
+
+/// Write `GpxData` as a KMZ archive containing a single `doc.kml`.
+pub fn write_kmz(data: &GpxData, name: Option<&str>) -> Result<Vec<u8>, String> {
+    let kml = write_kml(data, name);
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    zip.start_file("doc.kml", options)
+        .map_err(|e| format!("KMZ write error: {e}"))?;
+    zip.write_all(kml.as_bytes())
+        .map_err(|e| format!("KMZ write error: {e}"))?;
+    let cursor = zip.finish().map_err(|e| format!("KMZ write error: {e}"))?;
+    Ok(cursor.into_inner())
+}
+
+/// Parse KML or KMZ and return the result as a JSON string.
+///
+/// The format is chosen by the ZIP signature, not the file name.
+pub fn parse_to_json(data: &[u8]) -> Result<String, String> {
+    let gpx_data = if data.starts_with(b"PK\x03\x04") {
+        parse_kmz(data)?
+    } else {
+        parse_kml(data)?
+    };
+    serde_json::to_string(&gpx_data).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// Convert GPX data given as JSON into a KML document.
+pub fn json_to_kml(gpx_json: &str) -> Result<String, String> {
+    let data: GpxData = serde_json::from_str(gpx_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    Ok(write_kml(&data, None))
+}
+
+fn push_name(out: &mut String, name: Option<&str>) {
+    if let Some(name) = name {
+        out.push_str(&format!("<name>{}</name>\n", escape_str_pcdata(name)));
+    }
+}
+
+fn push_extended_data(out: &mut String, key: &str, value: &str) {
+    out.push_str(&format!(
+        "<ExtendedData><Data name=\"{key}\"><value>{}</value></Data></ExtendedData>\n",
+        escape_str_pcdata(value)
+    ));
+}
+
+fn push_line_string(out: &mut String, points: &[Point]) {
+    out.push_str("<LineString><tessellate>1</tessellate><coordinates>\n");
+    for p in points {
+        out.push_str(&format_coordinate(p));
+        out.push('\n');
+    }
+    out.push_str("</coordinates></LineString>\n");
+}
+
+/// Write a timed track; `whens` holds the formatted time of each point.
+/// Points without an elevation get a two-value coordinate.
+fn push_gx_track(out: &mut String, points: &[Point], whens: &[String]) {
+    out.push_str("<gx:Track>\n");
+    for when in whens {
+        out.push_str(&format!("<when>{when}</when>\n"));
+    }
+    for p in points {
+        match p.ele {
+            Some(ele) => out.push_str(&format!("<gx:coord>{} {} {ele}</gx:coord>\n", p.lon, p.lat)),
+            None => out.push_str(&format!("<gx:coord>{} {}</gx:coord>\n", p.lon, p.lat)),
+        }
+    }
+    out.push_str("</gx:Track>\n");
+}
+
+/// Format a point as a KML `lon,lat[,alt]` tuple.
+fn format_coordinate(p: &Point) -> String {
+    match p.ele {
+        Some(ele) => format!("{},{},{}", p.lon, p.lat, ele),
+        None => format!("{},{}", p.lon, p.lat),
+    }
+}
+
+/// Parse a whitespace-separated list of `lon,lat[,alt]` tuples.
+fn parse_coordinates(text: &str) -> Result<Vec<Point>, String> {
+    text.split_whitespace()
+        .map(|tuple| {
+            let values: Vec<&str> = tuple.split(',').collect();
+            parse_tuple(&values)
+                .map_err(|e| format!("KML parse error: bad coordinate \"{tuple}\": {e}"))
+        })
+        .collect()
+}
+
+/// Build a point from `lon`, `lat` and an optional altitude.
+fn parse_tuple(values: &[&str]) -> Result<Point, String> {
+    let [lon, lat, rest @ ..] = values else {
+        return Err("missing lat/lon".to_string());
+    };
+    Ok(Point {
+        ele: rest.first().and_then(|alt| alt.parse().ok()),
+        ..parse_position(lat, lon)?
+    })
+}
+
+/// Geometry collected from one placemark.
+enum Geometry {
+    Point(Point),
+    Line(Vec<Point>),
+}
+
+/// Accumulates placemarks from KML events.
+#[derive(Default)]
+struct KmlBuilder {
+    data: GpxData,
+    path: Vec<String>,
+    text: String,
+    in_placemark: bool,
+    name: Option<String>,
+    data_key: Option<String>,
+    is_route: bool,
+    icon: Option<String>,
+    geometries: Vec<Geometry>,
+    whens: Vec<Option<f64>>,
+    coords: Vec<Point>,
+}
+
+impl KmlBuilder {
+    fn start(&mut self, local: String, data_name: Option<String>) {
+        self.text.clear();
+        match local.as_str() {
+            "Placemark" => {
+                self.in_placemark = true;
+                self.name = None;
+                self.is_route = false;
+                self.icon = None;
+                self.geometries.clear();
+            }
+            "Data" => self.data_key = data_name,
+            "Track" => {
+                self.whens.clear();
+                self.coords.clear();
+            }
+            _ => {}
+        }
+        self.path.push(local);
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        let Some(local) = self.path.pop() else {
+            return Ok(());
+        };
+        let parent = self.path.last().map(String::as_str);
+        let text = std::mem::take(&mut self.text);
+
+        match (local.as_str(), parent) {
+            ("name", Some("Placemark")) => self.name = Some(text),
+            ("value", Some("Data")) => match self.data_key.as_deref() {
+                Some(TYPE_KEY) => self.is_route = text == "route",
+                Some(SYM_KEY) => self.icon = Some(text),
+                _ => {}
+            },
+            ("coordinates", Some("Point")) if self.in_placemark => {
+                if let Some(p) = parse_coordinates(&text)?.into_iter().next() {
+                    self.geometries.push(Geometry::Point(p));
+                }
+            }
+            ("coordinates", Some("LineString")) if self.in_placemark => {
+                self.geometries.push(Geometry::Line(parse_coordinates(&text)?));
+            }
+            ("when", Some("Track")) => self.whens.push(parse_timestamp(&text)),
+            ("coord", Some("Track")) => {
+                let values: Vec<&str> = text.split_whitespace().collect();
+                let point = parse_tuple(&values)
+                    .map_err(|e| format!("KML parse error: bad gx:coord \"{text}\": {e}"))?;
+                self.coords.push(point);
+            }
+            ("Track", _) if self.in_placemark => {
+                let mut points = std::mem::take(&mut self.coords);
+                for (p, when) in points.iter_mut().zip(&self.whens) {
+                    p.time = *when;
+                }
+                self.geometries.push(Geometry::Line(points));
+            }
+            ("Placemark", _) => {
+                self.in_placemark = false;
+                self.finish_placemark();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish_placemark(&mut self) {
+        for geometry in std::mem::take(&mut self.geometries) {
+            match geometry {
+                Geometry::Point(point) => self.data.waypoints.push(Waypoint {
+                    name: self.name.clone(),
+                    point,
+                    icon: self.icon.clone(),
+                }),
+                Geometry::Line(points) if self.is_route => self.data.routes.push(Route {
+                    name: self.name.clone(),
+                    points,
+                }),
+                Geometry::Line(points) => self.data.tracks.push(Track {
+                    name: self.name.clone(),
+                    points,
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOGLE_EARTH_KML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+<Document>
+  <name>Alpine tour</name>
+  <Folder>
+    <name>Stops</name>
+    <Placemark>
+      <name>Fuel &amp; Coffee</name>
+      <Point><coordinates>11.39,47.26,574</coordinates></Point>
+    </Placemark>
+  </Folder>
+  <Placemark>
+    <name>Stelvio</name>
+    <LineString>
+      <tessellate>1</tessellate>
+      <coordinates>
+        10.45,46.53,1200 10.46,46.52,1500
+        10.47,46.51,2757
+      </coordinates>
+    </LineString>
+  </Placemark>
+  <Placemark>
+    <name>Recorded</name>
+    <gx:Track>
+      <when>2024-05-01T10:00:00Z</when>
+      <when>2024-05-01T10:00:10Z</when>
+      <gx:coord>16.0 48.0 200</gx:coord>
+      <gx:coord>16.001 48.001 201</gx:coord>
+    </gx:Track>
+  </Placemark>
+  <Placemark>
+    <name>Two parts</name>
+    <MultiGeometry>
+      <LineString><coordinates>1,2 3,4</coordinates></LineString>
+      <LineString><coordinates>5,6 7,8</coordinates></LineString>
+    </MultiGeometry>
+  </Placemark>
+</Document>
+</kml>"#;
+
+    #[test]
+    fn parse_point_placemark() {
+        let data = parse_kml(GOOGLE_EARTH_KML.as_bytes()).unwrap();
+
+        assert_eq!(data.waypoints.len(), 1);
+        let wp = &data.waypoints[0];
+        assert_eq!(wp.name.as_deref(), Some("Fuel & Coffee"));
+        assert_eq!((wp.point.lat, wp.point.lon, wp.point.ele), (47.26, 11.39, Some(574.0)));
+    }
+
+    #[test]
+    fn parse_line_string_as_track() {
+        let data = parse_kml(GOOGLE_EARTH_KML.as_bytes()).unwrap();
+
+        let track = &data.tracks[0];
+        assert_eq!(track.name.as_deref(), Some("Stelvio"));
+        assert_eq!(track.points.len(), 3);
+        assert_eq!(track.points[2].ele, Some(2757.0));
+        assert!(data.routes.is_empty());
+    }
+
+    #[test]
+    fn parse_gx_track_with_times() {
+        let data = parse_kml(GOOGLE_EARTH_KML.as_bytes()).unwrap();
+
+        let track = &data.tracks[1];
+        assert_eq!(track.name.as_deref(), Some("Recorded"));
+        assert_eq!(track.points.len(), 2);
+        assert_eq!(track.points[0].lat, 48.0);
+        assert_eq!(track.points[1].time, Some(1_714_557_610.0));
+    }
+
+    #[test]
+    fn parse_multi_geometry_splits_tracks() {
+        let data = parse_kml(GOOGLE_EARTH_KML.as_bytes()).unwrap();
+
+        assert_eq!(data.tracks.len(), 4);
+        assert_eq!(data.tracks[3].name.as_deref(), Some("Two parts"));
+        assert_eq!(data.tracks[3].points[0].lat, 6.0);
+    }
+
+    #[test]
+    fn parse_rejects_non_kml() {
+        assert!(parse_kml(b"<gpx></gpx>").is_err());
+        assert!(parse_kml(b"not xml").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_bad_coordinates() {
+        let kml = "<kml><Placemark><Point><coordinates>abc</coordinates></Point></Placemark></kml>";
+        assert!(parse_kml(kml.as_bytes()).is_err());
+        let kml = "<kml><Placemark><Point><coordinates>16,95</coordinates></Point></Placemark></kml>";
+        let err = parse_kml(kml.as_bytes()).unwrap_err();
+        assert!(err.contains("out of range"), "{err}");
+    }
+
+    fn sample_data() -> GpxData {
+        let pt = |lat, lon, time| Point {
+            lat,
+            lon,
+            ele: Some(100.0),
+            time,
+            speed: None,
+            course: None,
+        };
+        GpxData {
+            tracks: vec![
+                Track {
+                    name: Some("Timed".into()),
+                    points: vec![pt(48.0, 16.0, Some(1000.0)), pt(48.1, 16.1, Some(1010.0))],
+                },
+                Track {
+                    name: Some("Plain <1>".into()),
+                    points: vec![pt(48.0, 16.0, None), pt(48.1, 16.1, None)],
+                },
+            ],
+            routes: vec![Route {
+                name: Some("Plan".into()),
+                points: vec![pt(47.0, 15.0, None), pt(47.5, 15.5, None)],
+            }],
+            waypoints: vec![Waypoint {
+                name: Some("Hut".into()),
+                point: pt(47.2, 11.4, None),
+                icon: Some("hotel".into()),
+            }],
+            version: None,
+        }
+    }
+
+    #[test]
+    fn kml_round_trip() {
+        let original = sample_data();
+        let kml = write_kml(&original, Some("Export"));
+        let parsed = parse_kml(kml.as_bytes()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn timed_track_without_elevation_round_trip() {
+        let pt = |lat, ele, time| Point { lat, lon: 16.0, ele, time, speed: None, course: None };
+        let original = GpxData {
+            tracks: vec![
+                Track {
+                    name: None,
+                    points: vec![pt(48.0, None, Some(1000.0)), pt(48.1, Some(120.0), Some(1010.0))],
+                },
+                // A time that cannot be written falls back to a plain line
+                Track {
+                    name: None,
+                    points: vec![pt(48.0, None, Some(1000.0)), pt(48.1, None, Some(1e20))],
+                },
+            ],
+            ..Default::default()
+        };
+        let kml = write_kml(&original, None);
+        assert!(!kml.contains(" 0</gx:coord>"));
+        let parsed = parse_kml(kml.as_bytes()).unwrap();
+
+        let timed = &parsed.tracks[0].points;
+        assert_eq!(timed[0].ele, None);
+        assert_eq!(timed[0].time, Some(1000.0));
+        assert_eq!(timed[1].ele, Some(120.0));
+        assert_eq!(timed[1].time, Some(1010.0));
+        let plain = &parsed.tracks[1].points;
+        assert_eq!((plain[0].lat, plain[1].lat), (48.0, 48.1));
+        assert!(plain.iter().all(|p| p.ele.is_none() && p.time.is_none()));
+    }
+
+    #[test]
+    fn kmz_round_trip() {
+        let original = sample_data();
+        let kmz = write_kmz(&original, None).unwrap();
+        assert!(kmz.starts_with(b"PK\x03\x04"));
+
+        let parsed = parse_kmz(&kmz).unwrap();
+        assert_eq!(parsed.tracks.len(), 2);
+        assert_eq!(parsed.routes.len(), 1);
+        assert_eq!(parsed.waypoints[0].icon.as_deref(), Some("hotel"));
+    }
+
+    #[test]
+    fn parse_to_json_detects_kmz() {
+        let kmz = write_kmz(&sample_data(), None).unwrap();
+        let json = parse_to_json(&kmz).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["routes"][0]["name"], "Plan");
+
+        let json = parse_to_json(GOOGLE_EARTH_KML.as_bytes()).unwrap();
+        assert!(json.contains("Stelvio"));
+    }
+
+    #[test]
+    fn kmz_without_kml_is_error() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("image.png", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"png").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        assert!(parse_kmz(&bytes).is_err());
+    }
+}
@@ -1,7 +1,25 @@
 pub mod android_jni;
+pub mod binary;
+pub mod climb;
 pub mod convert;
+pub mod dem;
+pub mod deviation;
+pub mod edit;
+pub mod elevation;
+pub mod fit;
+pub mod geodesic;
+pub mod geojson;
 pub mod gpx;
+pub mod import;
+pub mod kml;
+pub mod merge;
 pub mod nav;
+pub mod profile;
 pub mod route_nav;
+pub mod similarity;
+pub mod stream;
+pub mod tcx;
+pub mod terrain;
+pub mod validate;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,305 @@
+//! Merging several GPX files into one.
+//!
+//! Syncing and importing from different planners tends to produce the
+//! same tour and the same waypoints in several files. Merging keeps one
+//! copy of each: tracks and routes count as duplicates when their
+//! Hausdorff distance stays within a tolerance, waypoints when they lie
+//! within a radius of each other and their names do not disagree.
+
+use serde::Serialize;
+
+use crate::gpx::{Bounds, GpxData, Point, Waypoint};
+use crate::nav::haversine;
+use crate::similarity::directed_hausdorff;
+use crate::validate::Collection;
+
+/// Thresholds for duplicate detection.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeOptions {
+    /// Largest Hausdorff distance between two duplicate tracks or
+    /// routes, in meters. Covers GPS noise and differently placed
+    /// route points.
+    pub track_tolerance_m: f64,
+    /// Largest distance between two duplicate waypoints, in meters.
+    pub waypoint_radius_m: f64,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            track_tolerance_m: 30.0,
+            waypoint_radius_m: 25.0,
+        }
+    }
+}
+
+/// An item left out of the merge because it repeats a kept one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Duplicate {
+    pub collection: Collection,
+    /// Index of the input file the duplicate came from.
+    pub file_index: usize,
+    /// Index of the duplicate within its collection in that file.
+    pub item_index: usize,
+    /// Index of the kept item within its collection in the merged data.
+    pub kept_index: usize,
+    /// Hausdorff distance for tracks and routes, plain distance for
+    /// waypoints, in meters.
+    pub distance_m: f64,
+}
+
+/// Result of [`merge`].
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeReport {
+    pub data: GpxData,
+    pub duplicates: Vec<Duplicate>,
+}
+
+/// Merge files in order, dropping duplicates.
+///
+/// Of two duplicate tracks or routes the one with more points is kept,
+/// in the slot of the one seen first; a missing name is taken from the
+/// other. Of two duplicate waypoints the first is kept and fills its
+/// missing name, icon and elevation from the other.
+pub fn merge(files: &[GpxData], options: &MergeOptions) -> MergeReport {
+    let mut data = GpxData::default();
+    let mut duplicates = Vec::new();
+
+    for (file_index, file) in files.iter().enumerate() {
+        for (item_index, track) in file.tracks.iter().enumerate() {
+            let lines: Vec<&[Point]> = data.tracks.iter().map(|t| t.points.as_slice()).collect();
+            match find_duplicate_line(&lines, &track.points, options.track_tolerance_m) {
+                Some((kept_index, distance_m)) => {
+                    let kept = &mut data.tracks[kept_index];
+                    if track.points.len() > kept.points.len() {
+                        kept.points = track.points.clone();
+                    }
+                    if kept.name.is_none() {
+                        kept.name = track.name.clone();
+                    }
+                    duplicates.push(Duplicate {
+                        collection: Collection::Track,
+                        file_index,
+                        item_index,
+                        kept_index,
+                        distance_m,
+                    });
+                }
+                None => data.tracks.push(track.clone()),
+            }
+        }
+
+        for (item_index, route) in file.routes.iter().enumerate() {
+            let lines: Vec<&[Point]> = data.routes.iter().map(|r| r.points.as_slice()).collect();
+            match find_duplicate_line(&lines, &route.points, options.track_tolerance_m) {
+                Some((kept_index, distance_m)) => {
+                    let kept = &mut data.routes[kept_index];
+                    if route.points.len() > kept.points.len() {
+                        kept.points = route.points.clone();
+                    }
+                    if kept.name.is_none() {
+                        kept.name = route.name.clone();
+                    }
+                    duplicates.push(Duplicate {
+                        collection: Collection::Route,
+                        file_index,
+                        item_index,
+                        kept_index,
+                        distance_m,
+                    });
+                }
+                None => data.routes.push(route.clone()),
+            }
+        }
+
+        for (item_index, wp) in file.waypoints.iter().enumerate() {
+            match find_duplicate_waypoint(&data.waypoints, wp, options.waypoint_radius_m) {
+                Some((kept_index, distance_m)) => {
+                    let kept = &mut data.waypoints[kept_index];
+                    if kept.name.is_none() {
+                        kept.name = wp.name.clone();
+                    }
+                    if kept.icon.is_none() {
+                        kept.icon = wp.icon.clone();
+                    }
+                    if kept.point.ele.is_none() {
+                        kept.point.ele = wp.point.ele;
+                    }
+                    duplicates.push(Duplicate {
+                        collection: Collection::Waypoint,
+                        file_index,
+                        item_index,
+                        kept_index,
+                        distance_m,
+                    });
+                }
+                None => data.waypoints.push(wp.clone()),
+            }
+        }
+    }
+
+    MergeReport { data, duplicates }
+}
+
+/// Merge a JSON array of GPX data objects and return a JSON `MergeReport`.
+pub fn merge_json(
+    files_json: &str,
+    track_tolerance_m: f64,
+    waypoint_radius_m: f64,
+) -> Result<String, String> {
+    let files: Vec<GpxData> = serde_json::from_str(files_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    let options = MergeOptions {
+        track_tolerance_m,
+        waypoint_radius_m,
+    };
+    serde_json::to_string(&merge(&files, &options))
+        .map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// The first kept line within `tolerance_m` of `line`, with the distance.
+fn find_duplicate_line(
+    kept: &[&[Point]],
+    line: &[Point],
+    tolerance_m: f64,
+) -> Option<(usize, f64)> {
+    let bounds = bounds_of(line)?;
+    kept.iter().enumerate().find_map(|(i, other)| {
+        let other_bounds = bounds_of(other)?;
+        if !bounds_within(&bounds, &other_bounds, tolerance_m) {
+            return None;
+        }
+        let d = directed_hausdorff(line, other, tolerance_m)
+            .max(directed_hausdorff(other, line, tolerance_m));
+        (d <= tolerance_m).then_some((i, d))
+    })
+}
+
+/// The nearest kept waypoint within `radius_m` whose name does not
+/// contradict the new one, with the distance.
+fn find_duplicate_waypoint(
+    kept: &[Waypoint],
+    wp: &Waypoint,
+    radius_m: f64,
+) -> Option<(usize, f64)> {
+    kept.iter()
+        .enumerate()
+        .filter(|(_, k)| names_agree(k.name.as_deref(), wp.name.as_deref()))
+        .map(|(i, k)| (i, haversine(&k.point, &wp.point)))
+        .filter(|&(_, d)| d <= radius_m)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Names agree when they match ignoring case and surrounding space, or
+/// when either is missing.
+fn names_agree(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.trim().to_lowercase() == b.trim().to_lowercase(),
+        _ => true,
+    }
+}
+
+fn bounds_of(points: &[Point]) -> Option<Bounds> {
+    let (first, rest) = points.split_first()?;
+    let mut bounds = Bounds::from_point(first);
+    for p in rest {
+        bounds.extend(p);
+    }
+    Some(bounds)
+}
+
+/// Cheap rejection before the Hausdorff check: lines whose boxes differ
+/// by more than the tolerance on any side cannot be within it.
+fn bounds_within(a: &Bounds, b: &Bounds, tolerance_m: f64) -> bool {
+    let lat_deg = tolerance_m / 111_000.0;
+    let max_lat = a.max_lat.abs().max(a.min_lat.abs()).min(89.0);
+    let lon_deg = lat_deg / max_lat.to_radians().cos();
+    (a.min_lat - b.min_lat).abs() <= lat_deg
+        && (a.max_lat - b.max_lat).abs() <= lat_deg
+        && (a.min_lon - b.min_lon).abs() <= lon_deg
+        && (a.max_lon - b.max_lon).abs() <= lon_deg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpx::{Route, Track};
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    /// A 1 km line going east at 48°N in `n` segments, shifted north by
+    /// `offset_lat` degrees.
+    fn line(n: usize, offset_lat: f64) -> Vec<Point> {
+        (0..=n).map(|i| pt(48.0 + offset_lat, 16.0 + 0.0134 * i as f64 / n as f64)).collect()
+    }
+
+    fn wp(name: Option<&str>, lat: f64, lon: f64) -> Waypoint {
+        Waypoint { name: name.map(Into::into), point: pt(lat, lon), icon: None }
+    }
+
+    #[test]
+    fn merge_drops_duplicate_tracks_keeping_the_richer_one() {
+        let a = GpxData {
+            tracks: vec![Track { name: None, points: line(3, 0.0) }],
+            routes: vec![Route { name: Some("Plan".into()), points: line(2, 0.0) }],
+            ..Default::default()
+        };
+        let b = GpxData {
+            tracks: vec![
+                Track { name: Some("Tour".into()), points: line(40, 0.0001) },
+                Track { name: None, points: line(10, 0.01) },
+            ],
+            routes: vec![Route { name: None, points: line(5, 0.0) }],
+            ..Default::default()
+        };
+        let report = merge(&[a, b], &MergeOptions::default());
+        assert_eq!(report.data.tracks.len(), 2);
+        assert_eq!(report.data.tracks[0].points.len(), 41);
+        assert_eq!(report.data.tracks[0].name.as_deref(), Some("Tour"));
+        assert_eq!(report.data.routes.len(), 1);
+        assert_eq!(report.data.routes[0].name.as_deref(), Some("Plan"));
+        assert_eq!(report.duplicates.len(), 2);
+        let dup = &report.duplicates[0];
+        assert_eq!(dup.collection, Collection::Track);
+        assert_eq!((dup.file_index, dup.item_index, dup.kept_index), (1, 0, 0));
+        assert!(dup.distance_m < 12.0);
+    }
+
+    #[test]
+    fn merge_waypoints_within_radius_and_matching_names() {
+        let mut hut = wp(Some("Hut"), 47.0, 11.0);
+        hut.point.ele = Some(1800.0);
+        let a = GpxData {
+            waypoints: vec![wp(Some("Hut"), 47.0001, 11.0), wp(Some("Spring"), 47.0, 11.0)],
+            ..Default::default()
+        };
+        let b = GpxData {
+            waypoints: vec![hut, wp(Some(" hut "), 47.001, 11.0), wp(None, 47.0, 11.0001)],
+            ..Default::default()
+        };
+        let report = merge(&[a, b], &MergeOptions::default());
+        let names: Vec<_> = report.data.waypoints.iter().map(|w| w.name.as_deref()).collect();
+        assert_eq!(names, [Some("Hut"), Some("Spring"), Some(" hut ")]);
+        assert_eq!(report.data.waypoints[0].point.ele, Some(1800.0));
+        assert_eq!(report.duplicates.len(), 2);
+        // The unnamed one is nearest to "Spring"
+        assert_eq!(report.duplicates[1].kept_index, 1);
+    }
+
+    #[test]
+    fn merge_json_roundtrip() {
+        let file = GpxData {
+            tracks: vec![Track { name: None, points: line(3, 0.0) }],
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&[&file, &file]).unwrap();
+        let merged = merge_json(&json, 30.0, 25.0).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(v["data"]["tracks"].as_array().unwrap().len(), 1);
+        assert_eq!(v["duplicates"][0]["collection"], "track");
+        assert_eq!(v["duplicates"][0]["distance_m"], 0.0);
+        assert!(merge_json("{}", 30.0, 25.0).is_err());
+    }
+}
@@ -4,7 +4,8 @@
 //! calculations, and distance computations. All coordinates use
 //! WGS84 (lat/lon in degrees).
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use crate::geodesic;
 use crate::gpx::Point;
 
 /// Result of projecting a position onto a track.
@@ -21,7 +22,7 @@ pub struct ProjectionResult {
 }
 
 /// Earth radius in meters (WGS84 mean).
-const EARTH_RADIUS_M: f64 = 6_371_008.8;
+pub(crate) const EARTH_RADIUS_M: f64 = 6_371_008.8;
 
 /// Haversine distance between two points in meters.
 pub fn haversine(a: &Point, b: &Point) -> f64 {
@@ -52,6 +53,21 @@ pub fn track_length(points: &[Point]) -> f64 {
 ///
 /// Returns None if the track has fewer than 2 points.
 pub fn project_on_track(position: &Point, track: &[Point]) -> Option<ProjectionResult> {
+    project_within(position, track, f64::NEG_INFINITY, f64::INFINITY)
+}
+
+/// Project a position onto the part of a track between `from_m` and
+/// `to_m` meters along it. Segments outside the window are skipped and
+/// projections beyond its edges are moved back onto them.
+///
+/// Returns None if the track has fewer than 2 points or no segment
+/// overlaps the window.
+pub fn project_within(
+    position: &Point,
+    track: &[Point],
+    from_m: f64,
+    to_m: f64,
+) -> Option<ProjectionResult> {
     if track.len() < 2 {
         return None;
     }
@@ -64,11 +80,23 @@ pub fn project_on_track(position: &Point, track: &[Point]) -> Option<ProjectionR
         let b = &segment[1];
         let seg_len = haversine(a, b);
 
-        let projected = project_on_segment(position, a, b);
-        let dist = haversine(position, &projected);
+        if cumulative_distance + seg_len < from_m || cumulative_distance > to_m {
+            cumulative_distance += seg_len;
+            continue;
+        }
+
+        let mut projected = project_on_segment(position, a, b);
 
         // Distance along track to this projected point
-        let along = cumulative_distance + haversine(a, &projected);
+        let mut along = cumulative_distance + haversine(a, &projected);
+
+        // Keep to the window on segments that straddle its edges
+        if along < from_m || along > to_m {
+            along = along.clamp(from_m, to_m);
+            let t = if seg_len > 0.0 { (along - cumulative_distance) / seg_len } else { 0.0 };
+            projected = crate::convert::interpolate(a, b, t.clamp(0.0, 1.0));
+        }
+        let dist = haversine(position, &projected);
 
         let is_better = match &best {
             Some(prev) => dist < prev.distance_m,
@@ -90,35 +118,183 @@ pub fn project_on_track(position: &Point, track: &[Point]) -> Option<ProjectionR
     best
 }
 
-/// Project a point onto a line segment defined by two endpoints.
+/// Overall geometry of a track, which decides how positions are matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackShape {
+    /// Start and finish are apart.
+    OneWay,
+    /// Start and finish coincide.
+    Loop,
+    /// Start and finish coincide and the way back retraces the way out.
+    OutAndBack,
+}
+
+impl TrackShape {
+    /// Whether start and finish coincide, so that a position near the
+    /// start also lies near the end of the track.
+    pub fn is_closed(self) -> bool {
+        self != TrackShape::OneWay
+    }
+}
+
+/// Result of [`detect_shape`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ShapeInfo {
+    pub shape: TrackShape,
+    pub length_m: f64,
+    /// Distance between start and finish in meters.
+    pub closure_m: f64,
+}
+
+/// Distance from the track beyond which a windowed match on a closed
+/// track is given up in favor of a match further ahead, e.g. when the
+/// rider joins a loop halfway round. Matches the off-track warning.
+pub const REACQUIRE_M: f64 = 100.0;
+
+/// How far behind the last matched position a fix may be matched on a
+/// closed track, to absorb GPS jitter and short backtracking.
+const WINDOW_BEHIND_M: f64 = 200.0;
+/// How far ahead of the last matched position a fix may be matched on
+/// a closed track, to bridge GPS gaps such as tunnels.
+const WINDOW_AHEAD_M: f64 = 2000.0;
+/// Share of a closed track that must be covered before arrival counts.
+const ARRIVAL_MIN_FRACTION: f64 = 0.8;
+
+/// Classify a track as one-way, loop, or out-and-back.
 ///
-/// Uses a planar approximation scaled by latitude cosine, which is
-/// accurate enough for short segments (< 10 km).
-fn project_on_segment(p: &Point, a: &Point, b: &Point) -> Point {
-    let cos_lat = ((a.lat + b.lat) / 2.0).to_radians().cos();
+/// A track is closed when its finish lies within 5% of its length of
+/// the start (at least 30 m, at most 300 m). A closed track is an
+/// out-and-back when at least 80% of the way back, from the point
+/// farthest from the start, runs within 50 m of the way out.
+pub fn detect_shape(points: &[Point]) -> ShapeInfo {
+    let length_m = track_length(points);
+    let closure_m = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => haversine(first, last),
+        _ => 0.0,
+    };
+    let closed = points.len() >= 3 && closure_m <= (0.05 * length_m).clamp(30.0, 300.0);
+    let shape = if !closed {
+        TrackShape::OneWay
+    } else {
+        let turn = points
+            .iter()
+            .map(|p| haversine(&points[0], p))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(i, _)| i);
+        let back = crate::similarity::overlap(&points[turn..], &points[..=turn], 50.0);
+        if turn > 0 && back.shared_fraction >= 0.8 {
+            TrackShape::OutAndBack
+        } else {
+            TrackShape::Loop
+        }
+    };
+    ShapeInfo { shape, length_m, closure_m }
+}
 
-    let dx = (b.lon - a.lon) * cos_lat;
-    let dy = b.lat - a.lat;
-    let px = (p.lon - a.lon) * cos_lat;
-    let py = p.lat - a.lat;
+/// A projection with the navigation state derived from it.
+#[derive(Debug, Clone, Serialize)]
+pub struct NavMatch {
+    #[serde(flatten)]
+    pub projection: ProjectionResult,
+    /// Whether enough of the track is covered for the rider to have
+    /// arrived once near the finish.
+    pub arrival_allowed: bool,
+}
 
-    let seg_len_sq = dx * dx + dy * dy;
+/// Project a position onto a track, taking its shape into account.
+///
+/// On one-way tracks this is [`project_on_track`]. On closed tracks
+/// the match is kept near the last one (`last_along_m`, None at the
+/// start of a session), so a rider at the start of a loop is matched
+/// to its first rather than its last segment and progress runs forward
+/// from zero; on out-and-back tracks the same keeps the way out and the
+/// way back apart. Only when the rider is more than [`REACQUIRE_M`]
+/// from the window may the match skip further ahead. The final 20% of
+/// a closed track is only reachable once the match has got there along
+/// the track, and arrival is allowed only beyond it.
+pub fn project_for_navigation(
+    position: &Point,
+    track: &[Point],
+    shape: TrackShape,
+    last_along_m: Option<f64>,
+) -> Option<NavMatch> {
+    let length = track_length(track);
+    let projection = if shape.is_closed() {
+        // The final stretch, where the finish meets the start, is out of
+        // reach until the rider has come close to it along the track
+        let limit = ARRIVAL_MIN_FRACTION * length;
+        let (from, to, reach) = match last_along_m {
+            None => (0.0, limit, limit),
+            Some(last) if last < limit => {
+                let to = last + WINDOW_AHEAD_M.min(limit - last);
+                (last - WINDOW_BEHIND_M, to, limit)
+            }
+            Some(last) => (last - WINDOW_BEHIND_M, last + WINDOW_AHEAD_M, length),
+        };
+        let window = project_within(position, track, from, to);
+        match window {
+            Some(w) if w.distance_m <= REACQUIRE_M => w,
+            _ => {
+                // Skip ahead, but never straight into the final stretch
+                let nearest = project_within(position, track, from, reach.max(to))?;
+                match window {
+                    Some(w) if w.distance_m <= nearest.distance_m => w,
+                    _ => nearest,
+                }
+            }
+        }
+    } else {
+        project_on_track(position, track)?
+    };
+    let arrival_allowed =
+        !shape.is_closed() || projection.distance_along_m > ARRIVAL_MIN_FRACTION * length;
+    Some(NavMatch { projection, arrival_allowed })
+}
 
-    if seg_len_sq < 1e-20 {
-        // Degenerate segment, return endpoint
-        return a.clone();
-    }
+/// Project a point onto a line segment defined by two endpoints.
+///
+/// Uses a planar approximation scaled by latitude cosine for short
+/// segments, and the WGS84 geodesic for long, polar or antimeridian
+/// legs (see [`geodesic::needs_geodesic`]).
+fn project_on_segment(p: &Point, a: &Point, b: &Point) -> Point {
+    let (lat, lon, t) = if geodesic::needs_geodesic(a, b) {
+        let (foot, t) = geodesic::project_on_segment(p, a, b);
+        (foot.lat, foot.lon, t)
+    } else {
+        let cos_lat = ((a.lat + b.lat) / 2.0).to_radians().cos();
+
+        let dx = (b.lon - a.lon) * cos_lat;
+        let dy = b.lat - a.lat;
+        let px = (p.lon - a.lon) * cos_lat;
+        let py = p.lat - a.lat;
+
+        let seg_len_sq = dx * dx + dy * dy;
+
+        if seg_len_sq < 1e-20 {
+            // Degenerate segment, return endpoint
+            return a.clone();
+        }
 
-    // Clamp parameter t to [0, 1] to stay on the segment
-    let t = ((px * dx + py * dy) / seg_len_sq).clamp(0.0, 1.0);
+        // Clamp parameter t to [0, 1] to stay on the segment
+        let t = ((px * dx + py * dy) / seg_len_sq).clamp(0.0, 1.0);
+        (a.lat + t * (b.lat - a.lat), a.lon + t * (b.lon - a.lon), t)
+    };
 
     Point {
-        lat: a.lat + t * (b.lat - a.lat),
-        lon: a.lon + t * (b.lon - a.lon),
+        lat,
+        lon,
         ele: match (a.ele, b.ele) {
             (Some(ea), Some(eb)) => Some(ea + t * (eb - ea)),
             _ => None,
         },
+        time: match (a.time, b.time) {
+            (Some(ta), Some(tb)) => Some(ta + t * (tb - ta)),
+            _ => None,
+        },
+        speed: None,
+        course: None,
     }
 }
 
@@ -127,7 +303,7 @@ mod tests {
     use super::*;
 
     fn pt(lat: f64, lon: f64) -> Point {
-        Point { lat, lon, ele: None }
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
     }
 
     #[test]
@@ -213,4 +389,133 @@ mod tests {
         let r2 = project_on_track(&pt(48.0, 16.8), &track).unwrap();
         assert!(r2.distance_along_m > r1.distance_along_m);
     }
+
+    #[test]
+    fn project_on_long_leg_uses_geodesic() {
+        // 200 km leg along the 60th parallel's endpoints; the geodesic
+        // bulges poleward of the straight lat/lon line
+        let track = vec![pt(60.0, 0.0), pt(60.0, 4.0)];
+        let result = project_on_track(&pt(60.0, 2.0), &track).unwrap();
+        assert!(result.point.lat > 60.0, "lat {}", result.point.lat);
+        assert!((result.point.lon - 2.0).abs() < 1e-3);
+        assert!(result.distance_m > 1000.0);
+    }
+
+    #[test]
+    fn project_across_antimeridian() {
+        let track = vec![pt(0.0, 179.9), pt(0.0, -179.9)];
+        let result = project_on_track(&pt(0.001, 180.0), &track).unwrap();
+        assert!(result.point.lon.abs() > 179.9, "lon {}", result.point.lon);
+        assert!(result.distance_m < 120.0, "distance {}", result.distance_m);
+    }
+
+    /// About 1.1 km square going east, north, west and south back to
+    /// the start at 48°N.
+    fn square_loop() -> Vec<Point> {
+        vec![
+            pt(48.0, 16.0),
+            pt(48.0, 16.015),
+            pt(48.01, 16.015),
+            pt(48.01, 16.0),
+            pt(48.0, 16.0),
+        ]
+    }
+
+    /// East for 0.02° and back again 11 m further north.
+    fn out_and_back() -> Vec<Point> {
+        let out = (0..=4).map(|i| pt(48.0, 16.0 + 0.005 * i as f64));
+        let back = (0..=4).rev().map(|i| pt(48.0001, 16.0 + 0.005 * i as f64));
+        out.chain(back).collect()
+    }
+
+    #[test]
+    fn detect_shapes() {
+        let one_way = vec![pt(48.0, 16.0), pt(48.0, 16.01), pt(48.01, 16.01)];
+        assert_eq!(detect_shape(&one_way).shape, TrackShape::OneWay);
+        let info = detect_shape(&square_loop());
+        assert_eq!(info.shape, TrackShape::Loop);
+        assert_eq!(info.closure_m, 0.0);
+        assert!(info.length_m > 4000.0);
+        assert_eq!(detect_shape(&out_and_back()).shape, TrackShape::OutAndBack);
+        assert_eq!(detect_shape(&[]).shape, TrackShape::OneWay);
+    }
+
+    #[test]
+    fn loop_start_matches_first_segment() {
+        let track = square_loop();
+        // Beside the closing segment, a little further from the first
+        let rider = pt(48.0005, 15.999_95);
+        assert_eq!(project_on_track(&rider, &track).unwrap().segment_index, 3);
+
+        let m = project_for_navigation(&rider, &track, TrackShape::Loop, None).unwrap();
+        assert_eq!(m.projection.segment_index, 0);
+        assert!(m.projection.distance_along_m < 1.0);
+        assert!(!m.arrival_allowed);
+
+        // Coming home after a full lap the closing segment matches
+        let last = track_length(&track) - 100.0;
+        let m = project_for_navigation(&rider, &track, TrackShape::Loop, Some(last)).unwrap();
+        assert_eq!(m.projection.segment_index, 3);
+        assert!(m.arrival_allowed);
+    }
+
+    #[test]
+    fn short_loop_start_stays_on_first_side() {
+        // About 250 m sides, so the whole loop fits in the look-ahead
+        let track = vec![
+            pt(48.0, 16.0),
+            pt(48.0, 16.003_36),
+            pt(48.002_25, 16.003_36),
+            pt(48.002_25, 16.0),
+            pt(48.0, 16.0),
+        ];
+        assert!(track_length(&track) < 1100.0);
+        let rider = pt(48.0005, 15.999_95);
+        let m = project_for_navigation(&rider, &track, TrackShape::Loop, None).unwrap();
+        assert_eq!(m.projection.segment_index, 0);
+        assert!(!m.arrival_allowed);
+
+        // Riding along the first side keeps matching it
+        let mut last = m.projection.distance_along_m;
+        for lon in [16.0005, 16.001, 16.0015, 16.002] {
+            let fix = pt(48.000_05, lon);
+            let m = project_for_navigation(&fix, &track, TrackShape::Loop, Some(last)).unwrap();
+            assert_eq!(m.projection.segment_index, 0);
+            assert!(m.projection.distance_m < 10.0);
+            assert!(m.projection.distance_along_m > last);
+            assert!(!m.arrival_allowed);
+            last = m.projection.distance_along_m;
+        }
+    }
+
+    #[test]
+    fn out_and_back_keeps_direction() {
+        let track = out_and_back();
+        let rider = pt(48.000_05, 16.01);
+        let out = project_for_navigation(&rider, &track, TrackShape::OutAndBack, None).unwrap();
+        assert!(out.projection.segment_index < 4);
+        let back =
+            project_for_navigation(&rider, &track, TrackShape::OutAndBack, Some(2200.0)).unwrap();
+        assert!(back.projection.segment_index > 4);
+        assert!((back.projection.distance_along_m - 2250.0).abs() < 20.0);
+    }
+
+    #[test]
+    fn reacquire_skips_ahead_but_not_to_the_finish() {
+        let track = square_loop();
+        // Joining halfway along the north side
+        let north = pt(48.01, 16.0075);
+        let m = project_for_navigation(&north, &track, TrackShape::Loop, None).unwrap();
+        assert_eq!(m.projection.segment_index, 2);
+        assert!(m.projection.distance_m < 1.0);
+        // Halfway down the final side is out of reach from the start
+        let south = pt(48.005, 16.0);
+        let m = project_for_navigation(&south, &track, TrackShape::Loop, None).unwrap();
+        assert!(m.projection.distance_along_m <= 0.8 * track_length(&track) + 1e-6);
+        assert!(!m.arrival_allowed);
+        // One-way tracks match anywhere
+        let m = project_for_navigation(&south, &track, TrackShape::OneWay, None).unwrap();
+        assert_eq!(m.projection.segment_index, 3);
+        assert!(m.arrival_allowed);
+    }
 }
@@ -0,0 +1,505 @@
+//! Elevation profiles for tracks and routes.
+//!
+//! Resamples a point list at even distance steps and produces the
+//! series behind an elevation chart: elevation, grade and cumulative
+//! ascent per sample. Distances use the same haversine sums as
+//! `nav::project_on_track`, so a projection's `distance_along_m` maps
+//! directly onto the profile with [`Profile::index_at`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::dem::Dem;
+use crate::elevation::{filter_points, ElevationFilter};
+use crate::gpx::Point;
+use crate::nav::haversine;
+
+/// Where the distance between profile samples comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// This many samples (at least 2), evenly spread over the length.
+    Count(usize),
+    /// One sample every this many meters, plus one at the end.
+    Spacing(f64),
+}
+
+/// Most samples a profile will have, however fine the sampling asked for.
+pub const MAX_SAMPLES: usize = 10_000;
+
+/// Options for [`build_profile`].
+#[derive(Debug, Clone, Copy)]
+pub struct ProfileOptions {
+    pub sampling: Sampling,
+    /// Width of the centered moving-average window in meters.
+    /// 0 disables smoothing.
+    pub smoothing_m: f64,
+    /// Clean recorded elevations with [`ElevationFilter::recommended`]
+    /// before sampling, so totals and grades are not inflated by GPS
+    /// jitter. Elevations taken from the DEM are not filtered.
+    pub filter_elevation: bool,
+}
+
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        Self {
+            sampling: Sampling::Count(200),
+            smoothing_m: 200.0,
+            filter_elevation: true,
+        }
+    }
+}
+
+/// One point of an elevation profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileSample {
+    /// Distance from the start, in meters.
+    pub distance_m: f64,
+    /// Smoothed elevation, in meters.
+    pub ele_m: f64,
+    /// Grade of the step leading to this sample, in percent. The first
+    /// sample repeats the grade of the first step.
+    pub grade_pct: f64,
+    /// Total ascent from the start up to this sample, in meters.
+    pub ascent_m: f64,
+}
+
+/// A resampled elevation profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub samples: Vec<ProfileSample>,
+    pub total_distance_m: f64,
+    pub total_ascent_m: f64,
+    pub total_descent_m: f64,
+    pub min_ele_m: f64,
+    pub max_ele_m: f64,
+}
+
+impl Profile {
+    /// Index of the sample nearest to a distance along the path, e.g.
+    /// `ProjectionResult::distance_along_m` for the rider position.
+    pub fn index_at(&self, distance_along_m: f64) -> usize {
+        let after = self
+            .samples
+            .partition_point(|s| s.distance_m < distance_along_m);
+        if after == 0 {
+            return 0;
+        }
+        if after == self.samples.len() {
+            return self.samples.len() - 1;
+        }
+        let before_gap = distance_along_m - self.samples[after - 1].distance_m;
+        let after_gap = self.samples[after].distance_m - distance_along_m;
+        if before_gap <= after_gap {
+            after - 1
+        } else {
+            after
+        }
+    }
+}
+
+/// Build an elevation profile with at most [`MAX_SAMPLES`] samples.
+///
+/// Elevations come from the DEM when one is given and covers the
+/// sample position, otherwise from the points' own elevations,
+/// interpolated along the path and, with `filter_elevation`, cleaned
+/// of GPS noise first. Returns `None` for fewer than two points, a
+/// zero-length path, a spacing that is not positive, or no elevation
+/// data at all.
+pub fn build_profile(
+    points: &[Point],
+    options: &ProfileOptions,
+    mut dem: Option<&mut Dem>,
+) -> Option<Profile> {
+    if points.len() < 2 {
+        return None;
+    }
+    if let Sampling::Spacing(spacing) = options.sampling {
+        if !(spacing.is_finite() && spacing > 0.0) {
+            return None;
+        }
+    }
+    let cumulative = cumulative_distances(points);
+    let total = *cumulative.last()?;
+    if total <= 0.0 {
+        return None;
+    }
+
+    let distances = sample_distances(total, options.sampling);
+    let filtered;
+    let recorded = if options.filter_elevation {
+        let mut copy = points.to_vec();
+        filter_points(&mut copy, &ElevationFilter::recommended(), None).ok()?;
+        filtered = copy;
+        &filtered
+    } else {
+        points
+    };
+    let point_elevations = interpolated_elevations(recorded, &cumulative, &distances);
+    let mut elevations = Vec::with_capacity(distances.len());
+    for (i, &d) in distances.iter().enumerate() {
+        let from_dem = dem.as_deref_mut().and_then(|dem| {
+            let p = position_at(points, &cumulative, d);
+            dem.elevation(p.lat, p.lon)
+        });
+        elevations.push(from_dem.or(point_elevations[i]));
+    }
+    let elevations = fill_gaps(&elevations)?;
+
+    let step = total / (distances.len() - 1) as f64;
+    let elevations = smooth(&elevations, options.smoothing_m, step);
+
+    let mut samples = Vec::with_capacity(distances.len());
+    let (mut ascent, mut descent) = (0.0, 0.0);
+    for i in 0..distances.len() {
+        let grade_pct = if i == 0 {
+            grade(&distances, &elevations, 1)
+        } else {
+            let diff = elevations[i] - elevations[i - 1];
+            if diff > 0.0 {
+                ascent += diff;
+            } else {
+                descent -= diff;
+            }
+            grade(&distances, &elevations, i)
+        };
+        samples.push(ProfileSample {
+            distance_m: distances[i],
+            ele_m: elevations[i],
+            grade_pct,
+            ascent_m: ascent,
+        });
+    }
+
+    Some(Profile {
+        samples,
+        total_distance_m: total,
+        total_ascent_m: ascent,
+        total_descent_m: descent,
+        min_ele_m: elevations.iter().copied().fold(f64::INFINITY, f64::min),
+        max_ele_m: elevations.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    })
+}
+
+/// Build a profile from a JSON point array and return it as JSON.
+///
+/// A positive `sample_count` selects [`Sampling::Count`], otherwise
+/// `spacing_m` is used. `dem_dir` enables DEM elevations.
+pub fn profile_json(
+    points_json: &str,
+    sample_count: usize,
+    spacing_m: f64,
+    smoothing_m: f64,
+    dem_dir: Option<&str>,
+) -> Result<String, String> {
+    let points: Vec<Point> = serde_json::from_str(points_json)
+        .map_err(|e| format!("Points JSON parse failed: {e}"))?;
+    let sampling = if sample_count > 0 {
+        Sampling::Count(sample_count)
+    } else if spacing_m.is_finite() && spacing_m > 0.0 {
+        Sampling::Spacing(spacing_m)
+    } else {
+        return Err("Profile needs a sample count or a positive spacing".to_string());
+    };
+    let options = ProfileOptions {
+        sampling,
+        smoothing_m: smoothing_m.max(0.0),
+        filter_elevation: true,
+    };
+    let mut dem = dem_dir.map(Dem::new);
+    let profile = build_profile(&points, &options, dem.as_mut())
+        .ok_or_else(|| "No elevation profile: too few points or no elevation data".to_string())?;
+    serde_json::to_string(&profile).map_err(|e| format!("JSON serialize failed: {e}"))
+}
+
+/// Map a distance along the path to a profile index, for JSON input.
+pub fn index_at_json(profile_json: &str, distance_along_m: f64) -> Result<usize, String> {
+    let profile: Profile = serde_json::from_str(profile_json)
+        .map_err(|e| format!("Profile JSON parse failed: {e}"))?;
+    Ok(profile.index_at(distance_along_m))
+}
+
+/// Haversine distance from the first point to each point, in meters.
+pub(crate) fn cumulative_distances(points: &[Point]) -> Vec<f64> {
+    let mut cumulative = Vec::with_capacity(points.len());
+    let mut total = 0.0;
+    cumulative.push(0.0);
+    for w in points.windows(2) {
+        total += haversine(&w[0], &w[1]);
+        cumulative.push(total);
+    }
+    cumulative
+}
+
+/// Sample distances along a path of length `total`, at most
+/// [`MAX_SAMPLES`] of them; a spacing that would need more is widened.
+fn sample_distances(total: f64, sampling: Sampling) -> Vec<f64> {
+    match sampling {
+        Sampling::Count(n) => {
+            let n = n.clamp(2, MAX_SAMPLES);
+            (0..n).map(|i| total * i as f64 / (n - 1) as f64).collect()
+        }
+        Sampling::Spacing(spacing) => {
+            let steps = (total / spacing).ceil().max(1.0);
+            if steps >= MAX_SAMPLES as f64 {
+                return sample_distances(total, Sampling::Count(MAX_SAMPLES));
+            }
+            let steps = steps as usize;
+            let mut distances: Vec<f64> = (0..steps).map(|i| i as f64 * spacing).collect();
+            distances.push(total);
+            distances
+        }
+    }
+}
+
+/// Segment containing distance `d` and the fraction along it.
+fn locate(cumulative: &[f64], d: f64) -> (usize, f64) {
+    let i = cumulative
+        .partition_point(|&c| c <= d)
+        .clamp(1, cumulative.len() - 1);
+    let (start, end) = (cumulative[i - 1], cumulative[i]);
+    let t = if end > start { (d - start) / (end - start) } else { 0.0 };
+    (i - 1, t.clamp(0.0, 1.0))
+}
+
+/// Position at distance `d` along the path, interpolated linearly.
+pub(crate) fn position_at(points: &[Point], cumulative: &[f64], d: f64) -> Point {
+    let (i, t) = locate(cumulative, d);
+    let (a, b) = (&points[i], &points[i + 1]);
+    Point {
+        lat: a.lat + t * (b.lat - a.lat),
+        lon: a.lon + t * (b.lon - a.lon),
+        ele: None,
+        time: None,
+        speed: None,
+        course: None,
+    }
+}
+
+/// Point elevations interpolated at each sample distance, skipping
+/// points without elevation. Samples outside the first and last point
+/// with an elevation are `None`.
+fn interpolated_elevations(
+    points: &[Point],
+    cumulative: &[f64],
+    distances: &[f64],
+) -> Vec<Option<f64>> {
+    let known: Vec<(f64, f64)> = points
+        .iter()
+        .zip(cumulative)
+        .filter_map(|(p, &d)| p.ele.map(|e| (d, e)))
+        .collect();
+    distances
+        .iter()
+        .map(|&d| {
+            let i = known.partition_point(|&(kd, _)| kd < d);
+            match (known.get(i.wrapping_sub(1)), known.get(i)) {
+                (_, Some(&(kd, e))) if kd == d => Some(e),
+                (Some(&(d0, e0)), Some(&(d1, e1))) => Some(e0 + (e1 - e0) * (d - d0) / (d1 - d0)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Fill leading and trailing gaps with the nearest known elevation.
+fn fill_gaps(elevations: &[Option<f64>]) -> Option<Vec<f64>> {
+    let first = elevations.iter().find_map(|&e| e)?;
+    let mut last = first;
+    Some(
+        elevations
+            .iter()
+            .map(|&e| {
+                if let Some(e) = e {
+                    last = e;
+                }
+                last
+            })
+            .collect(),
+    )
+}
+
+/// Centered moving average over a window of `window_m` meters.
+fn smooth(elevations: &[f64], window_m: f64, step_m: f64) -> Vec<f64> {
+    let half = (window_m / step_m / 2.0).round() as usize;
+    if half == 0 {
+        return elevations.to_vec();
+    }
+    (0..elevations.len())
+        .map(|i| {
+            let lo = i.saturating_sub(half);
+            let hi = (i + half).min(elevations.len() - 1);
+            let window = &elevations[lo..=hi];
+            window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect()
+}
+
+fn grade(distances: &[f64], elevations: &[f64], i: usize) -> f64 {
+    let run = distances[i] - distances[i - 1];
+    if run > 0.0 {
+        (elevations[i] - elevations[i - 1]) / run * 100.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points due north along a meridian, roughly 111 m apart.
+    fn climb(eles: &[Option<f64>]) -> Vec<Point> {
+        eles.iter()
+            .enumerate()
+            .map(|(i, &ele)| Point {
+                lat: 47.0 + i as f64 * 0.001,
+                lon: 11.0,
+                ele,
+                time: None,
+                speed: None,
+                course: None,
+            })
+            .collect()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{a} != {b}");
+    }
+
+    fn no_smoothing(sampling: Sampling) -> ProfileOptions {
+        ProfileOptions {
+            sampling,
+            smoothing_m: 0.0,
+            filter_elevation: false,
+        }
+    }
+
+    #[test]
+    fn fixed_count_profile() {
+        let points = climb(&[Some(100.0), Some(110.0), Some(120.0), Some(110.0)]);
+        let profile = build_profile(&points, &no_smoothing(Sampling::Count(7)), None).unwrap();
+
+        assert_eq!(profile.samples.len(), 7);
+        assert_eq!(profile.samples[0].distance_m, 0.0);
+        assert!((profile.samples[6].distance_m - profile.total_distance_m).abs() < 1e-9);
+        assert_close(profile.samples[2].ele_m, 110.0);
+        assert_close(profile.total_ascent_m, 20.0);
+        assert_close(profile.total_descent_m, 10.0);
+        assert_close(profile.min_ele_m, 100.0);
+        assert_close(profile.max_ele_m, 120.0);
+
+        // 10 m over ~111 m is a 9 % grade
+        assert!((profile.samples[1].grade_pct - 9.0).abs() < 0.1);
+        assert_eq!(profile.samples[0].grade_pct, profile.samples[1].grade_pct);
+        assert!(profile.samples[6].grade_pct < -8.0);
+        assert_close(profile.samples[4].ascent_m, 20.0);
+    }
+
+    #[test]
+    fn fixed_spacing_profile() {
+        let points = climb(&[Some(0.0), Some(0.0), Some(0.0)]);
+        let profile = build_profile(&points, &no_smoothing(Sampling::Spacing(50.0)), None).unwrap();
+
+        // ~222 m: samples at 0, 50, 100, 150, 200 and the end
+        assert_eq!(profile.samples.len(), 6);
+        assert_eq!(profile.samples[3].distance_m, 150.0);
+    }
+
+    #[test]
+    fn gaps_in_elevation_are_interpolated() {
+        let points = climb(&[None, Some(100.0), None, Some(200.0), None]);
+        let profile = build_profile(&points, &no_smoothing(Sampling::Count(5)), None).unwrap();
+        for (sample, expected) in profile.samples.iter().zip([100.0, 100.0, 150.0, 200.0, 200.0]) {
+            assert_close(sample.ele_m, expected);
+        }
+
+        assert!(build_profile(&climb(&[None, None]), &ProfileOptions::default(), None).is_none());
+        assert!(build_profile(&climb(&[Some(1.0)]), &ProfileOptions::default(), None).is_none());
+    }
+
+    #[test]
+    fn smoothing_flattens_spikes() {
+        let points = climb(&[Some(100.0), Some(100.0), Some(160.0), Some(100.0), Some(100.0)]);
+        let raw = build_profile(&points, &no_smoothing(Sampling::Count(5)), None).unwrap();
+        let options = ProfileOptions {
+            sampling: Sampling::Count(5),
+            smoothing_m: 300.0,
+            filter_elevation: false,
+        };
+        let smoothed = build_profile(&points, &options, None).unwrap();
+
+        assert_close(raw.max_ele_m, 160.0);
+        assert_close(smoothed.max_ele_m, 120.0);
+        assert!(smoothed.total_ascent_m < raw.total_ascent_m);
+    }
+
+    #[test]
+    fn recorded_elevations_are_filtered() {
+        // Flat road with ±4 m GPS jitter
+        let eles: Vec<Option<f64>> =
+            (0..40).map(|i| Some(if i % 2 == 0 { 104.0 } else { 96.0 })).collect();
+        let points = climb(&eles);
+        let raw = build_profile(&points, &no_smoothing(Sampling::Count(40)), None).unwrap();
+        let options =
+            ProfileOptions { filter_elevation: true, ..no_smoothing(Sampling::Count(40)) };
+        let filtered = build_profile(&points, &options, None).unwrap();
+        assert!(raw.total_ascent_m > 100.0);
+        assert!(filtered.total_ascent_m < 5.0, "ascent {}", filtered.total_ascent_m);
+    }
+
+    #[test]
+    fn dem_elevations_take_precedence() {
+        let dir = std::env::temp_dir().join(format!("ndkarte-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Flat 2x2 tile at 500 m
+        let tile: Vec<u8> = std::iter::repeat_n(500i16.to_be_bytes(), 4).flatten().collect();
+        std::fs::write(dir.join("N47E011.hgt"), tile).unwrap();
+
+        let points = climb(&[None, None, None]);
+        let mut dem = Dem::new(&dir);
+        let profile =
+            build_profile(&points, &no_smoothing(Sampling::Count(3)), Some(&mut dem)).unwrap();
+        assert!(profile.samples.iter().all(|s| s.ele_m == 500.0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn index_at_maps_distance_to_nearest_sample() {
+        let points = climb(&[Some(0.0), Some(10.0), Some(20.0)]);
+        let profile = build_profile(&points, &no_smoothing(Sampling::Spacing(50.0)), None).unwrap();
+
+        assert_eq!(profile.index_at(-5.0), 0);
+        assert_eq!(profile.index_at(24.0), 0);
+        assert_eq!(profile.index_at(26.0), 1);
+        assert_eq!(profile.index_at(100.0), 2);
+        assert_eq!(profile.index_at(1e6), profile.samples.len() - 1);
+
+        // Consistent with the distances reported by track projection
+        let rider = Point { lat: 47.0015, ..points[0].clone() };
+        let proj = crate::nav::project_on_track(&rider, &points).unwrap();
+        let i = profile.index_at(proj.distance_along_m);
+        assert!((profile.samples[i].distance_m - 150.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn profile_json_round_trip() {
+        let json = r#"[{"lat": 47.0, "lon": 11.0, "ele": 100},
+                       {"lat": 47.01, "lon": 11.0, "ele": 200}]"#;
+        let profile = profile_json(json, 11, 0.0, 0.0, None).unwrap();
+        assert_eq!(index_at_json(&profile, 0.0).unwrap(), 0);
+        assert_eq!(index_at_json(&profile, 1e6).unwrap(), 10);
+        assert!(profile_json(json, 0, 0.0, 0.0, None).is_err());
+        assert!(profile_json(json, 0, f64::NAN, 0.0, None).is_err());
+        assert!(profile_json(json, 0, f64::INFINITY, 0.0, None).is_err());
+    }
+
+    #[test]
+    fn sample_count_is_capped() {
+        let points = climb(&[Some(100.0), Some(120.0)]);
+        let fine = build_profile(&points, &no_smoothing(Sampling::Spacing(1e-9)), None).unwrap();
+        assert_eq!(fine.samples.len(), MAX_SAMPLES);
+        let options = no_smoothing(Sampling::Count(usize::MAX));
+        let many = build_profile(&points, &options, None).unwrap();
+        assert_eq!(many.samples.len(), MAX_SAMPLES);
+        assert!(build_profile(&points, &no_smoothing(Sampling::Spacing(-1.0)), None).is_none());
+    }
+}
@@ -89,21 +89,20 @@ pub fn generate_instructions(points: &[Point]) -> Vec<Instruction> {
 ///
 /// Uses the bearing change (relative angle) to categorize the turn.
 fn compute_turn(a: &Point, b: &Point, c: &Point) -> Turn {
-    let bearing_ab = bearing(a, b);
-    let bearing_bc = bearing(b, c);
-
-    // Relative angle: positive = right turn, negative = left turn
-    let mut angle = bearing_bc - bearing_ab;
+    classify_turn(relative_angle(bearing(a, b), bearing(b, c)))
+}
 
-    // Normalize to [-180, 180]
+/// Relative angle between two bearings in degrees, normalized to
+/// [-180, 180]: positive = right turn, negative = left turn.
+pub(crate) fn relative_angle(bearing_in: f64, bearing_out: f64) -> f64 {
+    let mut angle = bearing_out - bearing_in;
     while angle > 180.0 {
         angle -= 360.0;
     }
     while angle < -180.0 {
         angle += 360.0;
     }
-
-    classify_turn(angle)
+    angle
 }
 
 /// Classify a relative bearing angle into a turn direction.
@@ -124,7 +123,7 @@ fn classify_turn(angle: f64) -> Turn {
 }
 
 /// Compute the initial bearing from point A to point B in degrees [0, 360).
-fn bearing(a: &Point, b: &Point) -> f64 {
+pub(crate) fn bearing(a: &Point, b: &Point) -> f64 {
     let lat1 = a.lat.to_radians();
     let lat2 = b.lat.to_radians();
     let dlon = (b.lon - a.lon).to_radians();
@@ -164,7 +163,7 @@ mod tests {
     use super::*;
 
     fn pt(lat: f64, lon: f64) -> Point {
-        Point { lat, lon, ele: None }
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
     }
 
     #[test]
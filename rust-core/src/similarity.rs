@@ -0,0 +1,340 @@
+//! Track similarity: Hausdorff and discrete Fréchet distance, and
+//! overlap analysis between two point lists.
+//!
+//! Answers "have I ridden this before?" and "where did I leave the
+//! plan?". Hausdorff distance ignores direction and order, Fréchet
+//! distance respects both; the overlap analysis splits one line into
+//! stretches shared with the other and stretches that diverge from it.
+
+use serde::Serialize;
+
+use crate::convert::resample_by_distance;
+use crate::gpx::Point;
+use crate::nav::{haversine, project_on_track, track_length};
+use crate::profile::cumulative_distances;
+
+/// A run of consecutive points of the first line that either all stay
+/// within the tolerance of the second line or all leave it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stretch {
+    pub shared: bool,
+    /// Index of the first point of the stretch in the first line.
+    pub start_index: usize,
+    /// Index of the last point; this is also the first point of the
+    /// next stretch, so stretches join up.
+    pub end_index: usize,
+    /// Distance along the first line where the stretch starts, in meters.
+    pub start_m: f64,
+    /// Distance along the first line where the stretch ends, in meters.
+    pub end_m: f64,
+    /// Part of the second line the stretch runs along, in meters from
+    /// its start. Only present for shared stretches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_start_m: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_end_m: Option<f64>,
+    /// Farthest a point of the stretch lies from the second line, in meters.
+    pub max_distance_m: f64,
+}
+
+/// Result of [`overlap`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Overlap {
+    /// Length of the first line running along the second, in meters.
+    pub shared_m: f64,
+    /// Length of the first line away from the second, in meters.
+    pub divergent_m: f64,
+    /// Share of the first line's length running along the second (0..1).
+    pub shared_fraction: f64,
+    /// Share of the second line's length covered by the first (0..1).
+    pub covered_fraction: f64,
+    pub stretches: Vec<Stretch>,
+    /// For every point of the first line, the distance along the second
+    /// line of its nearest position there, in meters.
+    #[serde(skip)]
+    pub other_along_m: Vec<Option<f64>>,
+}
+
+/// Full comparison of two lines, as returned by [`compare_json`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub hausdorff_m: f64,
+    pub frechet_m: f64,
+    #[serde(flatten)]
+    pub overlap: Overlap,
+}
+
+/// Symmetric Hausdorff distance between two polylines in meters: the
+/// farthest any point of one line lies from the other line.
+///
+/// Points are measured against the other line's segments rather than
+/// its points, so two recordings of the same path at different sample
+/// rates come out close. Direction does not matter.
+pub fn hausdorff_m(a: &[Point], b: &[Point]) -> f64 {
+    directed_hausdorff(a, b, f64::INFINITY).max(directed_hausdorff(b, a, f64::INFINITY))
+}
+
+/// Farthest distance from a point of `from` to the line `to`. Stops
+/// early and returns a value above `limit` once one is found.
+pub(crate) fn directed_hausdorff(from: &[Point], to: &[Point], limit: f64) -> f64 {
+    let mut worst = 0.0_f64;
+    for p in from {
+        worst = worst.max(distance_to_line(p, to));
+        if worst > limit {
+            break;
+        }
+    }
+    worst
+}
+
+/// Discrete Fréchet distance between two point lists in meters.
+///
+/// The shortest leash that lets two walkers traverse both lists from
+/// start to end, each only moving forward from point to point. Unlike
+/// Hausdorff distance it tells a route from the same route ridden
+/// backwards. It measures between points only, so lists should be
+/// sampled at similar, fine spacing (see [`resample_by_distance`]).
+/// Takes time proportional to the product of both lengths.
+pub fn frechet_m(a: &[Point], b: &[Point]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return f64::INFINITY;
+    }
+    let mut prev = vec![0.0_f64; b.len()];
+    let mut row = vec![0.0; b.len()];
+    for (i, p) in a.iter().enumerate() {
+        for (j, q) in b.iter().enumerate() {
+            let d = haversine(p, q);
+            row[j] = match (i, j) {
+                (0, 0) => d,
+                (0, _) => row[j - 1].max(d),
+                (_, 0) => prev[0].max(d),
+                _ => prev[j].min(prev[j - 1]).min(row[j - 1]).max(d),
+            };
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    prev[b.len() - 1]
+}
+
+/// Split line `a` into stretches shared with line `b` and stretches
+/// diverging from it.
+///
+/// A point of `a` is shared when it lies within `tolerance_m` of `b`.
+/// Each point is matched to the nearest part of `b`, so a line crossing
+/// `b` counts as sharing the crossing point.
+pub fn overlap(a: &[Point], b: &[Point], tolerance_m: f64) -> Overlap {
+    let along = cumulative_distances(a);
+    let matches: Vec<(f64, Option<f64>)> = a
+        .iter()
+        .map(|p| match project_on_track(p, b) {
+            Some(r) => (r.distance_m, Some(r.distance_along_m)),
+            None => (distance_to_line(p, b), None),
+        })
+        .collect();
+
+    // Start index of every run of points on the same side of the tolerance
+    let is_shared = |i: usize| matches[i].0 <= tolerance_m;
+    let starts: Vec<usize> = (0..a.len())
+        .filter(|&i| i == 0 || is_shared(i) != is_shared(i - 1))
+        .collect();
+
+    let mut stretches = Vec::with_capacity(starts.len());
+    for (k, &start) in starts.iter().enumerate() {
+        let next = starts.get(k + 1).copied();
+        let end = next.unwrap_or(a.len() - 1);
+        let members = &matches[start..next.unwrap_or(a.len())];
+        let shared = is_shared(start);
+        let other = members.iter().filter_map(|m| m.1);
+        let (other_start_m, other_end_m) = if shared {
+            (other.clone().reduce(f64::min), other.reduce(f64::max))
+        } else {
+            (None, None)
+        };
+        stretches.push(Stretch {
+            shared,
+            start_index: start,
+            end_index: end,
+            start_m: along[start],
+            end_m: along[end],
+            other_start_m,
+            other_end_m,
+            max_distance_m: members.iter().map(|m| m.0).fold(0.0, f64::max),
+        });
+    }
+
+    let total = along.last().copied().unwrap_or(0.0);
+    let shared_m: f64 = stretches.iter().filter(|s| s.shared).map(|s| s.end_m - s.start_m).sum();
+    let other_total = track_length(b);
+    Overlap {
+        shared_m,
+        divergent_m: total - shared_m,
+        shared_fraction: if total > 0.0 { shared_m / total } else { 0.0 },
+        covered_fraction: if other_total > 0.0 {
+            covered_length(&stretches) / other_total
+        } else {
+            0.0
+        },
+        stretches,
+        other_along_m: matches.iter().map(|m| m.1).collect(),
+    }
+}
+
+/// Hausdorff and Fréchet distance plus the overlap of `a` with `b`.
+///
+/// Fréchet distance is taken on copies of both lines resampled at
+/// `tolerance_m` spacing (coarser for very long lines, to bound the
+/// work), so that differently sampled recordings compare fairly.
+pub fn compare(a: &[Point], b: &[Point], tolerance_m: f64) -> Comparison {
+    let longest = track_length(a).max(track_length(b));
+    let spacing = tolerance_m.max(longest / MAX_FRECHET_POINTS);
+    Comparison {
+        hausdorff_m: hausdorff_m(a, b),
+        frechet_m: frechet_m(&resampled(a, spacing), &resampled(b, spacing)),
+        overlap: overlap(a, b, tolerance_m),
+    }
+}
+
+/// Compare two JSON point lists and return a JSON `Comparison`.
+pub fn compare_json(a_json: &str, b_json: &str, tolerance_m: f64) -> Result<String, String> {
+    let a: Vec<Point> = serde_json::from_str(a_json)
+        .map_err(|e| format!("Points JSON parse failed: {e}"))?;
+    let b: Vec<Point> = serde_json::from_str(b_json)
+        .map_err(|e| format!("Points JSON parse failed: {e}"))?;
+    if !(tolerance_m.is_finite() && tolerance_m > 0.0) {
+        return Err(format!("Tolerance must be positive, got {tolerance_m}"));
+    }
+    serde_json::to_string(&compare(&a, &b, tolerance_m))
+        .map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// Upper bound on the resampled length of each line in [`compare`].
+const MAX_FRECHET_POINTS: f64 = 2000.0;
+
+/// A line resampled for [`frechet_m`]. The spacing chosen by [`compare`]
+/// keeps the result far below the resampling limit; should it not, the
+/// line is used as it is.
+fn resampled(line: &[Point], spacing: f64) -> Vec<Point> {
+    resample_by_distance(line, spacing).unwrap_or_else(|_| line.to_vec())
+}
+
+/// Distance from a point to the nearest part of a line in meters.
+fn distance_to_line(p: &Point, line: &[Point]) -> f64 {
+    match line {
+        [] => f64::INFINITY,
+        [only] => haversine(p, only),
+        _ => project_on_track(p, line).map_or(f64::INFINITY, |r| r.distance_m),
+    }
+}
+
+/// Length of the union of the ranges along the second line matched by
+/// shared stretches.
+fn covered_length(stretches: &[Stretch]) -> f64 {
+    let mut ranges: Vec<(f64, f64)> = stretches
+        .iter()
+        .filter_map(|s| Some((s.other_start_m?, s.other_end_m?)))
+        .collect();
+    ranges.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let mut covered = 0.0;
+    let mut current: Option<(f64, f64)> = None;
+    for (start, end) in ranges {
+        match current {
+            Some((s, e)) if start <= e => current = Some((s, e.max(end))),
+            Some((s, e)) => {
+                covered += e - s;
+                current = Some((start, end));
+            }
+            None => current = Some((start, end)),
+        }
+    }
+    covered + current.map_or(0.0, |(s, e)| e - s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(lat: f64, lon: f64) -> Point {
+        Point { lat, lon, ele: None, time: None, speed: None, course: None }
+    }
+
+    /// A line going east at 48°N from `from` to `to` degrees longitude
+    /// in `n` segments, shifted north by `offset_lat` degrees.
+    fn east(from: f64, to: f64, n: usize, offset_lat: f64) -> Vec<Point> {
+        (0..=n)
+            .map(|i| pt(48.0 + offset_lat, from + (to - from) * i as f64 / n as f64))
+            .collect()
+    }
+
+    #[test]
+    fn hausdorff_ignores_sampling_and_direction() {
+        let coarse = east(16.0, 16.0134, 2, 0.0);
+        let mut fine = east(16.0, 16.0134, 50, 0.0001);
+        assert!((hausdorff_m(&coarse, &fine) - 11.1).abs() < 0.2);
+        fine.reverse();
+        assert!((hausdorff_m(&fine, &coarse) - 11.1).abs() < 0.2);
+        // Half the line only: the missing half is far from it
+        assert!(hausdorff_m(&coarse, &fine[..25]) > 400.0);
+    }
+
+    #[test]
+    fn frechet_respects_direction() {
+        let a = east(16.0, 16.01, 10, 0.0);
+        let b = east(16.0, 16.01, 10, 0.0001);
+        assert!((frechet_m(&a, &b) - 11.1).abs() < 0.2);
+        let mut reversed = b.clone();
+        reversed.reverse();
+        // Start meets end: the leash must span the whole line
+        assert!(frechet_m(&a, &reversed) > 700.0);
+        assert!(hausdorff_m(&a, &reversed) < 12.0);
+        assert!(frechet_m(&a, &[]).is_infinite());
+    }
+
+    #[test]
+    fn overlap_finds_shared_and_divergent_stretches() {
+        // Plan: straight east over 0.02°. Ride: along it, a detour 500 m
+        // north for the middle third, then back on the plan.
+        let plan = east(16.0, 16.02, 2, 0.0);
+        let mut ride = east(16.0, 16.02, 30, 0.0);
+        for p in &mut ride[11..=19] {
+            p.lat += 0.0045;
+        }
+        let result = overlap(&ride, &plan, 30.0);
+        let kinds: Vec<bool> = result.stretches.iter().map(|s| s.shared).collect();
+        assert_eq!(kinds, [true, false, true]);
+        assert_eq!(result.stretches[1].start_index, 11);
+        assert_eq!(result.stretches[1].end_index, 20);
+        assert!(result.stretches[1].max_distance_m > 490.0);
+        assert!(result.stretches[0].max_distance_m < 1.0);
+        let first = &result.stretches[0];
+        assert_eq!(first.other_start_m, Some(0.0));
+        // The last shared point before the detour is point 10
+        assert!((first.other_end_m.unwrap() - haversine(&plan[0], &ride[10])).abs() < 1.0);
+        assert!(result.shared_fraction > 0.4 && result.shared_fraction < 0.8);
+        assert!((result.shared_m + result.divergent_m - track_length(&ride)).abs() < 1e-6);
+        // The detour skips about 10/30 of the plan
+        assert!((result.covered_fraction - 20.0 / 30.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn overlap_of_disjoint_lines() {
+        let result = overlap(&east(16.0, 16.01, 5, 0.0), &east(17.0, 17.01, 5, 0.0), 30.0);
+        assert_eq!(result.stretches.len(), 1);
+        assert!(!result.stretches[0].shared);
+        assert_eq!(result.shared_m, 0.0);
+        assert_eq!(result.covered_fraction, 0.0);
+    }
+
+    #[test]
+    fn compare_json_reports_all_measures() {
+        let a = serde_json::to_string(&east(16.0, 16.01, 3, 0.0)).unwrap();
+        let b = serde_json::to_string(&east(16.0, 16.01, 40, 0.0001)).unwrap();
+        let v: serde_json::Value =
+            serde_json::from_str(&compare_json(&a, &b, 20.0).unwrap()).unwrap();
+        assert!(v["hausdorff_m"].as_f64().unwrap() < 12.0);
+        assert!(v["frechet_m"].as_f64().unwrap() < 25.0);
+        assert_eq!(v["shared_fraction"], 1.0);
+        assert_eq!(v["stretches"].as_array().unwrap().len(), 1);
+        assert!(compare_json(&a, &b, 0.0).is_err());
+        assert!(compare_json("{}", &b, 20.0).is_err());
+    }
+}
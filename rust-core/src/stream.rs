@@ -0,0 +1,658 @@
+//! Streaming GPX reading.
+//!
+//! Reads GPX straight from the XML event stream and yields tracks,
+//! routes, and points one event at a time, without building the full
+//! document tree first. Memory use is bounded by the largest single
+//! element rather than the file size, which matters for multi-week
+//! recordings of 50+ MB on low-end phones.
+//!
+//! Elements are matched by local name only, so missing or foreign
+//! namespaces and GPX 1.0 documents are read the same way as GPX 1.1.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use xml::common::Position;
+use xml::reader::{ErrorKind, EventReader, ParserConfig, ParserConfig2, XmlEvent};
+
+use crate::gpx::{
+    parse_position, parse_timestamp, Bounds, GpxData, GpxVersion, ParseWarning, Point, Route, Track,
+    WarningKind, Waypoint,
+};
+use crate::nav::haversine;
+
+/// A single piece of GPX content, in document order.
+#[derive(Debug, Clone)]
+pub enum GpxEvent {
+    TrackStart,
+    /// Start of a `<trkseg>` in the enclosing track.
+    SegmentStart,
+    TrackEnd,
+    RouteStart,
+    RouteEnd,
+    /// Name of the enclosing track or route.
+    Name(String),
+    /// A point of the enclosing track or route.
+    Point(Point),
+    Waypoint(Waypoint),
+}
+
+/// Pull parser that turns a GPX byte stream into [`GpxEvent`]s.
+///
+/// In strict mode any problem ends the stream with an error. In lenient
+/// mode problems are recorded as warnings and the stream continues;
+/// a fatal XML error or truncated file ends it after closing any open
+/// track or route.
+pub struct GpxReader<R: Read> {
+    xml: EventReader<R>,
+    lenient: bool,
+    seen_root: bool,
+    version: Option<GpxVersion>,
+    done: bool,
+    path: Vec<String>,
+    text: String,
+    in_track: bool,
+    in_route: bool,
+    point: Option<PendingPoint>,
+    queue: VecDeque<GpxEvent>,
+    warnings: Vec<ParseWarning>,
+}
+
+/// A point currently being read, with the child values seen so far.
+struct PendingPoint {
+    point: Option<Point>,
+    name: Option<String>,
+    icon: Option<String>,
+}
+
+impl<R: Read> GpxReader<R> {
+    /// Create a strict reader.
+    pub fn new(reader: R) -> Self {
+        let config = ParserConfig::new().cdata_to_characters(true);
+        Self::with_config(reader, config, false)
+    }
+
+    /// Create a lenient reader that records problems as warnings.
+    pub fn lenient(reader: R) -> Self {
+        let config = ParserConfig::new()
+            .cdata_to_characters(true)
+            .ignore_invalid_encoding_declarations(true);
+        Self::with_config(reader, config, true)
+    }
+
+    pub(crate) fn with_config(
+        reader: R,
+        config: impl Into<ParserConfig2>,
+        lenient: bool,
+    ) -> Self {
+        Self {
+            xml: config.into().create_reader(reader),
+            lenient,
+            seen_root: false,
+            version: None,
+            done: false,
+            path: Vec::new(),
+            text: String::new(),
+            in_track: false,
+            in_route: false,
+            point: None,
+            queue: VecDeque::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// GPX version declared by the root element, once it has been read.
+    ///
+    /// Taken from the `version` attribute, or from the namespace when
+    /// the attribute is missing.
+    pub fn version(&self) -> Option<GpxVersion> {
+        self.version
+    }
+
+    /// Take the warnings collected so far (lenient mode only).
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Record a problem: a warning in lenient mode, an error otherwise.
+    fn problem(&mut self, kind: WarningKind, line: u64, message: String) -> Result<(), String> {
+        if self.lenient {
+            self.warnings.push(ParseWarning {
+                kind,
+                line: Some(line),
+                message,
+            });
+            Ok(())
+        } else {
+            Err(format!("GPX parse error at line {line}: {message}"))
+        }
+    }
+
+    fn handle(&mut self, event: XmlEvent, line: u64) -> Result<(), String> {
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let local = name.local_name;
+                if !self.seen_root {
+                    if local != "gpx" {
+                        let message = format!("root element is <{local}>, not <gpx>");
+                        return Err(format!("GPX parse error: {message}"));
+                    }
+                    self.seen_root = true;
+                    let declared = attributes
+                        .iter()
+                        .find(|a| a.name.local_name == "version")
+                        .map(|a| a.value.trim());
+                    self.version = match (declared, name.namespace.as_deref()) {
+                        (Some("1.0"), _) => Some(GpxVersion::Gpx10),
+                        (Some("1.1"), _) => Some(GpxVersion::Gpx11),
+                        (_, Some(ns)) if ns.ends_with("/GPX/1/0") => Some(GpxVersion::Gpx10),
+                        (_, Some(ns)) if ns.ends_with("/GPX/1/1") => Some(GpxVersion::Gpx11),
+                        _ => None,
+                    };
+                }
+                self.text.clear();
+                match local.as_str() {
+                    "trk" => {
+                        self.in_track = true;
+                        self.queue.push_back(GpxEvent::TrackStart);
+                    }
+                    "trkseg" if self.in_track => self.queue.push_back(GpxEvent::SegmentStart),
+                    "rte" => {
+                        self.in_route = true;
+                        self.queue.push_back(GpxEvent::RouteStart);
+                    }
+                    "trkpt" | "rtept" | "wpt" => {
+                        let attr = |key: &str| {
+                            attributes
+                                .iter()
+                                .find(|a| a.name.local_name == key)
+                                .map(|a| a.value.trim().to_string())
+                        };
+                        let position = match (attr("lat"), attr("lon")) {
+                            (Some(lat), Some(lon)) => parse_position(&lat, &lon),
+                            _ => Err("missing lat/lon".to_string()),
+                        };
+                        let point = match position {
+                            Ok(point) => Some(point),
+                            Err(reason) => {
+                                let message = format!("dropped <{local}>: {reason}");
+                                self.problem(WarningKind::InvalidCoordinate, line, message)?;
+                                None
+                            }
+                        };
+                        self.point = Some(PendingPoint {
+                            point,
+                            name: None,
+                            icon: None,
+                        });
+                    }
+                    _ => {}
+                }
+                self.path.push(local);
+            }
+            XmlEvent::Characters(s) => self.text.push_str(&s),
+            XmlEvent::EndElement { .. } => {
+                let Some(local) = self.path.pop() else {
+                    return Ok(());
+                };
+                let parent = self.path.last().map(String::as_str);
+                let text = std::mem::take(&mut self.text);
+                let text = text.trim();
+                match (local.as_str(), parent) {
+                    ("ele", Some("trkpt" | "rtept" | "wpt")) => {
+                        match text.parse::<f64>() {
+                            Ok(ele) if ele.is_finite() => {
+                                if let Some(p) = self.pending_point() {
+                                    p.ele = Some(ele);
+                                }
+                            }
+                            _ => {
+                                let message = format!("ignored elevation \"{text}\"");
+                                self.problem(WarningKind::InvalidValue, line, message)?;
+                            }
+                        }
+                    }
+                    ("time", Some("trkpt" | "rtept" | "wpt")) => match parse_timestamp(text) {
+                        Some(time) => {
+                            if let Some(p) = self.pending_point() {
+                                p.time = Some(time);
+                            }
+                        }
+                        None => {
+                            let message = format!("ignored time \"{text}\"");
+                            self.problem(WarningKind::InvalidValue, line, message)?;
+                        }
+                    },
+                    // GPX 1.0 elements, or Garmin TrackPointExtension v2 in GPX 1.1
+                    (
+                        "speed" | "course",
+                        Some("trkpt" | "rtept" | "wpt" | "TrackPointExtension"),
+                    ) => match text.parse::<f64>() {
+                        // Loggers write a negative speed when it is unknown
+                        Ok(v) if local == "speed" && v < 0.0 => {}
+                        Ok(v) if v.is_finite() && v >= 0.0 => {
+                            if let Some(p) = self.pending_point() {
+                                if local == "speed" {
+                                    p.speed = Some(v);
+                                } else {
+                                    p.course = Some(v % 360.0);
+                                }
+                            }
+                        }
+                        _ => {
+                            let message = format!("ignored {local} \"{text}\"");
+                            self.problem(WarningKind::InvalidValue, line, message)?;
+                        }
+                    },
+                    ("name", Some("trkpt" | "rtept" | "wpt")) => {
+                        if let Some(p) = self.point.as_mut() {
+                            p.name = Some(text.to_string());
+                        }
+                    }
+                    ("sym", Some("trkpt" | "rtept" | "wpt")) => {
+                        if let Some(p) = self.point.as_mut() {
+                            p.icon = Some(text.to_string());
+                        }
+                    }
+                    ("name", Some("trk" | "rte")) => {
+                        self.queue.push_back(GpxEvent::Name(text.to_string()));
+                    }
+                    ("trkpt" | "rtept" | "wpt", _) => self.finish_point(&local, line)?,
+                    ("trk", _) => {
+                        self.in_track = false;
+                        self.queue.push_back(GpxEvent::TrackEnd);
+                    }
+                    ("rte", _) => {
+                        self.in_route = false;
+                        self.queue.push_back(GpxEvent::RouteEnd);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn pending_point(&mut self) -> Option<&mut Point> {
+        self.point.as_mut().and_then(|p| p.point.as_mut())
+    }
+
+    fn finish_point(&mut self, element: &str, line: u64) -> Result<(), String> {
+        let Some(pending) = self.point.take() else {
+            return Ok(());
+        };
+        let Some(point) = pending.point else {
+            return Ok(());
+        };
+        let inside = match element {
+            "trkpt" => self.in_track,
+            "rtept" => self.in_route,
+            _ => true,
+        };
+        if !inside {
+            let parent = if element == "trkpt" { "trk" } else { "rte" };
+            let message = format!("dropped <{element}> outside of <{parent}>");
+            return self.problem(WarningKind::SkippedElement, line, message);
+        }
+        self.queue.push_back(match element {
+            "wpt" => GpxEvent::Waypoint(Waypoint {
+                name: pending.name,
+                point,
+                icon: pending.icon,
+            }),
+            _ => GpxEvent::Point(point),
+        });
+        Ok(())
+    }
+
+    /// Close anything still open after a truncated or unreadable file.
+    fn flush(&mut self) {
+        if let Some(point) = self.point.take().and_then(|p| p.point) {
+            if self.in_track || self.in_route {
+                self.queue.push_back(GpxEvent::Point(point));
+            }
+        }
+        if std::mem::take(&mut self.in_track) {
+            self.queue.push_back(GpxEvent::TrackEnd);
+        }
+        if std::mem::take(&mut self.in_route) {
+            self.queue.push_back(GpxEvent::RouteEnd);
+        }
+    }
+}
+
+impl<R: Read> Iterator for GpxReader<R> {
+    type Item = Result<GpxEvent, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+
+            let result = match self.xml.next() {
+                Ok(XmlEvent::EndDocument) => {
+                    self.done = true;
+                    Ok(())
+                }
+                Ok(event) => {
+                    let line = self.xml.position().row + 1;
+                    self.handle(event, line)
+                }
+                Err(e) if self.lenient && self.seen_root => {
+                    self.done = true;
+                    // xml-rs reports most premature endings as syntax errors
+                    let truncated = matches!(e.kind(), ErrorKind::UnexpectedEof)
+                        || e.msg().starts_with("Unexpected end of stream");
+                    let (kind, message) = if truncated {
+                        (WarningKind::Truncated, "file is truncated".to_string())
+                    } else {
+                        (
+                            WarningKind::SkippedContent,
+                            format!("unreadable XML, rest skipped: {}", e.msg()),
+                        )
+                    };
+                    self.warnings.push(ParseWarning {
+                        kind,
+                        line: Some(e.position().row + 1),
+                        message,
+                    });
+                    self.flush();
+                    Ok(())
+                }
+                Err(e) => Err(format!("GPX parse error: {e}")),
+            };
+
+            if let Err(e) = result {
+                self.done = true;
+                self.queue.clear();
+                return Some(Err(e));
+            }
+            if self.done && !self.seen_root {
+                return Some(Err("GPX parse error: no <gpx> root element".to_string()));
+            }
+        }
+    }
+}
+
+/// Which kind of item the most recent start event opened.
+enum Open {
+    None,
+    Track,
+    Route,
+}
+
+/// Drain a reader into `GpxData`.
+///
+/// Multi-segment tracks are flattened into a single point list, as
+/// with [`crate::gpx::parse`].
+pub fn collect<R: Read>(reader: &mut GpxReader<R>) -> Result<GpxData, String> {
+    let mut data = GpxData::default();
+    let mut open = Open::None;
+
+    for event in reader.by_ref() {
+        match event? {
+            GpxEvent::TrackStart => {
+                data.tracks.push(Track {
+                    name: None,
+                    points: Vec::new(),
+                });
+                open = Open::Track;
+            }
+            GpxEvent::RouteStart => {
+                data.routes.push(Route {
+                    name: None,
+                    points: Vec::new(),
+                });
+                open = Open::Route;
+            }
+            // Segments are flattened
+            GpxEvent::SegmentStart => {}
+            GpxEvent::TrackEnd | GpxEvent::RouteEnd => open = Open::None,
+            GpxEvent::Name(name) => match open {
+                Open::Track => data.tracks.last_mut().unwrap().name = Some(name),
+                Open::Route => data.routes.last_mut().unwrap().name = Some(name),
+                Open::None => {}
+            },
+            GpxEvent::Point(point) => match open {
+                Open::Track => data.tracks.last_mut().unwrap().points.push(point),
+                Open::Route => data.routes.last_mut().unwrap().points.push(point),
+                Open::None => {}
+            },
+            GpxEvent::Waypoint(wp) => data.waypoints.push(wp),
+        }
+    }
+
+    data.version = reader.version();
+    data.tracks.shrink_to_fit();
+    for track in &mut data.tracks {
+        track.points.shrink_to_fit();
+    }
+    Ok(data)
+}
+
+/// Parse GPX from any reader without building an intermediate tree.
+pub fn parse_streaming<R: Read>(reader: R) -> Result<GpxData, String> {
+    collect(&mut GpxReader::new(reader))
+}
+
+/// Parse a GPX file from disk with bounded read buffering.
+pub fn parse_file(path: &str) -> Result<GpxData, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {path}: {e}"))?;
+    parse_streaming(BufReader::new(file))
+}
+
+/// Parse a GPX file from disk and return the result as a JSON string.
+pub fn parse_file_to_json(path: &str) -> Result<String, String> {
+    let data = parse_file(path)?;
+    serde_json::to_string(&data).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+// -- Summary --
+
+/// Overview of a GPX file, computed without keeping any points.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GpxSummary {
+    pub tracks: usize,
+    pub routes: usize,
+    pub waypoints: usize,
+    pub track_points: usize,
+    pub route_points: usize,
+    /// Names of all tracks and routes, in document order.
+    pub names: Vec<String>,
+    /// Bounding box of all points, absent for an empty file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+    /// Combined length of all tracks in meters.
+    pub track_length_m: f64,
+    /// Earliest and latest track point timestamps, in Unix seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<f64>,
+}
+
+/// Read a GPX stream and compute its summary.
+pub fn summarize<R: Read>(reader: R) -> Result<GpxSummary, String> {
+    let mut summary = GpxSummary::default();
+    let mut open = Open::None;
+    let mut previous: Option<Point> = None;
+
+    for event in GpxReader::new(reader) {
+        match event? {
+            GpxEvent::TrackStart => {
+                summary.tracks += 1;
+                open = Open::Track;
+                previous = None;
+            }
+            // The gap between segments is not part of the track length
+            GpxEvent::SegmentStart => previous = None,
+            GpxEvent::RouteStart => {
+                summary.routes += 1;
+                open = Open::Route;
+            }
+            GpxEvent::TrackEnd | GpxEvent::RouteEnd => open = Open::None,
+            GpxEvent::Name(name) => summary.names.push(name),
+            GpxEvent::Point(point) => {
+                extend_bounds(&mut summary.bounds, &point);
+                match open {
+                    Open::Track => {
+                        summary.track_points += 1;
+                        if let Some(prev) = &previous {
+                            summary.track_length_m += haversine(prev, &point);
+                        }
+                        if let Some(t) = point.time {
+                            summary.start_time = Some(summary.start_time.map_or(t, |s| s.min(t)));
+                            summary.end_time = Some(summary.end_time.map_or(t, |e| e.max(t)));
+                        }
+                        previous = Some(point);
+                    }
+                    Open::Route => summary.route_points += 1,
+                    Open::None => {}
+                }
+            }
+            GpxEvent::Waypoint(wp) => {
+                summary.waypoints += 1;
+                extend_bounds(&mut summary.bounds, &wp.point);
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn extend_bounds(bounds: &mut Option<Bounds>, p: &Point) {
+    match bounds {
+        Some(b) => b.extend(p),
+        None => *bounds = Some(Bounds::from_point(p)),
+    }
+}
+
+/// Summarize a GPX file from disk and return the result as a JSON string.
+pub fn summarize_file_to_json(path: &str) -> Result<String, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {path}: {e}"))?;
+    let summary = summarize(BufReader::new(file))?;
+    serde_json::to_string(&summary).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GPX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test"
+     xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="48.5" lon="16.5"><name>Hut</name><sym>hotel</sym></wpt>
+  <trk>
+    <name>Day 1</name>
+    <trkseg>
+      <trkpt lat="48.0" lon="16.0"><time>2024-05-01T10:00:00Z</time></trkpt>
+      <trkpt lat="48.0" lon="16.1"><time>2024-05-01T10:10:00Z</time></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="48.1" lon="16.1"><ele>300</ele></trkpt>
+    </trkseg>
+  </trk>
+  <rte>
+    <name>Plan</name>
+    <rtept lat="47.9" lon="15.9"></rtept>
+    <rtept lat="48.2" lon="16.2"></rtept>
+  </rte>
+</gpx>"#;
+
+    #[test]
+    fn events_in_document_order() {
+        let events: Vec<GpxEvent> = GpxReader::new(GPX.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert!(matches!(events[0], GpxEvent::Waypoint(_)));
+        assert!(matches!(events[1], GpxEvent::TrackStart));
+        assert!(matches!(&events[2], GpxEvent::Name(n) if n == "Day 1"));
+        assert!(matches!(events[3], GpxEvent::SegmentStart));
+        assert!(matches!(events[4], GpxEvent::Point(_)));
+        assert!(matches!(events[6], GpxEvent::SegmentStart));
+        assert!(matches!(events[8], GpxEvent::TrackEnd));
+        assert!(matches!(events.last(), Some(GpxEvent::RouteEnd)));
+    }
+
+    #[test]
+    fn streaming_matches_tree_parser() {
+        let streamed = parse_streaming(GPX.as_bytes()).unwrap();
+        let tree = crate::gpx::parse_bytes(GPX.as_bytes()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&streamed).unwrap(),
+            serde_json::to_value(&tree).unwrap()
+        );
+    }
+
+    #[test]
+    fn strict_reader_rejects_bad_coordinates() {
+        let gpx = r#"<gpx><trk><trkseg><trkpt lat="99" lon="0"/></trkseg></trk></gpx>"#;
+        let err = parse_streaming(gpx.as_bytes()).unwrap_err();
+        assert!(err.contains("out of range"), "{err}");
+    }
+
+    #[test]
+    fn strict_reader_rejects_non_gpx() {
+        assert!(parse_streaming(&b"<kml></kml>"[..]).is_err());
+        assert!(parse_streaming(&b"not xml at all"[..]).is_err());
+        assert!(parse_streaming(&b""[..]).is_err());
+    }
+
+    #[test]
+    fn summary_counts_without_points() {
+        let summary = summarize(GPX.as_bytes()).unwrap();
+
+        assert_eq!(summary.tracks, 1);
+        assert_eq!(summary.routes, 1);
+        assert_eq!(summary.waypoints, 1);
+        assert_eq!(summary.track_points, 3);
+        assert_eq!(summary.route_points, 2);
+        assert_eq!(summary.names, vec!["Day 1", "Plan"]);
+
+        let bounds = summary.bounds.unwrap();
+        assert_eq!((bounds.min_lat, bounds.max_lat), (47.9, 48.5));
+        assert_eq!((bounds.min_lon, bounds.max_lon), (15.9, 16.5));
+
+        // 0.1 deg of longitude (~7.4 km) in the first segment; the jump
+        // to the second segment does not count
+        assert!(summary.track_length_m > 7_000.0 && summary.track_length_m < 8_000.0);
+        assert_eq!(summary.start_time, Some(1_714_557_600.0));
+        assert_eq!(summary.end_time, Some(1_714_558_200.0));
+    }
+
+    #[test]
+    fn summary_of_empty_file() {
+        let gpx = r#"<gpx version="1.1"></gpx>"#;
+        let summary = summarize(gpx.as_bytes()).unwrap();
+        assert_eq!(summary.tracks, 0);
+        assert!(summary.bounds.is_none());
+    }
+
+    #[test]
+    fn parse_file_reads_from_disk() {
+        let path = std::env::temp_dir().join("ndkarte_stream_test.gpx");
+        std::fs::write(&path, GPX).unwrap();
+
+        let data = parse_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(data.tracks[0].points.len(), 3);
+
+        let json = summarize_file_to_json(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(json.contains("\"track_points\":3"));
+    }
+
+    #[test]
+    fn parse_file_missing() {
+        assert!(parse_file("/nonexistent/file.gpx").is_err());
+    }
+}
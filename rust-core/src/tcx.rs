@@ -0,0 +1,303 @@
+//! Garmin Training Center (TCX) activity and course parsing.
+//!
+//! Each `<Activity>` becomes one track (its laps are flattened, like
+//! multi-segment GPX tracks) named after the activity `<Id>`. Each
+//! `<Course>` becomes a track named after its `<Name>`, and its
+//! `<CoursePoint>`s become waypoints. Trackpoints without a position
+//! (heart-rate-only samples recorded before a GPS fix) are skipped.
+
+use xml::reader::{ParserConfig, XmlEvent};
+
+use crate::gpx::{parse_position, parse_timestamp, GpxData, Point, Track, Waypoint};
+
+/// Whether the bytes look like a TCX document.
+///
+/// Checks for the `TrainingCenterDatabase` root element near the start
+/// of the file, after the XML declaration and any comments.
+pub fn is_tcx(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(1024)];
+    String::from_utf8_lossy(head).contains("<TrainingCenterDatabase")
+}
+
+/// Parse a TCX document into `GpxData`.
+pub fn parse_tcx(data: &[u8]) -> Result<GpxData, String> {
+    let config = ParserConfig::new()
+        .cdata_to_characters(true)
+        .trim_whitespace(true);
+    let mut reader = config.create_reader(data);
+    let mut builder = TcxBuilder::default();
+    let mut seen_root = false;
+
+    loop {
+        match reader.next() {
+            Ok(XmlEvent::EndDocument) => break,
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                if !seen_root {
+                    if name.local_name != "TrainingCenterDatabase" {
+                        return Err(format!(
+                            "TCX parse error: root element is <{}>, not <TrainingCenterDatabase>",
+                            name.local_name
+                        ));
+                    }
+                    seen_root = true;
+                }
+                builder.start(name.local_name);
+            }
+            Ok(XmlEvent::Characters(s)) => builder.text.push_str(&s),
+            Ok(XmlEvent::EndElement { .. }) => builder.end()?,
+            Ok(_) => {}
+            Err(e) => return Err(format!("TCX parse error: {e}")),
+        }
+    }
+
+    Ok(builder.data)
+}
+
+/// Parse a TCX document and return the result as GPX JSON.
+pub fn parse_to_json(data: &[u8]) -> Result<String, String> {
+    let gpx_data = parse_tcx(data)?;
+    serde_json::to_string(&gpx_data).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+/// Values of the `<Trackpoint>` or `<CoursePoint>` being read.
+#[derive(Default)]
+struct PendingPoint {
+    lat: Option<String>,
+    lon: Option<String>,
+    ele: Option<f64>,
+    time: Option<f64>,
+    name: Option<String>,
+}
+
+impl PendingPoint {
+    /// The point, or `None` for samples without a position such as
+    /// heart-rate-only ones. Positions are checked like GPX coordinates.
+    fn into_point(self) -> Result<Option<Point>, String> {
+        let (Some(lat), Some(lon)) = (self.lat, self.lon) else {
+            return Ok(None);
+        };
+        let position = parse_position(&lat, &lon)
+            .map_err(|e| format!("TCX parse error: bad position, {e}"))?;
+        Ok(Some(Point {
+            ele: self.ele,
+            time: self.time,
+            ..position
+        }))
+    }
+}
+
+#[derive(Default)]
+struct TcxBuilder {
+    data: GpxData,
+    path: Vec<String>,
+    text: String,
+    track: Option<Track>,
+    point: Option<PendingPoint>,
+}
+
+impl TcxBuilder {
+    fn start(&mut self, local: String) {
+        self.text.clear();
+        match local.as_str() {
+            "Activity" | "Course" => {
+                self.track = Some(Track {
+                    name: None,
+                    points: Vec::new(),
+                })
+            }
+            "Trackpoint" | "CoursePoint" => self.point = Some(PendingPoint::default()),
+            _ => {}
+        }
+        self.path.push(local);
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        let Some(local) = self.path.pop() else {
+            return Ok(());
+        };
+        let parent = self.path.last().map(String::as_str);
+        let text = std::mem::take(&mut self.text);
+
+        let number = |text: &str| {
+            text.parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| format!("TCX parse error: bad <{local}> value \"{text}\""))
+        };
+
+        match (local.as_str(), parent) {
+            ("Id", Some("Activity")) | ("Name", Some("Course")) => {
+                if let Some(track) = self.track.as_mut() {
+                    track.name = Some(text);
+                }
+            }
+            ("Name", Some("CoursePoint")) => {
+                if let Some(p) = self.point.as_mut() {
+                    p.name = Some(text);
+                }
+            }
+            ("Time", Some("Trackpoint" | "CoursePoint")) => {
+                if let Some(p) = self.point.as_mut() {
+                    p.time = parse_timestamp(&text);
+                }
+            }
+            ("LatitudeDegrees", Some("Position")) => {
+                if let Some(p) = self.point.as_mut() {
+                    p.lat = Some(text);
+                }
+            }
+            ("LongitudeDegrees", Some("Position")) => {
+                if let Some(p) = self.point.as_mut() {
+                    p.lon = Some(text);
+                }
+            }
+            ("AltitudeMeters", Some("Trackpoint" | "CoursePoint")) => {
+                let ele = number(&text)?;
+                if let Some(p) = self.point.as_mut() {
+                    p.ele = Some(ele);
+                }
+            }
+            ("Trackpoint", _) => {
+                let point = self.point.take().map(PendingPoint::into_point).transpose()?.flatten();
+                if let (Some(point), Some(track)) = (point, self.track.as_mut()) {
+                    track.points.push(point);
+                }
+            }
+            ("CoursePoint", _) => {
+                if let Some(pending) = self.point.take() {
+                    let name = pending.name.clone();
+                    if let Some(point) = pending.into_point()? {
+                        self.data.waypoints.push(Waypoint {
+                            name,
+                            point,
+                            icon: None,
+                        });
+                    }
+                }
+            }
+            ("Activity" | "Course", _) => {
+                if let Some(track) = self.track.take() {
+                    self.data.tracks.push(track);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIVITY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Other">
+      <Id>2024-05-01T10:00:00Z</Id>
+      <Lap StartTime="2024-05-01T10:00:00Z">
+        <Track>
+          <Trackpoint>
+            <Time>2024-05-01T10:00:00Z</Time>
+            <HeartRateBpm><Value>90</Value></HeartRateBpm>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2024-05-01T10:00:01Z</Time>
+            <Position>
+              <LatitudeDegrees>48.2082</LatitudeDegrees>
+              <LongitudeDegrees>16.3738</LongitudeDegrees>
+            </Position>
+            <AltitudeMeters>171.4</AltitudeMeters>
+          </Trackpoint>
+        </Track>
+      </Lap>
+      <Lap StartTime="2024-05-01T10:05:00Z">
+        <Track>
+          <Trackpoint>
+            <Time>2024-05-01T10:05:00Z</Time>
+            <Position>
+              <LatitudeDegrees>48.2100</LatitudeDegrees>
+              <LongitudeDegrees>16.3760</LongitudeDegrees>
+            </Position>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+
+    const COURSE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Courses>
+    <Course>
+      <Name>Passo Giau</Name>
+      <Track>
+        <Trackpoint>
+          <Position><LatitudeDegrees>46.48</LatitudeDegrees><LongitudeDegrees>12.05</LongitudeDegrees></Position>
+          <AltitudeMeters>2236</AltitudeMeters>
+        </Trackpoint>
+      </Track>
+      <CoursePoint>
+        <Name>Summit</Name>
+        <Time>2024-05-01T11:00:00Z</Time>
+        <Position><LatitudeDegrees>46.48</LatitudeDegrees><LongitudeDegrees>12.05</LongitudeDegrees></Position>
+        <PointType>Summit</PointType>
+      </CoursePoint>
+    </Course>
+  </Courses>
+</TrainingCenterDatabase>"#;
+
+    #[test]
+    fn detects_signature() {
+        assert!(is_tcx(ACTIVITY.as_bytes()));
+        assert!(!is_tcx(b"<?xml version=\"1.0\"?><gpx></gpx>"));
+    }
+
+    #[test]
+    fn parse_activity_flattens_laps() {
+        let data = parse_tcx(ACTIVITY.as_bytes()).unwrap();
+
+        assert_eq!(data.tracks.len(), 1);
+        let track = &data.tracks[0];
+        assert_eq!(track.name.as_deref(), Some("2024-05-01T10:00:00Z"));
+        // The heart-rate-only sample has no position and is skipped
+        assert_eq!(track.points.len(), 2);
+        assert_eq!(track.points[0].lat, 48.2082);
+        assert_eq!(track.points[0].ele, Some(171.4));
+        assert_eq!(track.points[0].time, Some(1_714_557_601.0));
+        assert_eq!(track.points[1].ele, None);
+    }
+
+    #[test]
+    fn parse_course_with_points() {
+        let data = parse_tcx(COURSE.as_bytes()).unwrap();
+
+        assert_eq!(data.tracks[0].name.as_deref(), Some("Passo Giau"));
+        assert_eq!(data.tracks[0].points[0].ele, Some(2236.0));
+        assert_eq!(data.waypoints.len(), 1);
+        assert_eq!(data.waypoints[0].name.as_deref(), Some("Summit"));
+    }
+
+    #[test]
+    fn parse_rejects_other_documents() {
+        assert!(parse_tcx(b"<gpx></gpx>").is_err());
+        assert!(parse_tcx(b"garbage").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_bad_numbers() {
+        let tcx = ACTIVITY.replace("48.2082", "north");
+        assert!(parse_tcx(tcx.as_bytes()).is_err());
+        let tcx = ACTIVITY.replace("171.4", "NaN");
+        assert!(parse_tcx(tcx.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_positions() {
+        let tcx = ACTIVITY.replace("48.2082", "123").replace("16.3738", "NaN");
+        let err = parse_tcx(tcx.as_bytes()).unwrap_err();
+        assert!(err.starts_with("TCX parse error: bad position"), "{err}");
+        let tcx = ACTIVITY.replace("16.3738", "NaN");
+        assert!(parse_tcx(tcx.as_bytes()).is_err());
+    }
+}
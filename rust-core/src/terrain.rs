@@ -0,0 +1,915 @@
+//! Offline terrain tiles from DEM data.
+//!
+//! Renders the HGT tiles read by [`crate::dem`] into web mercator map
+//! tiles that MapLibre can show without a network connection:
+//!
+//! - hillshade rasters: grayscale PNG with alpha, shaded with Horn's
+//!   slope method from a configurable sun position
+//! - contour lines: Mapbox Vector Tiles with a `contour` layer whose
+//!   features carry `ele` (meters) and `index` (every n-th line)
+//!
+//! Both are written to MBTiles files (SQLite, TMS row order) so the
+//! style can reference them with `mbtiles://` sources.
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::{Compression, Crc};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::Write;
+use std::path::Path;
+
+use crate::dem::Dem;
+use crate::gpx::Bounds;
+use crate::nav::EARTH_RADIUS_M;
+
+/// Latitude limit of the web mercator projection.
+const MAX_MERCATOR_LAT: f64 = 85.051_128_78;
+
+/// Coordinate range of vector tile geometry.
+const MVT_EXTENT: u32 = 4096;
+
+/// Grid cells per contour tile side. 128 cells of 32 units each keeps
+/// lines smooth without sampling the DEM far beyond its resolution.
+const CONTOUR_CELLS: usize = 128;
+
+/// Name of the vector tile layer holding contour lines.
+const CONTOUR_LAYER: &str = "contour";
+
+/// Highest zoom level generated; beyond this even 1 arc-second DEM data
+/// only adds interpolation artifacts.
+const MAX_ZOOM: u8 = 16;
+
+/// Smallest contour interval in meters; finer steps are below the
+/// accuracy of the DEM and fill tiles with lines.
+const MIN_CONTOUR_INTERVAL_M: f64 = 1.0;
+
+/// Most tiles a single run may render, over all zoom levels, so that a
+/// large area at a high zoom is rejected up front instead of running
+/// for hours and filling the device.
+pub const MAX_TILES: u64 = 100_000;
+
+/// Options for hillshade and contour generation.
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainOptions {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// Contours are only generated from this zoom level up, since
+    /// lower zooms would be solid with lines.
+    pub contour_min_zoom: u8,
+    /// Elevation step between contour lines in meters.
+    pub contour_interval_m: f64,
+    /// Every n-th contour line is flagged as an index line.
+    pub index_every: u32,
+    /// Hillshade raster width and height in pixels.
+    pub tile_size: usize,
+    /// Direction the light comes from, clockwise from north, in degrees.
+    pub azimuth_deg: f64,
+    /// Height of the light above the horizon in degrees.
+    pub altitude_deg: f64,
+    /// Vertical exaggeration applied to slopes before shading.
+    pub exaggeration: f64,
+}
+
+impl Default for TerrainOptions {
+    fn default() -> Self {
+        Self {
+            min_zoom: 8,
+            max_zoom: 14,
+            contour_min_zoom: 11,
+            contour_interval_m: 20.0,
+            index_every: 5,
+            tile_size: 256,
+            azimuth_deg: 315.0,
+            altitude_deg: 45.0,
+            exaggeration: 1.0,
+        }
+    }
+}
+
+impl TerrainOptions {
+    fn check(&self, bounds: &Bounds) -> Result<(), String> {
+        if self.min_zoom > self.max_zoom || self.max_zoom > MAX_ZOOM {
+            return Err(format!(
+                "Invalid zoom range {}..{} (max {MAX_ZOOM})",
+                self.min_zoom, self.max_zoom
+            ));
+        }
+        if !self.contour_interval_m.is_finite()
+            || self.contour_interval_m < MIN_CONTOUR_INTERVAL_M
+        {
+            return Err(format!(
+                "Invalid contour interval {} (min {MIN_CONTOUR_INTERVAL_M} m)",
+                self.contour_interval_m
+            ));
+        }
+        if self.tile_size == 0 {
+            return Err("Tile size must be positive".into());
+        }
+        let tiles = tile_count(bounds, self.min_zoom, self.max_zoom);
+        if tiles > MAX_TILES {
+            return Err(format!(
+                "Area needs {tiles} tiles at zoom {}..{}, more than {MAX_TILES}",
+                self.min_zoom, self.max_zoom
+            ));
+        }
+        Ok(())
+    }
+}
+
+// -- Tile math --
+
+/// Longitude of a (fractional) tile column's western edge.
+pub fn tile_lon(x: f64, z: u8) -> f64 {
+    x / f64::from(1u32 << z) * 360.0 - 180.0
+}
+
+/// Latitude of a (fractional) tile row's northern edge.
+pub fn tile_lat(y: f64, z: u8) -> f64 {
+    let n = PI * (1.0 - 2.0 * y / f64::from(1u32 << z));
+    n.sinh().atan().to_degrees()
+}
+
+/// Tile column and row containing a position.
+pub fn tile_at(lat: f64, lon: f64, z: u8) -> (u32, u32) {
+    let n = f64::from(1u32 << z);
+    let lat = lat.clamp(-MAX_MERCATOR_LAT, MAX_MERCATOR_LAT).to_radians();
+    let x = (lon + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n;
+    let max = (1u32 << z) - 1;
+    ((x.max(0.0) as u32).min(max), (y.max(0.0) as u32).min(max))
+}
+
+/// All tiles at zoom `z` that intersect `bounds`, row by row.
+fn tiles_in(bounds: &Bounds, z: u8) -> impl Iterator<Item = (u32, u32)> {
+    let (x0, y0) = tile_at(bounds.max_lat, bounds.min_lon, z);
+    let (x1, y1) = tile_at(bounds.min_lat, bounds.max_lon, z);
+    (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| (x, y)))
+}
+
+/// Number of tiles [`tiles_in`] yields for `bounds` over a zoom range.
+pub fn tile_count(bounds: &Bounds, min_zoom: u8, max_zoom: u8) -> u64 {
+    (min_zoom..=max_zoom)
+        .map(|z| {
+            let (x0, y0) = tile_at(bounds.max_lat, bounds.min_lon, z);
+            let (x1, y1) = tile_at(bounds.min_lat, bounds.max_lon, z);
+            if x1 < x0 || y1 < y0 {
+                return 0;
+            }
+            u64::from(x1 - x0 + 1) * u64::from(y1 - y0 + 1)
+        })
+        .sum()
+}
+
+/// Sample DEM elevations on an `n` x `n` grid over tile (z, x, y).
+///
+/// The tile spans `cells` grid steps; sample `i` sits at `i + start`
+/// steps from the north-west corner.
+fn sample_grid(
+    dem: &mut Dem,
+    z: u8,
+    x: u32,
+    y: u32,
+    cells: usize,
+    start: f64,
+    n: usize,
+) -> Vec<Option<f64>> {
+    let step = 1.0 / cells as f64;
+    let lons: Vec<f64> = (0..n)
+        .map(|i| tile_lon(f64::from(x) + (i as f64 + start) * step, z))
+        .collect();
+    let mut grid = Vec::with_capacity(n * n);
+    for row in 0..n {
+        let lat = tile_lat(f64::from(y) + (row as f64 + start) * step, z);
+        grid.extend(lons.iter().map(|&lon| dem.elevation(lat, lon)));
+    }
+    grid
+}
+
+// -- Hillshade --
+
+/// Render the hillshade raster for one tile as PNG.
+///
+/// Returns `None` when the DEM has no data for the tile.
+pub fn hillshade_tile(
+    dem: &mut Dem,
+    z: u8,
+    x: u32,
+    y: u32,
+    options: &TerrainOptions,
+) -> Option<Vec<u8>> {
+    let size = options.tile_size;
+    // One pixel of margin so edge pixels have all eight neighbors
+    let n = size + 2;
+    let grid = sample_grid(dem, z, x, y, size, -0.5, n);
+    if grid.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let zenith = (90.0 - options.altitude_deg).to_radians();
+    let azimuth = (360.0 - options.azimuth_deg + 90.0).to_radians();
+    let mut pixels = Vec::with_capacity(size * size * 2);
+    for row in 0..size {
+        let lat = tile_lat(f64::from(y) + (row as f64 + 0.5) / size as f64, z);
+        let pixel_m = 2.0 * PI * EARTH_RADIUS_M * lat.to_radians().cos()
+            / (f64::from(1u32 << z) * size as f64);
+        for col in 0..size {
+            let at = |dr: usize, dc: usize| grid[(row + dr) * n + col + dc];
+            let Some(center) = at(1, 1) else {
+                pixels.extend_from_slice(&[0, 0]);
+                continue;
+            };
+            let v = |dr, dc| at(dr, dc).unwrap_or(center);
+            let (a, b, c) = (v(0, 0), v(0, 1), v(0, 2));
+            let (d, f) = (v(1, 0), v(1, 2));
+            let (g, h, i) = (v(2, 0), v(2, 1), v(2, 2));
+            let dzdx = ((c + 2.0 * f + i) - (a + 2.0 * d + g)) / (8.0 * pixel_m);
+            let dzdy = ((g + 2.0 * h + i) - (a + 2.0 * b + c)) / (8.0 * pixel_m);
+            let slope = (options.exaggeration * dzdx.hypot(dzdy)).atan();
+            let aspect = dzdy.atan2(-dzdx);
+            let shade = zenith.cos() * slope.cos()
+                + zenith.sin() * slope.sin() * (azimuth - aspect).cos();
+            pixels.push((shade.clamp(0.0, 1.0) * 255.0).round() as u8);
+            pixels.push(255);
+        }
+    }
+    Some(encode_png_gray_alpha(size, size, &pixels))
+}
+
+/// Encode 8-bit gray+alpha pixels as PNG.
+fn encode_png_gray_alpha(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        out.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits, gray+alpha, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 4, 0, 0, 0]);
+
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks_exact(width * 2) {
+        // Filter type 0: rows are stored as they are
+        let _ = zlib.write_all(&[0]);
+        let _ = zlib.write_all(row);
+    }
+    let idat = zlib.finish().unwrap_or_default();
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(&mut out, b"IDAT", &idat);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+// -- Contours --
+
+/// One contour line in grid coordinates.
+struct ContourLine {
+    ele: f64,
+    points: Vec<(f64, f64)>,
+}
+
+/// Trace contour lines at every multiple of `interval` through an
+/// `n` x `n` elevation grid with marching squares.
+///
+/// Segments are joined through the grid edges they cross, so each
+/// returned line is continuous; closed rings start and end at the
+/// same point.
+fn trace_contours(grid: &[Option<f64>], n: usize, interval: f64) -> Vec<ContourLine> {
+    let (lo, hi) = grid
+        .iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    if lo > hi {
+        return Vec::new();
+    }
+
+    let mut lines = Vec::new();
+    let mut level = (lo / interval).ceil() * interval;
+    while level <= hi {
+        lines.extend(trace_level(grid, n, level).into_iter().map(|points| ContourLine {
+            ele: level,
+            points,
+        }));
+        level += interval;
+    }
+    lines
+}
+
+fn trace_level(grid: &[Option<f64>], n: usize, level: f64) -> Vec<Vec<(f64, f64)>> {
+    // Edge keys: horizontal edge right of vertex (c, r) is even,
+    // vertical edge below it is odd.
+    let h_edge = |c: usize, r: usize| ((r * n + c) * 2) as u64;
+    let v_edge = |c: usize, r: usize| ((r * n + c) * 2 + 1) as u64;
+
+    let mut crossings: HashMap<u64, (f64, f64)> = HashMap::new();
+    let mut segments: Vec<(u64, u64)> = Vec::new();
+    for r in 0..n - 1 {
+        for c in 0..n - 1 {
+            let (Some(tl), Some(tr), Some(bl), Some(br)) = (
+                grid[r * n + c],
+                grid[r * n + c + 1],
+                grid[(r + 1) * n + c],
+                grid[(r + 1) * n + c + 1],
+            ) else {
+                continue;
+            };
+            // Edges in order top, right, bottom, left with their end values
+            let edges = [
+                (h_edge(c, r), tl, tr, (c as f64, r as f64), (1.0, 0.0)),
+                (v_edge(c + 1, r), tr, br, ((c + 1) as f64, r as f64), (0.0, 1.0)),
+                (h_edge(c, r + 1), bl, br, (c as f64, (r + 1) as f64), (1.0, 0.0)),
+                (v_edge(c, r), tl, bl, (c as f64, r as f64), (0.0, 1.0)),
+            ];
+            let mut crossed = [None; 4];
+            for (k, &(key, a, b, origin, dir)) in edges.iter().enumerate() {
+                if (a >= level) != (b >= level) {
+                    let t = (level - a) / (b - a);
+                    crossings.insert(key, (origin.0 + dir.0 * t, origin.1 + dir.1 * t));
+                    crossed[k] = Some(key);
+                }
+            }
+            match crossed {
+                [Some(t), Some(r), Some(b), Some(l)] => {
+                    // Saddle: the cell center decides which corners connect
+                    let center = (tl + tr + bl + br) / 4.0;
+                    if (center >= level) == (tl >= level) {
+                        segments.push((t, r));
+                        segments.push((l, b));
+                    } else {
+                        segments.push((t, l));
+                        segments.push((r, b));
+                    }
+                }
+                _ => {
+                    let mut ends = crossed.iter().flatten();
+                    if let (Some(&a), Some(&b)) = (ends.next(), ends.next()) {
+                        segments.push((a, b));
+                    }
+                }
+            }
+        }
+    }
+
+    chain_segments(&segments)
+        .into_iter()
+        .map(|keys| keys.iter().map(|k| crossings[k]).collect::<Vec<_>>())
+        // A level equal to a lone peak's height collapses onto the vertex
+        .filter(|points| points.iter().any(|p| *p != points[0]))
+        .collect()
+}
+
+/// Join segments that share an edge into polylines of edge keys.
+fn chain_segments(segments: &[(u64, u64)]) -> Vec<Vec<u64>> {
+    let mut at_edge: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in segments.iter().enumerate() {
+        at_edge.entry(a).or_default().push(i);
+        at_edge.entry(b).or_default().push(i);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = Vec::new();
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let (a, b) = segments[start];
+        let mut forward = vec![a, b];
+        extend_chain(&mut forward, segments, &at_edge, &mut used);
+        let mut backward = vec![a];
+        extend_chain(&mut backward, segments, &at_edge, &mut used);
+        backward.reverse();
+        backward.pop();
+        backward.extend(forward);
+        lines.push(backward);
+    }
+    lines
+}
+
+fn extend_chain(
+    line: &mut Vec<u64>,
+    segments: &[(u64, u64)],
+    at_edge: &HashMap<u64, Vec<usize>>,
+    used: &mut [bool],
+) {
+    while let Some(&last) = line.last() {
+        let next = at_edge[&last].iter().copied().find(|&i| !used[i]);
+        let Some(i) = next else {
+            break;
+        };
+        used[i] = true;
+        let (a, b) = segments[i];
+        line.push(if a == last { b } else { a });
+    }
+}
+
+/// Render the contour vector tile for one tile (uncompressed MVT).
+///
+/// Returns `None` when no contour line crosses the tile.
+pub fn contour_tile(
+    dem: &mut Dem,
+    z: u8,
+    x: u32,
+    y: u32,
+    options: &TerrainOptions,
+) -> Option<Vec<u8>> {
+    let n = CONTOUR_CELLS + 1;
+    let grid = sample_grid(dem, z, x, y, CONTOUR_CELLS, 0.0, n);
+    let lines = trace_contours(&grid, n, options.contour_interval_m);
+    if lines.is_empty() {
+        return None;
+    }
+    let scale = f64::from(MVT_EXTENT) / CONTOUR_CELLS as f64;
+    Some(encode_contour_mvt(&lines, scale, options))
+}
+
+// -- Vector tile encoding --
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    put_varint(buf, u64::from(field << 3 | wire_type));
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_key(buf, field, 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn put_packed(buf: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::with_capacity(values.len() * 2);
+    for &v in values {
+        put_varint(&mut packed, u64::from(v));
+    }
+    put_bytes(buf, field, &packed);
+}
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    id | (count as u32) << 3
+}
+
+/// Encode contour lines as a vector tile with one multi-line feature
+/// per elevation.
+fn encode_contour_mvt(lines: &[ContourLine], scale: f64, options: &TerrainOptions) -> Vec<u8> {
+    let mut by_level: Vec<(i64, Vec<&ContourLine>)> = Vec::new();
+    for line in lines {
+        let ele = line.ele.round() as i64;
+        match by_level.iter_mut().find(|(e, _)| *e == ele) {
+            Some((_, group)) => group.push(line),
+            None => by_level.push((ele, vec![line])),
+        }
+    }
+
+    let mut features = Vec::new();
+    // Value table: false and true for "index", then one per elevation
+    let mut values = vec![mvt_bool(false), mvt_bool(true)];
+    for (ele, group) in &by_level {
+        let geometry = line_geometry(group, scale);
+        if geometry.is_empty() {
+            continue;
+        }
+        let mut value = Vec::new();
+        put_key(&mut value, 4, 0);
+        put_varint(&mut value, *ele as u64);
+        values.push(value);
+
+        let step = (*ele as f64 / options.contour_interval_m).round() as i64;
+        let is_index = options.index_every > 0
+            && step.rem_euclid(i64::from(options.index_every)) == 0;
+
+        let mut feature = Vec::new();
+        put_key(&mut feature, 1, 0);
+        put_varint(&mut feature, features.len() as u64 + 1);
+        let ele_value = (values.len() - 1) as u32;
+        put_packed(&mut feature, 2, &[0, ele_value, 1, u32::from(is_index)]);
+        put_key(&mut feature, 3, 0);
+        put_varint(&mut feature, 2);
+        put_packed(&mut feature, 4, &geometry);
+        features.push(feature);
+    }
+
+    let mut layer = Vec::new();
+    put_key(&mut layer, 15, 0);
+    put_varint(&mut layer, 2);
+    put_bytes(&mut layer, 1, CONTOUR_LAYER.as_bytes());
+    for feature in &features {
+        put_bytes(&mut layer, 2, feature);
+    }
+    put_bytes(&mut layer, 3, b"ele");
+    put_bytes(&mut layer, 3, b"index");
+    for value in &values {
+        put_bytes(&mut layer, 4, value);
+    }
+    put_key(&mut layer, 5, 0);
+    put_varint(&mut layer, u64::from(MVT_EXTENT));
+
+    let mut tile = Vec::new();
+    put_bytes(&mut tile, 3, &layer);
+    tile
+}
+
+fn mvt_bool(flag: bool) -> Vec<u8> {
+    let mut value = Vec::new();
+    put_key(&mut value, 7, 0);
+    put_varint(&mut value, u64::from(flag));
+    value
+}
+
+/// MoveTo/LineTo commands for a set of lines in tile units.
+fn line_geometry(lines: &[&ContourLine], scale: f64) -> Vec<u32> {
+    let mut geometry = Vec::new();
+    let (mut cx, mut cy) = (0i32, 0i32);
+    for line in lines {
+        let mut coords: Vec<(i32, i32)> = line
+            .points
+            .iter()
+            .map(|&(x, y)| ((x * scale).round() as i32, (y * scale).round() as i32))
+            .collect();
+        coords.dedup();
+        if coords.len() < 2 {
+            continue;
+        }
+        for (k, &(x, y)) in coords.iter().enumerate() {
+            match k {
+                0 => geometry.push(command(1, 1)),
+                1 => geometry.push(command(2, coords.len() - 1)),
+                _ => {}
+            }
+            geometry.push(zigzag(x - cx));
+            geometry.push(zigzag(y - cy));
+            (cx, cy) = (x, y);
+        }
+    }
+    geometry
+}
+
+// -- MBTiles --
+
+/// Writer for an MBTiles 1.3 file. Tiles are inserted in a single
+/// transaction that [`MbtilesWriter::finish`] commits.
+pub struct MbtilesWriter {
+    conn: Connection,
+}
+
+impl MbtilesWriter {
+    /// Create (or replace) the file at `path` and write its metadata.
+    ///
+    /// `format` is `png` or `pbf`; `extra` adds metadata rows such as
+    /// the `json` layer description vector tiles need.
+    pub fn create(
+        path: &Path,
+        name: &str,
+        format: &str,
+        bounds: &Bounds,
+        options: &TerrainOptions,
+        extra: &[(&str, String)],
+    ) -> Result<Self, String> {
+        if path.exists() {
+            std::fs::remove_file(path)
+                .map_err(|e| format!("Cannot replace {}: {e}", path.display()))?;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Cannot create {}: {e}", parent.display()))?;
+        }
+        let conn = Connection::open(path).map_err(|e| format!("MBTiles open error: {e}"))?;
+        conn.execute_batch(
+            "CREATE TABLE metadata (name TEXT, value TEXT);
+             CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER,
+                                 tile_row INTEGER, tile_data BLOB);
+             CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+             BEGIN;",
+        )
+        .map_err(|e| format!("MBTiles schema error: {e}"))?;
+
+        let mut metadata = vec![
+            ("name", name.to_string()),
+            ("format", format.to_string()),
+            ("type", "overlay".to_string()),
+            (
+                "bounds",
+                format!(
+                    "{},{},{},{}",
+                    bounds.min_lon, bounds.min_lat, bounds.max_lon, bounds.max_lat
+                ),
+            ),
+            ("minzoom", options.min_zoom.to_string()),
+            ("maxzoom", options.max_zoom.to_string()),
+        ];
+        metadata.extend(extra.iter().cloned());
+        for (key, value) in metadata {
+            conn.execute("INSERT INTO metadata (name, value) VALUES (?1, ?2)", params![key, value])
+                .map_err(|e| format!("MBTiles metadata error: {e}"))?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Store a tile given in XYZ numbering.
+    pub fn put_tile(&self, z: u8, x: u32, y: u32, data: &[u8]) -> Result<(), String> {
+        // MBTiles rows count from the south (TMS)
+        let row = (1u32 << z) - 1 - y;
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![z, x, row, data],
+            )
+            .map_err(|e| format!("MBTiles write error: {e}"))?;
+        Ok(())
+    }
+
+    /// Commit all tiles.
+    pub fn finish(self) -> Result<(), String> {
+        self.conn
+            .execute_batch("COMMIT;")
+            .map_err(|e| format!("MBTiles commit error: {e}"))
+    }
+}
+
+/// Render hillshade tiles for `bounds` into an MBTiles file.
+/// Returns the number of tiles written.
+pub fn write_hillshade(
+    dem: &mut Dem,
+    bounds: &Bounds,
+    options: &TerrainOptions,
+    path: &Path,
+) -> Result<usize, String> {
+    options.check(bounds)?;
+    let writer = MbtilesWriter::create(path, "Hillshade", "png", bounds, options, &[])?;
+    let mut count = 0;
+    for z in options.min_zoom..=options.max_zoom {
+        for (x, y) in tiles_in(bounds, z) {
+            if let Some(png) = hillshade_tile(dem, z, x, y, options) {
+                writer.put_tile(z, x, y, &png)?;
+                count += 1;
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(count)
+}
+
+/// Render gzip-compressed contour vector tiles for `bounds` into an
+/// MBTiles file. Returns the number of tiles written.
+pub fn write_contours(
+    dem: &mut Dem,
+    bounds: &Bounds,
+    options: &TerrainOptions,
+    path: &Path,
+) -> Result<usize, String> {
+    options.check(bounds)?;
+    let min_zoom = options.contour_min_zoom.clamp(options.min_zoom, options.max_zoom);
+    let layers = serde_json::json!({
+        "vector_layers": [{
+            "id": CONTOUR_LAYER,
+            "fields": { "ele": "Number", "index": "Boolean" },
+            "minzoom": min_zoom,
+            "maxzoom": options.max_zoom,
+        }]
+    });
+    let zoom_options = TerrainOptions {
+        min_zoom,
+        ..*options
+    };
+    let writer = MbtilesWriter::create(
+        path,
+        "Contours",
+        "pbf",
+        bounds,
+        &zoom_options,
+        &[("json", layers.to_string())],
+    )?;
+    let mut count = 0;
+    for z in min_zoom..=options.max_zoom {
+        for (x, y) in tiles_in(bounds, z) {
+            let Some(mvt) = contour_tile(dem, z, x, y, options) else {
+                continue;
+            };
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            gz.write_all(&mvt).map_err(|e| format!("Tile compression error: {e}"))?;
+            let data = gz.finish().map_err(|e| format!("Tile compression error: {e}"))?;
+            writer.put_tile(z, x, y, &data)?;
+            count += 1;
+        }
+    }
+    writer.finish()?;
+    Ok(count)
+}
+
+/// Paths and tile counts of the generated terrain files.
+#[derive(Debug, Serialize)]
+pub struct TerrainResult {
+    pub hillshade_path: String,
+    pub contour_path: String,
+    pub hillshade_tiles: usize,
+    pub contour_tiles: usize,
+}
+
+/// Generate `hillshade.mbtiles` and `contours.mbtiles` in `out_dir`
+/// from the DEM tiles in `dem_dir`.
+pub fn build_terrain_json(
+    dem_dir: &str,
+    out_dir: &str,
+    bounds: Bounds,
+    min_zoom: u8,
+    max_zoom: u8,
+    contour_interval_m: f64,
+) -> Result<String, String> {
+    let options = TerrainOptions {
+        min_zoom,
+        max_zoom,
+        contour_interval_m,
+        ..TerrainOptions::default()
+    };
+    options.check(&bounds)?;
+    let mut dem = Dem::new(dem_dir);
+    let out = Path::new(out_dir);
+    let hillshade_path = out.join("hillshade.mbtiles");
+    let contour_path = out.join("contours.mbtiles");
+    let hillshade_tiles = write_hillshade(&mut dem, &bounds, &options, &hillshade_path)?;
+    let contour_tiles = write_contours(&mut dem, &bounds, &options, &contour_path)?;
+    let result = TerrainResult {
+        hillshade_path: hillshade_path.to_string_lossy().into_owned(),
+        contour_path: contour_path.to_string_lossy().into_owned(),
+        hillshade_tiles,
+        contour_tiles,
+    };
+    serde_json::to_string(&result).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dem::tile_name;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    /// Write a 3x3 HGT tile for N47E011 to a fresh directory.
+    fn dem_dir(tag: &str, heights: [i16; 9]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("ndkarte-terrain-{tag}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bytes: Vec<u8> = heights.iter().flat_map(|h| h.to_be_bytes()).collect();
+        std::fs::write(dir.join(format!("{}.hgt", tile_name(47, 11))), bytes).unwrap();
+        dir
+    }
+
+    fn small() -> TerrainOptions {
+        TerrainOptions { tile_size: 16, ..TerrainOptions::default() }
+    }
+
+    #[test]
+    fn tile_math_round_trips() {
+        assert!((tile_lat(0.0, 0) - MAX_MERCATOR_LAT).abs() < 1e-6);
+        assert_eq!(tile_lon(1.0, 1), 0.0);
+        let (x, y) = tile_at(47.5, 11.5, 10);
+        assert_eq!((x, y), (544, 358));
+        assert!(tile_lon(f64::from(x), 10) <= 11.5 && tile_lon(f64::from(x + 1), 10) > 11.5);
+        assert!(tile_lat(f64::from(y), 10) >= 47.5 && tile_lat(f64::from(y + 1), 10) < 47.5);
+    }
+
+    #[test]
+    fn options_reject_fine_contours_and_large_areas() {
+        let bounds = Bounds { min_lat: 47.4, min_lon: 11.4, max_lat: 47.6, max_lon: 11.6 };
+        let options = TerrainOptions::default();
+        assert!(options.check(&bounds).is_ok());
+        assert_eq!(tile_count(&bounds, 8, 8), 1);
+
+        let fine = TerrainOptions { contour_interval_m: 0.5, ..options };
+        assert!(fine.check(&bounds).is_err());
+
+        let alps = Bounds { min_lat: 44.0, min_lon: 5.0, max_lat: 48.0, max_lon: 16.0 };
+        let deep = TerrainOptions { max_zoom: 16, ..options };
+        assert!(tile_count(&alps, 8, 16) > MAX_TILES);
+        let err = deep.check(&alps).unwrap_err();
+        assert!(err.contains("tiles"), "{err}");
+    }
+
+    #[test]
+    fn png_has_valid_structure() {
+        let png = encode_png_gray_alpha(3, 2, &[7; 12]);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 3);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 2);
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let mut raw = Vec::new();
+        ZlibDecoder::new(&png[41..41 + idat_len]).read_to_end(&mut raw).unwrap();
+        assert_eq!(raw, [0, 7, 7, 7, 7, 7, 7, 0, 7, 7, 7, 7, 7, 7]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn hillshade_lights_north_west_slopes() {
+        // Rising towards the south-east, so the slope faces the default
+        // north-west sun
+        let dir = dem_dir("shade", [0, 500, 1000, 500, 1000, 1500, 1000, 1500, 2000]);
+        let mut dem = Dem::new(&dir);
+        let (x, y) = tile_at(47.5, 11.5, 9);
+        let lit = hillshade_tile(&mut dem, 9, x, y, &small()).unwrap();
+        let away = TerrainOptions { azimuth_deg: 135.0, ..small() };
+        let dark = hillshade_tile(&mut dem, 9, x, y, &away).unwrap();
+        let mean = |png: &[u8]| {
+            let mut raw = Vec::new();
+            ZlibDecoder::new(&png[41..png.len() - 16]).read_to_end(&mut raw).unwrap();
+            raw.chunks(33)
+                .flat_map(|row| row[1..].iter().step_by(2))
+                .map(|&v| f64::from(v))
+                .sum::<f64>()
+        };
+        assert!(mean(&lit) > mean(&dark));
+        assert!(hillshade_tile(&mut dem, 9, 0, 0, &small()).is_none());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn contours_trace_ramp_and_ring() {
+        // East-west ramp from 0 to 40 m: vertical lines at 10, 20, 30, 40
+        let n = 5;
+        let ramp: Vec<Option<f64>> = (0..n * n).map(|i| Some((i % n) as f64 * 10.0)).collect();
+        let lines = trace_contours(&ramp, n, 10.0);
+        let mid: Vec<_> = lines.iter().filter(|l| l.ele == 20.0).collect();
+        assert_eq!(mid.len(), 1);
+        assert_eq!(mid[0].points.len(), n);
+        assert!(mid[0].points.iter().all(|&(x, _)| (x - 2.0).abs() < 1e-9));
+
+        // A single peak in the middle gives a closed ring
+        let mut peak = vec![Some(0.0); 9];
+        peak[4] = Some(100.0);
+        let lines = trace_contours(&peak, 3, 50.0);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].points.first(), lines[0].points.last());
+        assert_eq!(lines[0].points.len(), 5);
+
+        // Holes split lines instead of connecting across them
+        let mut holed = ramp.clone();
+        holed[2 * n + 2] = None;
+        assert!(trace_contours(&holed, n, 10.0).iter().filter(|l| l.ele == 20.0).count() >= 1);
+    }
+
+    #[test]
+    fn writes_mbtiles() {
+        let dir = dem_dir("mbtiles", [1000, 900, 800, 900, 800, 700, 800, 700, 600]);
+        let out = dir.join("out");
+        let bounds = Bounds { min_lat: 47.4, min_lon: 11.4, max_lat: 47.6, max_lon: 11.6 };
+        let json = build_terrain_json(
+            dir.to_str().unwrap(),
+            out.to_str().unwrap(),
+            bounds,
+            9,
+            11,
+            50.0,
+        )
+        .unwrap();
+        let result: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(result["hillshade_tiles"].as_u64().unwrap() >= 3);
+        assert!(result["contour_tiles"].as_u64().unwrap() >= 1);
+
+        let conn = Connection::open(out.join("contours.mbtiles")).unwrap();
+        let format: String = conn
+            .query_row("SELECT value FROM metadata WHERE name = 'format'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(format, "pbf");
+        let (x, y) = tile_at(47.5, 11.5, 11);
+        let data: Vec<u8> = conn
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = 11 AND tile_column = ?1
+                 AND tile_row = ?2",
+                params![x, (1u32 << 11) - 1 - y],
+                |r| r.get(0),
+            )
+            .unwrap();
+        let mut mvt = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut mvt).unwrap();
+        // Tile -> layer (field 3), containing the layer name
+        assert_eq!(mvt[0], 3 << 3 | 2);
+        assert!(mvt.windows(7).any(|w| w == CONTOUR_LAYER.as_bytes()));
+
+        let reversed_zooms =
+            build_terrain_json(dir.to_str().unwrap(), out.to_str().unwrap(), bounds, 12, 9, 50.0);
+        assert!(reversed_zooms.is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
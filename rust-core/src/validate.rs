@@ -0,0 +1,454 @@
+//! Coordinate and data validation for imported GPX data.
+//!
+//! Checks parsed tracks, routes, and waypoints for values that make
+//! the distance and simplification math produce garbage: coordinates
+//! out of range or NaN, zero-length segments, implausible jumps between
+//! timestamped points, and elevation spikes. Every check can report
+//! only, or also repair the data in place.
+
+use serde::Serialize;
+use crate::gpx::{in_range, GpxData, Point};
+use crate::nav::haversine;
+
+/// Thresholds used by the validation checks.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationOptions {
+    /// Speed above which a single point between two plausible neighbors
+    /// is treated as a GPS glitch, in m/s.
+    pub max_speed_mps: f64,
+    /// Segments shorter than this are reported as zero-length, in meters.
+    pub min_segment_m: f64,
+    /// Lowest plausible elevation in meters (the Dead Sea shore is ~ -430 m).
+    pub min_ele_m: f64,
+    /// Highest plausible elevation in meters.
+    pub max_ele_m: f64,
+    /// Elevation difference to both neighbors that marks a spike, in meters.
+    pub spike_m: f64,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self {
+            max_speed_mps: 100.0,
+            min_segment_m: 0.01,
+            min_ele_m: -450.0,
+            max_ele_m: 9000.0,
+            spike_m: 100.0,
+        }
+    }
+}
+
+/// Kind of problem found by validation.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Latitude or longitude is NaN, infinite, or out of range.
+    /// Fixed by removing the point.
+    InvalidCoordinate,
+    /// Elevation is NaN or outside the plausible range.
+    /// Fixed by clearing the elevation.
+    ImplausibleElevation,
+    /// The point repeats the previous one, forming a zero-length segment.
+    /// Fixed by removing the point.
+    DuplicatePoint,
+    /// The point implies an impossible speed from and back to its neighbors.
+    /// Fixed by removing the point.
+    Teleport,
+    /// Elevation jumps away from both neighbors and back.
+    /// Fixed by replacing it with the neighbors' mean.
+    ElevationSpike,
+}
+
+/// Which collection of a `GpxData` an issue belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Collection {
+    Track,
+    Route,
+    Waypoint,
+}
+
+/// A single validation finding.
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub collection: Collection,
+    /// Index of the track, route, or waypoint within its collection.
+    pub item_index: usize,
+    /// Index of the offending point in the original point list.
+    pub point_index: usize,
+    pub message: String,
+}
+
+/// Result of validating a `GpxData`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+    /// The repaired data, present only when fixing was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<GpxData>,
+}
+
+/// Check all tracks, routes, and waypoints and report issues.
+pub fn validate(data: &GpxData, options: &ValidationOptions) -> Vec<Issue> {
+    let mut copy = data.clone();
+    check_data(&mut copy, options, false)
+}
+
+/// Check all tracks, routes, and waypoints and repair them in place.
+///
+/// Returns the issues found, with point indices referring to the
+/// point lists as they were before repair.
+pub fn repair(data: &mut GpxData, options: &ValidationOptions) -> Vec<Issue> {
+    check_data(data, options, true)
+}
+
+/// Validate GPX data given as JSON and return a JSON `ValidationReport`.
+pub fn validate_json(gpx_json: &str, fix: bool) -> Result<String, String> {
+    let mut data: GpxData = serde_json::from_str(gpx_json)
+        .map_err(|e| format!("GPX JSON parse failed: {e}"))?;
+    let options = ValidationOptions::default();
+
+    let report = if fix {
+        let issues = repair(&mut data, &options);
+        ValidationReport { issues, data: Some(data) }
+    } else {
+        ValidationReport { issues: validate(&data, &options), data: None }
+    };
+
+    serde_json::to_string(&report).map_err(|e| format!("JSON serialize error: {e}"))
+}
+
+fn check_data(data: &mut GpxData, options: &ValidationOptions, fix: bool) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    for (i, track) in data.tracks.iter_mut().enumerate() {
+        let found = check_points(&mut track.points, options, fix);
+        issues.extend(found.into_iter().map(|f| f.into_issue(Collection::Track, i)));
+    }
+    for (i, route) in data.routes.iter_mut().enumerate() {
+        let found = check_points(&mut route.points, options, fix);
+        issues.extend(found.into_iter().map(|f| f.into_issue(Collection::Route, i)));
+    }
+
+    let mut keep = Vec::with_capacity(data.waypoints.len());
+    for (i, wp) in data.waypoints.iter_mut().enumerate() {
+        let mut valid = true;
+        if let Some(f) = check_coordinate(&wp.point, 0) {
+            issues.push(f.into_issue(Collection::Waypoint, i));
+            valid = false;
+        } else if let Some(f) = check_elevation(&wp.point, 0, options) {
+            issues.push(f.into_issue(Collection::Waypoint, i));
+            if fix {
+                wp.point.ele = None;
+            }
+        }
+        keep.push(valid);
+    }
+    if fix {
+        let mut keep = keep.into_iter();
+        data.waypoints.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    issues
+}
+
+/// An issue before it is attributed to a track, route, or waypoint.
+struct Finding {
+    kind: IssueKind,
+    point_index: usize,
+    message: String,
+}
+
+impl Finding {
+    fn into_issue(self, collection: Collection, item_index: usize) -> Issue {
+        Issue {
+            kind: self.kind,
+            collection,
+            item_index,
+            point_index: self.point_index,
+            message: self.message,
+        }
+    }
+}
+
+/// Run all point-list checks, optionally repairing the list.
+///
+/// Checks run in order of severity; each later check only considers
+/// points that survived the earlier ones, so one bad fix (say, a NaN)
+/// is not also reported as a duplicate or a teleport.
+fn check_points(points: &mut Vec<Point>, options: &ValidationOptions, fix: bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    // Indices into `points` of the points still considered valid.
+    let mut kept: Vec<usize> = Vec::with_capacity(points.len());
+    for (i, p) in points.iter().enumerate() {
+        match check_coordinate(p, i) {
+            Some(f) => findings.push(f),
+            None => kept.push(i),
+        }
+    }
+
+    kept = drop_duplicates(points, &kept, options, &mut findings);
+    kept = drop_teleports(points, &kept, options, &mut findings);
+
+    for &i in &kept {
+        if let Some(f) = check_elevation(&points[i], i, options) {
+            findings.push(f);
+            if fix {
+                points[i].ele = None;
+            }
+        }
+    }
+
+    for w in kept.windows(3) {
+        let (Some(a), Some(b), Some(c)) = (points[w[0]].ele, points[w[1]].ele, points[w[2]].ele)
+        else {
+            continue;
+        };
+        let up = b - a > options.spike_m && b - c > options.spike_m;
+        let down = a - b > options.spike_m && c - b > options.spike_m;
+        if (up || down) && (a - c).abs() < options.spike_m {
+            findings.push(Finding {
+                kind: IssueKind::ElevationSpike,
+                point_index: w[1],
+                message: format!("elevation {b:.0} m between {a:.0} m and {c:.0} m"),
+            });
+            if fix {
+                points[w[1]].ele = Some((a + c) / 2.0);
+            }
+        }
+    }
+
+    if fix {
+        let mut keep = vec![false; points.len()];
+        for &i in &kept {
+            keep[i] = true;
+        }
+        let mut keep = keep.into_iter();
+        points.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    findings.sort_by_key(|f| f.point_index);
+    findings
+}
+
+fn check_coordinate(p: &Point, index: usize) -> Option<Finding> {
+    (!in_range(p.lat, p.lon)).then(|| Finding {
+        kind: IssueKind::InvalidCoordinate,
+        point_index: index,
+        message: format!("coordinate lat={} lon={} out of range", p.lat, p.lon),
+    })
+}
+
+fn check_elevation(p: &Point, index: usize, options: &ValidationOptions) -> Option<Finding> {
+    let ele = p.ele?;
+    let valid = (options.min_ele_m..=options.max_ele_m).contains(&ele);
+    (!valid).then(|| Finding {
+        kind: IssueKind::ImplausibleElevation,
+        point_index: index,
+        message: format!("elevation {ele} m is implausible"),
+    })
+}
+
+/// Remove points that repeat their predecessor, returning the survivors.
+fn drop_duplicates(
+    points: &[Point],
+    kept: &[usize],
+    options: &ValidationOptions,
+    findings: &mut Vec<Finding>,
+) -> Vec<usize> {
+    let mut out: Vec<usize> = Vec::with_capacity(kept.len());
+    for &i in kept {
+        if let Some(&prev) = out.last() {
+            let dist = haversine(&points[prev], &points[i]);
+            if dist < options.min_segment_m {
+                findings.push(Finding {
+                    kind: IssueKind::DuplicatePoint,
+                    point_index: i,
+                    message: format!("zero-length segment after point {prev}"),
+                });
+                continue;
+            }
+        }
+        out.push(i);
+    }
+    out
+}
+
+/// Remove single-point GPS glitches, returning the survivors.
+///
+/// A point is a teleport when reaching it from the previous point and
+/// leaving it toward the next both require more than `max_speed_mps`,
+/// while going directly from previous to next does not. A sustained
+/// relocation (ferry, train) is therefore left alone.
+fn drop_teleports(
+    points: &[Point],
+    kept: &[usize],
+    options: &ValidationOptions,
+    findings: &mut Vec<Finding>,
+) -> Vec<usize> {
+    let speed = |a: usize, b: usize| -> Option<f64> {
+        let dt = points[b].time? - points[a].time?;
+        (dt > 0.0).then(|| haversine(&points[a], &points[b]) / dt)
+    };
+
+    let mut out: Vec<usize> = Vec::with_capacity(kept.len());
+    for (k, &i) in kept.iter().enumerate() {
+        if let (Some(&prev), Some(&next)) = (out.last(), kept.get(k + 1)) {
+            let limit = options.max_speed_mps;
+            if let (Some(v_in), Some(v_out)) = (speed(prev, i), speed(i, next)) {
+                let direct_ok = speed(prev, next).is_some_and(|v| v <= limit);
+                if v_in > limit && v_out > limit && direct_ok {
+                    findings.push(Finding {
+                        kind: IssueKind::Teleport,
+                        point_index: i,
+                        message: format!("implausible speed {:.0} km/h", v_in * 3.6),
+                    });
+                    continue;
+                }
+            }
+        }
+        out.push(i);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpx::{Track, Waypoint};
+
+    fn pt(lat: f64, lon: f64, ele: Option<f64>, time: Option<f64>) -> Point {
+        Point { lat, lon, ele, time, speed: None, course: None }
+    }
+
+    fn track_data(points: Vec<Point>) -> GpxData {
+        GpxData {
+            tracks: vec![Track { name: None, points }],
+            ..Default::default()
+        }
+    }
+
+    fn kinds(issues: &[Issue]) -> Vec<IssueKind> {
+        issues.iter().map(|i| i.kind).collect()
+    }
+
+    #[test]
+    fn clean_track_has_no_issues() {
+        let data = track_data(vec![
+            pt(48.0, 16.0, Some(200.0), Some(0.0)),
+            pt(48.001, 16.0, Some(201.0), Some(10.0)),
+            pt(48.002, 16.0, Some(202.0), Some(20.0)),
+        ]);
+        assert!(validate(&data, &ValidationOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_coordinates_and_nan() {
+        let data = track_data(vec![
+            pt(48.0, 16.0, None, None),
+            pt(f64::NAN, 16.0, None, None),
+            pt(48.0, 200.0, None, None),
+            pt(48.001, 16.0, None, None),
+        ]);
+
+        let issues = validate(&data, &ValidationOptions::default());
+        assert_eq!(kinds(&issues), vec![IssueKind::InvalidCoordinate; 2]);
+        assert_eq!(issues[0].point_index, 1);
+        assert_eq!(issues[1].point_index, 2);
+    }
+
+    #[test]
+    fn repair_removes_duplicates() {
+        let mut data = track_data(vec![
+            pt(48.0, 16.0, None, None),
+            pt(48.0, 16.0, None, None),
+            pt(48.001, 16.0, None, None),
+        ]);
+
+        let issues = repair(&mut data, &ValidationOptions::default());
+        assert_eq!(kinds(&issues), vec![IssueKind::DuplicatePoint]);
+        assert_eq!(data.tracks[0].points.len(), 2);
+    }
+
+    #[test]
+    fn repair_removes_single_point_teleport() {
+        // 1 km/10 s apart, with a glitch 50 km away in between
+        let mut data = track_data(vec![
+            pt(48.000, 16.0, None, Some(0.0)),
+            pt(48.005, 16.0, None, Some(10.0)),
+            pt(48.500, 16.0, None, Some(20.0)),
+            pt(48.010, 16.0, None, Some(30.0)),
+        ]);
+
+        let issues = repair(&mut data, &ValidationOptions::default());
+        assert_eq!(kinds(&issues), vec![IssueKind::Teleport]);
+        assert_eq!(issues[0].point_index, 2);
+        assert_eq!(data.tracks[0].points.len(), 3);
+    }
+
+    #[test]
+    fn sustained_jump_is_not_a_teleport() {
+        // The rider stays at the new location afterwards (ferry, train)
+        let data = track_data(vec![
+            pt(48.000, 16.0, None, Some(0.0)),
+            pt(48.500, 16.0, None, Some(10.0)),
+            pt(48.501, 16.0, None, Some(20.0)),
+        ]);
+        assert!(validate(&data, &ValidationOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn repair_smooths_elevation_spike() {
+        let mut data = track_data(vec![
+            pt(48.000, 16.0, Some(500.0), None),
+            pt(48.001, 16.0, Some(1500.0), None),
+            pt(48.002, 16.0, Some(510.0), None),
+        ]);
+
+        let issues = repair(&mut data, &ValidationOptions::default());
+        assert_eq!(kinds(&issues), vec![IssueKind::ElevationSpike]);
+        assert_eq!(data.tracks[0].points[1].ele, Some(505.0));
+    }
+
+    #[test]
+    fn repair_clears_implausible_elevation() {
+        let mut data = track_data(vec![
+            pt(48.000, 16.0, Some(-9999.0), None),
+            pt(48.001, 16.0, Some(f64::NAN), None),
+        ]);
+
+        let issues = repair(&mut data, &ValidationOptions::default());
+        assert_eq!(kinds(&issues), vec![IssueKind::ImplausibleElevation; 2]);
+        assert!(data.tracks[0].points.iter().all(|p| p.ele.is_none()));
+    }
+
+    #[test]
+    fn repair_drops_invalid_waypoints() {
+        let mut data = GpxData {
+            waypoints: vec![
+                Waypoint { name: None, point: pt(91.0, 0.0, None, None), icon: None },
+                Waypoint { name: None, point: pt(48.0, 16.0, None, None), icon: None },
+            ],
+            ..Default::default()
+        };
+
+        let issues = repair(&mut data, &ValidationOptions::default());
+        assert_eq!(issues[0].collection, Collection::Waypoint);
+        assert_eq!(data.waypoints.len(), 1);
+    }
+
+    #[test]
+    fn validate_json_includes_data_only_when_fixing() {
+        let json = serde_json::to_string(&track_data(vec![pt(48.0, 16.0, None, None)])).unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_str(&validate_json(&json, false).unwrap()).unwrap();
+        assert!(report.get("data").is_none());
+
+        let report: serde_json::Value =
+            serde_json::from_str(&validate_json(&json, true).unwrap()).unwrap();
+        assert!(report["data"]["tracks"].is_array());
+    }
+}